@@ -0,0 +1,151 @@
+//! Parse time and peak memory of primary.xml parsing on a synthetic repo
+//! about the size of Fedora Everything.
+//!
+//! Run with `cargo bench --bench primary_parse`; set PRIMARY_BENCH_PACKAGES
+//! to change the number of generated packages.
+
+// Only the parser is exercised; the rest of these modules is unused here
+#![allow(dead_code)]
+
+#[path = "../src/compression.rs"]
+mod compression;
+#[path = "../src/package.rs"]
+mod package;
+#[path = "../src/primary.rs"]
+mod primary;
+#[path = "../src/zchunk.rs"]
+mod zchunk;
+
+use flate2::write::GzEncoder;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+fn generate(path: &Path, packages: usize) -> std::io::Result<()> {
+    let file = BufWriter::new(fs::File::create(path)?);
+    let mut out = GzEncoder::new(file, flate2::Compression::default());
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="{}">"#,
+        packages
+    )?;
+    for i in 0..packages {
+        let arch = ["x86_64", "noarch", "i686"][i % 3];
+        writeln!(out, r#"<package type="rpm">"#)?;
+        writeln!(out, "  <name>package-{}</name>", i)?;
+        writeln!(out, "  <arch>{}</arch>", arch)?;
+        writeln!(out, r#"  <version epoch="0" ver="1.{}.0" rel="1.fc39"/>"#, i % 17)?;
+        writeln!(out, r#"  <checksum type="sha256" pkgid="YES">{:064x}</checksum>"#, i)?;
+        writeln!(out, "  <summary>Synthetic package number {}</summary>", i)?;
+        writeln!(
+            out,
+            "  <description>This is synthetic package {} used to benchmark metadata parsing. \
+             It carries a description of realistic length so that text handling is measured \
+             alongside the structural parts of the document.</description>",
+            i
+        )?;
+        writeln!(out, "  <url>https://example.org/package-{}</url>", i)?;
+        writeln!(out, r#"  <size package="{}" installed="{}" archive="{}"/>"#, 50_000 + i, 200_000 + i, 201_000 + i)?;
+        writeln!(out, r#"  <location href="Packages/p/package-{}-1.{}.0-1.fc39.{}.rpm"/>"#, i, i % 17, arch)?;
+        writeln!(out, "  <format>")?;
+        writeln!(out, "    <rpm:license>{}</rpm:license>", ["MIT", "GPL-2.0-or-later", "LGPL-2.1-or-later", "Apache-2.0"][i % 4])?;
+        writeln!(out, "    <rpm:provides>")?;
+        writeln!(out, r#"      <rpm:entry name="package-{}" flags="EQ" epoch="0" ver="1.{}.0" rel="1.fc39"/>"#, i, i % 17)?;
+        writeln!(out, r#"      <rpm:entry name="libpackage-{}.so.1()(64bit)"/>"#, i)?;
+        writeln!(out, "    </rpm:provides>")?;
+        writeln!(out, "    <rpm:requires>")?;
+        for dep in 0..12 {
+            writeln!(out, r#"      <rpm:entry name="libshared-{}.so.6()(64bit)"/>"#, (i * 7 + dep * 131) % 2_000)?;
+        }
+        writeln!(out, r#"      <rpm:entry name="glibc" flags="GE" epoch="0" ver="2.38"/>"#)?;
+        writeln!(out, "    </rpm:requires>")?;
+        for file in 0..4 {
+            writeln!(out, "    <file>/usr/bin/package-{}-{}</file>", i, file)?;
+        }
+        writeln!(out, "  </format>")?;
+        writeln!(out, "</package>")?;
+    }
+    writeln!(out, "</metadata>")?;
+    out.finish()?.flush()
+}
+
+/// Peak resident set size of this process in KiB
+fn peak_rss_kib() -> u64 {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("VmHWM:"))
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|kib| kib.parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+fn parse_streaming(path: &Path) -> anyhow::Result<HashMap<String, package::Package>> {
+    let mut packages = HashMap::new();
+    let mut interner = primary::Interner::default();
+    let source = BufReader::new(compression::open(path)?);
+    primary::parse_primary(source, &mut interner, |pkg| {
+        packages.insert(pkg.name.name.clone(), pkg);
+    })?;
+    Ok(packages)
+}
+
+/// The previous approach: decompress everything into memory, then parse
+fn parse_in_memory(path: &Path) -> anyhow::Result<HashMap<String, package::Package>> {
+    let mut content = String::new();
+    compression::open(path)?.read_to_string(&mut content)?;
+
+    let mut packages = HashMap::new();
+    let mut interner = primary::Interner::default();
+    primary::parse_primary(content.as_bytes(), &mut interner, |pkg| {
+        packages.insert(pkg.name.name.clone(), pkg);
+    })?;
+    Ok(packages)
+}
+
+fn main() -> anyhow::Result<()> {
+    let count: usize = std::env::var("PRIMARY_BENCH_PACKAGES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(70_000);
+
+    let path = std::env::temp_dir().join(format!("rust-dnf-bench-primary-{}.xml.gz", std::process::id()));
+    generate(&path, count)?;
+    println!(
+        "primary_parse: {} packages, {} KiB compressed",
+        count,
+        fs::metadata(&path)?.len() / 1024
+    );
+
+    // Peak RSS only grows, so the streaming parser is measured first
+    let baseline = peak_rss_kib();
+    let start = Instant::now();
+    let packages = parse_streaming(&path)?;
+    println!(
+        "  streaming: {:>8.1} ms, peak RSS +{} KiB ({} packages)",
+        start.elapsed().as_secs_f64() * 1000.0,
+        peak_rss_kib().saturating_sub(baseline),
+        packages.len()
+    );
+    drop(packages);
+
+    let baseline = peak_rss_kib();
+    let start = Instant::now();
+    let packages = parse_in_memory(&path)?;
+    println!(
+        "  in-memory: {:>8.1} ms, peak RSS +{} KiB over streaming peak ({} packages)",
+        start.elapsed().as_secs_f64() * 1000.0,
+        peak_rss_kib().saturating_sub(baseline),
+        packages.len()
+    );
+
+    fs::remove_file(&path)?;
+    Ok(())
+}
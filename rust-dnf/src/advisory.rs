@@ -0,0 +1,345 @@
+use crate::package::{Package, Version};
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AdvisoryKind {
+    Security,
+    Bugfix,
+    Enhancement,
+    NewPackage,
+    Unknown,
+}
+
+impl AdvisoryKind {
+    fn parse(s: &str) -> Self {
+        match s {
+            "security" => Self::Security,
+            "bugfix" => Self::Bugfix,
+            "enhancement" => Self::Enhancement,
+            "newpackage" => Self::NewPackage,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvisoryReference {
+    pub kind: String, // "cve", "bugzilla", ...
+    pub id: String,
+    pub href: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvisoryPackage {
+    pub name: String,
+    pub arch: String,
+    pub version: Version,
+    pub filename: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    pub kind: AdvisoryKind,
+    pub severity: Option<String>,
+    pub title: String,
+    pub issued: String,
+    pub references: Vec<AdvisoryReference>,
+    pub packages: Vec<AdvisoryPackage>,
+}
+
+impl Advisory {
+    fn new() -> Self {
+        Self {
+            id: String::new(),
+            kind: AdvisoryKind::Unknown,
+            severity: None,
+            title: String::new(),
+            issued: String::new(),
+            references: Vec::new(),
+            packages: Vec::new(),
+        }
+    }
+
+    /// Returns the fixed version of `package` if this advisory ships a newer
+    /// build of the same name and architecture.
+    pub fn fixes(&self, package: &Package) -> Option<&AdvisoryPackage> {
+        self.packages.iter().find(|p| {
+            p.name == package.name.name
+                && *p.arch == *package.name.arch
+                && p.version > package.version
+        })
+    }
+}
+
+/// Selection of advisories given on the command line. Criteria are combined
+/// the way dnf does it: an advisory is selected if it matches any of them.
+#[derive(Debug, Default, Clone)]
+pub struct AdvisoryFilter {
+    pub security: bool,
+    pub bugfix: bool,
+    pub advisories: Vec<String>,
+    pub cves: Vec<String>,
+    pub severities: Vec<String>,
+}
+
+impl AdvisoryFilter {
+    pub fn is_empty(&self) -> bool {
+        !self.security
+            && !self.bugfix
+            && self.advisories.is_empty()
+            && self.cves.is_empty()
+            && self.severities.is_empty()
+    }
+
+    pub fn matches(&self, advisory: &Advisory) -> bool {
+        if self.security && advisory.kind == AdvisoryKind::Security {
+            return true;
+        }
+        if self.bugfix && advisory.kind == AdvisoryKind::Bugfix {
+            return true;
+        }
+        if self.advisories.iter().any(|id| id.eq_ignore_ascii_case(&advisory.id)) {
+            return true;
+        }
+        if advisory
+            .references
+            .iter()
+            .filter(|r| r.kind == "cve")
+            .any(|r| self.cves.iter().any(|cve| cve.eq_ignore_ascii_case(&r.id)))
+        {
+            return true;
+        }
+        // Severity only applies to security advisories
+        advisory.kind == AdvisoryKind::Security
+            && advisory.severity.as_ref().is_some_and(|severity| {
+                self.severities.iter().any(|s| s.eq_ignore_ascii_case(severity))
+            })
+    }
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<String> {
+    Ok(match e.try_get_attribute(name)? {
+        Some(attr) => attr.unescape_value()?.to_string(),
+        None => String::new(),
+    })
+}
+
+/// Parse an updateinfo.xml document into advisories.
+pub fn parse_updateinfo<R: BufRead>(source: R) -> Result<Vec<Advisory>> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut advisories = Vec::new();
+    let mut current: Option<Advisory> = None;
+    let mut current_package: Option<AdvisoryPackage> = None;
+    let mut current_text = String::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        let empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                current_text.clear();
+
+                match e.name().as_ref() {
+                    b"update" => {
+                        let mut advisory = Advisory::new();
+                        advisory.kind = AdvisoryKind::parse(&attribute(&e, "type")?);
+                        current = Some(advisory);
+                    }
+                    b"issued" => {
+                        if let Some(ref mut advisory) = current {
+                            advisory.issued = attribute(&e, "date")?;
+                        }
+                    }
+                    b"reference" => {
+                        if let Some(ref mut advisory) = current {
+                            advisory.references.push(AdvisoryReference {
+                                kind: attribute(&e, "type")?,
+                                id: attribute(&e, "id")?,
+                                href: attribute(&e, "href")?,
+                                title: attribute(&e, "title")?,
+                            });
+                        }
+                    }
+                    b"package" => {
+                        let epoch = attribute(&e, "epoch")?.parse().unwrap_or(0);
+                        let version = attribute(&e, "version")?;
+                        let release = attribute(&e, "release")?;
+                        let package = AdvisoryPackage {
+                            name: attribute(&e, "name")?,
+                            arch: attribute(&e, "arch")?,
+                            version: Version {
+                                epoch,
+                                version,
+                                release,
+                            },
+                            filename: String::new(),
+                        };
+                        match current {
+                            Some(ref mut advisory) if empty => advisory.packages.push(package),
+                            _ => current_package = Some(package),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            Event::Text(e) => {
+                current_text.push_str(&e.unescape()?);
+            }
+
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"id" => {
+                        if let Some(ref mut advisory) = current {
+                            advisory.id = current_text.clone();
+                        }
+                    }
+                    b"title" => {
+                        if let Some(ref mut advisory) = current {
+                            advisory.title = current_text.clone();
+                        }
+                    }
+                    b"severity" => {
+                        if let Some(ref mut advisory) = current {
+                            if !current_text.is_empty() && current_text != "None" {
+                                advisory.severity = Some(current_text.clone());
+                            }
+                        }
+                    }
+                    b"filename" => {
+                        if let Some(ref mut package) = current_package {
+                            package.filename = current_text.clone();
+                        }
+                    }
+                    b"package" => {
+                        if let (Some(package), Some(ref mut advisory)) =
+                            (current_package.take(), current.as_mut())
+                        {
+                            advisory.packages.push(package);
+                        }
+                    }
+                    b"update" => {
+                        if let Some(advisory) = current.take() {
+                            advisories.push(advisory);
+                        }
+                    }
+                    _ => {}
+                }
+                current_text.clear();
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    log::info!("Parsed {} advisories from updateinfo", advisories.len());
+    Ok(advisories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::PackageName;
+
+    const UPDATEINFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<updates>
+  <update from="updates@fedoraproject.org" status="stable" type="security" version="2.0">
+    <id>FEDORA-2023-0001</id>
+    <title>openssl-3.1.1-4.fc39</title>
+    <issued date="2023-10-01 12:00:00"/>
+    <severity>Important</severity>
+    <references>
+      <reference href="https://example.org/CVE-2023-5678" id="CVE-2023-5678" type="cve" title="CVE-2023-5678"/>
+      <reference href="https://bugzilla.example.org/1" id="1" type="bugzilla" title="A bug"/>
+    </references>
+    <pkglist>
+      <collection short="F39">
+        <package name="openssl" version="3.1.1" release="4.fc39" epoch="1" arch="x86_64" src="openssl.src.rpm">
+          <filename>openssl-3.1.1-4.fc39.x86_64.rpm</filename>
+        </package>
+        <package name="openssl-libs" version="3.1.1" release="4.fc39" arch="i686"/>
+      </collection>
+    </pkglist>
+  </update>
+  <update type="bugfix">
+    <id>FEDORA-2023-0002</id>
+    <title>nano-7.2-4.fc39</title>
+    <severity>None</severity>
+  </update>
+</updates>
+"#;
+
+    fn advisories() -> Vec<Advisory> {
+        parse_updateinfo(UPDATEINFO.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn updateinfo_is_parsed() {
+        let advisories = advisories();
+        assert_eq!(advisories.len(), 2);
+
+        let security = &advisories[0];
+        assert_eq!(security.id, "FEDORA-2023-0001");
+        assert_eq!(security.kind, AdvisoryKind::Security);
+        assert_eq!(security.severity.as_deref(), Some("Important"));
+        assert_eq!(security.title, "openssl-3.1.1-4.fc39");
+        assert_eq!(security.issued, "2023-10-01 12:00:00");
+        let references: Vec<_> = security.references.iter().map(|r| (r.kind.as_str(), r.id.as_str())).collect();
+        assert_eq!(references, [("cve", "CVE-2023-5678"), ("bugzilla", "1")]);
+
+        let packages: Vec<_> = security.packages.iter().map(|p| (p.name.as_str(), p.arch.as_str(), p.filename.as_str())).collect();
+        assert_eq!(packages, [("openssl", "x86_64", "openssl-3.1.1-4.fc39.x86_64.rpm"), ("openssl-libs", "i686", "")]);
+        assert_eq!(security.packages[0].version, Version::new(1, "3.1.1", "4.fc39").unwrap());
+        assert_eq!(security.packages[1].version.epoch, 0);
+
+        assert_eq!(advisories[1].kind, AdvisoryKind::Bugfix);
+        assert_eq!(advisories[1].severity, None);
+        assert!(advisories[1].packages.is_empty());
+    }
+
+    #[test]
+    fn filters_select_by_kind_id_cve_and_severity() {
+        let advisories = advisories();
+        let (security, bugfix) = (&advisories[0], &advisories[1]);
+        let selected = |filter: AdvisoryFilter| (filter.matches(security), filter.matches(bugfix));
+
+        assert!(AdvisoryFilter::default().is_empty());
+        assert_eq!(selected(AdvisoryFilter { security: true, ..Default::default() }), (true, false));
+        assert_eq!(selected(AdvisoryFilter { bugfix: true, ..Default::default() }), (false, true));
+        let by_id = AdvisoryFilter { advisories: vec!["fedora-2023-0002".to_string()], ..Default::default() };
+        assert_eq!(selected(by_id), (false, true));
+        let by_cve = AdvisoryFilter { cves: vec!["cve-2023-5678".to_string()], ..Default::default() };
+        assert_eq!(selected(by_cve), (true, false));
+        let by_bug_id = AdvisoryFilter { cves: vec!["1".to_string()], ..Default::default() };
+        assert_eq!(selected(by_bug_id), (false, false));
+        let by_severity = AdvisoryFilter { severities: vec!["important".to_string()], ..Default::default() };
+        assert_eq!(selected(by_severity), (true, false));
+        let other_severity = AdvisoryFilter { severities: vec!["Low".to_string()], ..Default::default() };
+        assert_eq!(selected(other_severity), (false, false));
+    }
+
+    #[test]
+    fn advisories_fix_older_builds_of_the_same_arch() {
+        let advisories = advisories();
+        let installed = |arch: &str, evr: &str| {
+            let mut version = Version::parse(evr).unwrap();
+            version.epoch = 1;
+            Package::new(PackageName::new("openssl", arch).unwrap(), version, String::new())
+        };
+
+        assert!(advisories[0].fixes(&installed("x86_64", "3.1.1-3.fc39")).is_some());
+        assert!(advisories[0].fixes(&installed("x86_64", "3.1.1-4.fc39")).is_none());
+        assert!(advisories[0].fixes(&installed("aarch64", "3.1.1-3.fc39")).is_none());
+    }
+}
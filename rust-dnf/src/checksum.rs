@@ -0,0 +1,75 @@
+use anyhow::Result;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+fn digest<D: Digest + std::io::Write>(reader: &mut dyn Read) -> Result<String> {
+    let mut hasher = D::new();
+    std::io::copy(reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hex digest of everything `reader` yields, by a checksum type name as
+/// used in repository metadata. Older createrepo versions call sha1 "sha".
+pub fn reader_digest(reader: &mut dyn Read, typ: &str) -> Result<String> {
+    match typ.to_lowercase().as_str() {
+        "md5" => digest::<Md5>(reader),
+        "sha" | "sha1" => digest::<Sha1>(reader),
+        "sha224" => digest::<Sha224>(reader),
+        "sha256" => digest::<Sha256>(reader),
+        "sha384" => digest::<Sha384>(reader),
+        "sha512" => digest::<Sha512>(reader),
+        _ => anyhow::bail!("Unsupported checksum type {}", typ),
+    }
+}
+
+/// Hex digest of the file at `path`
+pub fn file_digest(path: &Path, typ: &str) -> Result<String> {
+    reader_digest(&mut fs::File::open(path)?, typ)
+}
+
+/// Whether `file_digest` can compute digests of this type
+pub fn supported(typ: &str) -> bool {
+    matches!(
+        typ.to_lowercase().as_str(),
+        "md5" | "sha" | "sha1" | "sha224" | "sha256" | "sha384" | "sha512"
+    )
+}
+
+/// Fail unless the file at `path` has the given digest
+pub fn verify_file(path: &Path, typ: &str, expected: &str) -> Result<()> {
+    let actual = file_digest(path, typ)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!("{:?} {} mismatch: expected {}, got {}", path, typ, expected, actual);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_by_type_name() {
+        let digest = |typ: &str| reader_digest(&mut &b"abc"[..], typ).unwrap();
+        assert_eq!(digest("md5"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest("sha1"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(digest("sha"), digest("SHA1"));
+        assert_eq!(digest("sha256"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(reader_digest(&mut &b"abc"[..], "crc32").is_err());
+        assert!(supported("MD5") && supported("sha") && !supported("crc32"));
+    }
+
+    #[test]
+    fn verify_rejects_other_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "abc").unwrap();
+        verify_file(&path, "sha1", "A9993E364706816ABA3E25717850C26C9CD0D89D").unwrap();
+        fs::write(&path, "abd").unwrap();
+        assert!(verify_file(&path, "sha1", "a9993e364706816aba3e25717850c26c9cd0d89d").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::zchunk;
+use anyhow::Result;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use xz2::read::XzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Bzip2,
+    Zstd,
+    Zchunk,
+}
+
+impl Compression {
+    /// Detect the compression format from the first bytes of a file.
+    /// File extensions are not trusted; mirrors rename files freely.
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else if magic.starts_with(b"BZh") {
+            Self::Bzip2
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if magic.starts_with(zchunk::MAGIC) {
+            Self::Zchunk
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Wrap `reader` in the decoder matching its magic bytes
+pub fn decoder<R: BufRead + 'static>(mut reader: R) -> Result<Box<dyn Read>> {
+    let compression = Compression::detect(reader.fill_buf()?);
+    log::debug!("Detected {:?} compression", compression);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        Compression::Zchunk => Box::new(zchunk::Decoder::new(reader)?),
+    })
+}
+
+/// Open a metadata file, transparently decompressing it.
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+    decoder(BufReader::new(fs::File::open(path)?))
+}
+
+/// Make an uncompressed copy of `path` at `dest` for consumers that need
+/// random access, such as SQLite. Returns `path` itself when it is not
+/// compressed.
+pub fn decompress_to(path: &Path, dest: &Path) -> Result<PathBuf> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if Compression::detect(reader.fill_buf()?) == Compression::None {
+        return Ok(path.to_path_buf());
+    }
+
+    let tmp_path = dest.with_extension("tmp");
+    {
+        let mut out = fs::File::create(&tmp_path)?;
+        std::io::copy(&mut decoder(reader)?, &mut out)?;
+    }
+    fs::rename(&tmp_path, dest)?;
+    Ok(dest.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEXT: &[u8] = b"<metadata packages=\"1\">\n  <package type=\"rpm\"/>\n</metadata>\n";
+
+    fn compressed(compression: Compression) -> Vec<u8> {
+        match compression {
+            Compression::None => TEXT.to_vec(),
+            Compression::Gzip => {
+                let mut out = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                out.write_all(TEXT).unwrap();
+                out.finish().unwrap()
+            }
+            Compression::Xz => {
+                let mut out = xz2::write::XzEncoder::new(Vec::new(), 6);
+                out.write_all(TEXT).unwrap();
+                out.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut out = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                out.write_all(TEXT).unwrap();
+                out.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(TEXT, 3).unwrap(),
+            Compression::Zchunk => zchunk::tests::zchunk_file(&[&TEXT[..20], &TEXT[20..]]),
+        }
+    }
+
+    const ALL: [(Compression, &str); 6] = [
+        (Compression::None, "xml"),
+        (Compression::Gzip, "xml.gz"),
+        (Compression::Xz, "xml.xz"),
+        (Compression::Bzip2, "xml.bz2"),
+        (Compression::Zstd, "xml.zst"),
+        (Compression::Zchunk, "xml.zck"),
+    ];
+
+    fn read_all(mut reader: Box<dyn Read>) -> Vec<u8> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn formats_are_detected_and_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        for (compression, extension) in ALL {
+            let data = compressed(compression);
+            assert_eq!(Compression::detect(&data), compression);
+            let path = dir.path().join(format!("primary.{}", extension));
+            fs::write(&path, &data).unwrap();
+            assert_eq!(read_all(open(&path).unwrap()), TEXT, "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn extensions_are_not_trusted() {
+        let dir = tempfile::tempdir().unwrap();
+        for (compression, _) in ALL {
+            // Mirrors rename files, so the content alone decides
+            let path = dir.path().join("primary.unknown");
+            fs::write(&path, compressed(compression)).unwrap();
+            assert_eq!(read_all(open(&path).unwrap()), TEXT, "{:?}", compression);
+        }
+        let path = dir.path().join("primary.xml.gz");
+        fs::write(&path, TEXT).unwrap();
+        assert_eq!(read_all(open(&path).unwrap()), TEXT);
+        assert_eq!(Compression::detect(b""), Compression::None);
+    }
+
+    #[test]
+    fn only_compressed_files_are_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("primary.sqlite");
+        fs::write(&plain, TEXT).unwrap();
+        assert_eq!(decompress_to(&plain, &dir.path().join("copy.sqlite")).unwrap(), plain);
+        assert!(!dir.path().join("copy.sqlite").exists());
+
+        let packed = dir.path().join("primary.sqlite.xz");
+        fs::write(&packed, compressed(Compression::Xz)).unwrap();
+        let dest = dir.path().join("unpacked.sqlite");
+        assert_eq!(decompress_to(&packed, &dest).unwrap(), dest);
+        assert_eq!(fs::read(&dest).unwrap(), TEXT);
+    }
+}
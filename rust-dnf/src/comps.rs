@@ -0,0 +1,338 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PackageReqKind {
+    Mandatory,
+    Default,
+    Optional,
+    Conditional,
+}
+
+impl PackageReqKind {
+    fn parse(s: &str) -> Self {
+        match s {
+            "mandatory" => Self::Mandatory,
+            "optional" => Self::Optional,
+            "conditional" => Self::Conditional,
+            _ => Self::Default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageReq {
+    pub name: String,
+    pub kind: PackageReqKind,
+    pub requires: Option<String>, // Only for conditional packages
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub default: bool,
+    pub uservisible: bool,
+    pub packages: Vec<PackageReq>,
+}
+
+impl Group {
+    /// Packages a group install pulls in. Conditional packages are included
+    /// when `is_wanted` reports their required package as installed or
+    /// already part of the install.
+    pub fn install_set(&self, with_optional: bool, is_wanted: impl Fn(&str) -> bool) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .packages
+            .iter()
+            .filter(|req| match req.kind {
+                PackageReqKind::Mandatory | PackageReqKind::Default => true,
+                PackageReqKind::Optional => with_optional,
+                PackageReqKind::Conditional => false,
+            })
+            .map(|req| req.name.as_str())
+            .collect();
+
+        for req in &self.packages {
+            if req.kind != PackageReqKind::Conditional {
+                continue;
+            }
+            let wanted = req
+                .requires
+                .as_deref()
+                .map(|requires| names.contains(&requires) || is_wanted(requires))
+                .unwrap_or(false);
+            if wanted {
+                names.push(&req.name);
+            }
+        }
+
+        names
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub display_order: Option<u32>,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvironmentOption {
+    pub group: String,
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Environment {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub display_order: Option<u32>,
+    pub groups: Vec<String>,
+    pub options: Vec<EnvironmentOption>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Comps {
+    pub groups: Vec<Group>,
+    pub categories: Vec<Category>,
+    pub environments: Vec<Environment>,
+}
+
+enum Section {
+    None,
+    Group(Group),
+    Category(Category),
+    Environment(Environment),
+}
+
+/// Parse a comps document (group.xml). Translated names and descriptions
+/// are skipped; only the untranslated text is kept.
+pub fn parse_comps<R: BufRead>(source: R) -> Result<Comps> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut comps = Comps::default();
+    let mut section = Section::None;
+    let mut current_text = String::new();
+    let mut translated = false;
+    let mut in_optionlist = false;
+    let mut req_kind = PackageReqKind::Default;
+    let mut req_requires: Option<String> = None;
+    let mut option_default = false;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                current_text.clear();
+                translated = e.try_get_attribute("xml:lang")?.is_some();
+
+                match e.name().as_ref() {
+                    b"group" => section = Section::Group(Group::default()),
+                    b"category" => section = Section::Category(Category::default()),
+                    b"environment" => section = Section::Environment(Environment::default()),
+                    b"optionlist" => in_optionlist = true,
+                    b"packagereq" => {
+                        req_kind = match e.try_get_attribute("type")? {
+                            Some(attr) => PackageReqKind::parse(&attr.unescape_value()?),
+                            None => PackageReqKind::Default,
+                        };
+                        req_requires = match e.try_get_attribute("requires")? {
+                            Some(attr) => Some(attr.unescape_value()?.to_string()),
+                            None => None,
+                        };
+                    }
+                    b"groupid" => {
+                        option_default = match e.try_get_attribute("default")? {
+                            Some(attr) => attr.unescape_value()? == "true",
+                            None => false,
+                        };
+                    }
+                    _ => {}
+                }
+            }
+
+            Event::Text(e) => {
+                current_text.push_str(&e.unescape()?);
+            }
+
+            Event::End(e) => {
+                let text = std::mem::take(&mut current_text);
+                let tag = e.name();
+                let tag = tag.as_ref();
+
+                match (&mut section, tag) {
+                    (_, b"optionlist") => in_optionlist = false,
+                    (_, b"name" | b"description") if translated => {}
+
+                    (Section::Group(group), b"id") => group.id = text,
+                    (Section::Group(group), b"name") => group.name = text,
+                    (Section::Group(group), b"description") => group.description = text,
+                    (Section::Group(group), b"default") => group.default = text == "true",
+                    (Section::Group(group), b"uservisible") => group.uservisible = text == "true",
+                    (Section::Group(group), b"packagereq") => group.packages.push(PackageReq {
+                        name: text,
+                        kind: req_kind,
+                        requires: req_requires.take(),
+                    }),
+
+                    (Section::Category(category), b"id") => category.id = text,
+                    (Section::Category(category), b"name") => category.name = text,
+                    (Section::Category(category), b"description") => category.description = text,
+                    (Section::Category(category), b"display_order") => {
+                        category.display_order = text.parse().ok()
+                    }
+                    (Section::Category(category), b"groupid") => category.groups.push(text),
+
+                    (Section::Environment(env), b"id") => env.id = text,
+                    (Section::Environment(env), b"name") => env.name = text,
+                    (Section::Environment(env), b"description") => env.description = text,
+                    (Section::Environment(env), b"display_order") => {
+                        env.display_order = text.parse().ok()
+                    }
+                    (Section::Environment(env), b"groupid") if in_optionlist => {
+                        env.options.push(EnvironmentOption {
+                            group: text,
+                            default: option_default,
+                        })
+                    }
+                    (Section::Environment(env), b"groupid") => env.groups.push(text),
+
+                    (_, b"group" | b"category" | b"environment") => {
+                        match std::mem::replace(&mut section, Section::None) {
+                            Section::Group(group) => comps.groups.push(group),
+                            Section::Category(category) => comps.categories.push(category),
+                            Section::Environment(env) => comps.environments.push(env),
+                            Section::None => {}
+                        }
+                    }
+                    _ => {}
+                }
+                translated = false;
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    log::info!(
+        "Parsed {} groups, {} categories and {} environments from comps",
+        comps.groups.len(),
+        comps.categories.len(),
+        comps.environments.len()
+    );
+    Ok(comps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<comps>
+  <group>
+    <id>editors</id>
+    <name>Editors</name>
+    <name xml:lang="de">Editoren</name>
+    <description>Sometimes called text editors.</description>
+    <default>false</default>
+    <uservisible>true</uservisible>
+    <packagelist>
+      <packagereq type="mandatory">nano</packagereq>
+      <packagereq>vim-enhanced</packagereq>
+      <packagereq type="default">emacs</packagereq>
+      <packagereq type="optional">joe</packagereq>
+      <packagereq type="conditional" requires="emacs">emacs-spell</packagereq>
+      <packagereq type="conditional" requires="python3">vim-python</packagereq>
+      <packagereq type="conditional" requires="xemacs">xemacs-extras</packagereq>
+    </packagelist>
+  </group>
+  <category>
+    <id>apps</id>
+    <name>Applications</name>
+    <display_order>10</display_order>
+    <grouplist>
+      <groupid>editors</groupid>
+    </grouplist>
+  </category>
+  <environment>
+    <id>server</id>
+    <name>Server</name>
+    <description>A server.</description>
+    <display_order>5</display_order>
+    <grouplist>
+      <groupid>core</groupid>
+      <groupid>standard</groupid>
+    </grouplist>
+    <optionlist>
+      <groupid default="true">editors</groupid>
+      <groupid>web-server</groupid>
+    </optionlist>
+  </environment>
+</comps>
+"#;
+
+    fn comps() -> Comps {
+        parse_comps(COMPS.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn groups_are_parsed() {
+        let comps = comps();
+        assert_eq!(comps.groups.len(), 1);
+        let group = &comps.groups[0];
+        assert_eq!((group.id.as_str(), group.name.as_str()), ("editors", "Editors"));
+        assert_eq!(group.description, "Sometimes called text editors.");
+        assert!(!group.default && group.uservisible);
+
+        let packages: Vec<_> = group.packages.iter().map(|req| (req.name.as_str(), req.kind, req.requires.as_deref())).collect();
+        assert_eq!(
+            packages,
+            [
+                ("nano", PackageReqKind::Mandatory, None),
+                ("vim-enhanced", PackageReqKind::Default, None),
+                ("emacs", PackageReqKind::Default, None),
+                ("joe", PackageReqKind::Optional, None),
+                ("emacs-spell", PackageReqKind::Conditional, Some("emacs")),
+                ("vim-python", PackageReqKind::Conditional, Some("python3")),
+                ("xemacs-extras", PackageReqKind::Conditional, Some("xemacs")),
+            ]
+        );
+
+        let category = &comps.categories[0];
+        assert_eq!((category.id.as_str(), category.display_order), ("apps", Some(10)));
+        assert_eq!(category.groups, ["editors"]);
+    }
+
+    #[test]
+    fn environments_list_their_groups_and_options() {
+        let comps = comps();
+        assert_eq!(comps.environments.len(), 1);
+        let environment = &comps.environments[0];
+        assert_eq!((environment.id.as_str(), environment.name.as_str()), ("server", "Server"));
+        assert_eq!(environment.display_order, Some(5));
+        assert_eq!(environment.groups, ["core", "standard"]);
+        let options: Vec<_> = environment.options.iter().map(|option| (option.group.as_str(), option.default)).collect();
+        assert_eq!(options, [("editors", true), ("web-server", false)]);
+    }
+
+    #[test]
+    fn install_sets_follow_package_kinds() {
+        let comps = comps();
+        let group = &comps.groups[0];
+        let installed = |name: &str| name == "python3";
+
+        assert_eq!(group.install_set(false, installed), ["nano", "vim-enhanced", "emacs", "emacs-spell", "vim-python"]);
+        assert_eq!(group.install_set(true, |_| false), ["nano", "vim-enhanced", "emacs", "joe", "emacs-spell"]);
+    }
+}
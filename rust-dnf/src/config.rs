@@ -4,14 +4,75 @@ use std::fs;
 use std::path::PathBuf;
 use anyhow::Result;
 
+/// Proxy, TLS and authentication settings, named as in dnf.conf. They can be
+/// set globally and per repository, where each one that is set wins.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct NetworkOptions {
+    pub proxy: Option<String>, // "_none_" turns a global proxy off for a repository
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    pub sslverify: Option<bool>,
+    pub sslcacert: Option<PathBuf>,
+    pub sslclientcert: Option<PathBuf>,
+    pub sslclientkey: Option<PathBuf>, // Defaults to `sslclientcert` holding both
+    pub username: Option<String>,      // HTTP basic authentication
+    pub password: Option<String>,
+    pub http_headers: Vec<String>,     // Extra "Name: value" request headers
+}
+
+impl NetworkOptions {
+    /// These options with unset ones taken from `global`. Headers of both
+    /// are sent, ours last so that they win.
+    pub fn or(&self, global: &NetworkOptions) -> NetworkOptions {
+        NetworkOptions {
+            proxy: self.proxy.clone().or_else(|| global.proxy.clone()),
+            proxy_username: self.proxy_username.clone().or_else(|| global.proxy_username.clone()),
+            proxy_password: self.proxy_password.clone().or_else(|| global.proxy_password.clone()),
+            sslverify: self.sslverify.or(global.sslverify),
+            sslcacert: self.sslcacert.clone().or_else(|| global.sslcacert.clone()),
+            sslclientcert: self.sslclientcert.clone().or_else(|| global.sslclientcert.clone()),
+            sslclientkey: self.sslclientkey.clone().or_else(|| global.sslclientkey.clone()),
+            username: self.username.clone().or_else(|| global.username.clone()),
+            password: self.password.clone().or_else(|| global.password.clone()),
+            http_headers: global.http_headers.iter().chain(&self.http_headers).cloned().collect(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Repository {
     pub name: String,
-    pub url: String,
+    pub url: String, // Base URL, tried before any mirrors; may be empty
     pub enabled: bool,
     pub gpg_check: bool,
     pub gpg_key: Option<String>,
     pub metadata_sig: bool,  // Whether to check metadata signatures
+    #[serde(default)]
+    pub mirrorlist: Option<String>, // URL of a list of mirror base URLs
+    #[serde(default)]
+    pub metalink: Option<String>,   // URL of a metalink listing mirrors and repomd.xml hashes
+    #[serde(flatten)]
+    pub network: NetworkOptions,
+}
+
+impl Repository {
+    /// Whether mirrors are looked up through a mirrorlist or metalink
+    pub fn has_mirrors(&self) -> bool {
+        self.mirrorlist.is_some() || self.metalink.is_some()
+    }
+
+    /// Directory of a repository whose URL is a `file://` URL or an absolute
+    /// path. Such repositories are read in place instead of being downloaded.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        if let Some(path) = self.url.strip_prefix("file://") {
+            Some(PathBuf::from(path))
+        } else if self.url.starts_with('/') {
+            Some(PathBuf::from(&self.url))
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,6 +83,52 @@ pub struct Config {
     pub database_dir: PathBuf,
     pub releasever: String,  // Fedora release version
     pub basearch: String,    // Base architecture
+    #[serde(default = "default_max_parallel_downloads")]
+    pub max_parallel_downloads: usize,
+    #[serde(default = "default_max_connections_per_host")]
+    pub max_connections_per_host: usize,
+    #[serde(default = "default_retries")]
+    pub retries: u32,        // Retries of a failed download, after all mirrors failed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,        // Seconds to wait for a connection or for data
+    #[serde(default = "default_minrate")]
+    pub minrate: u64,        // Bytes/s below which a download is abandoned after `timeout`
+    #[serde(default)]
+    pub throttle: String,    // Limit for all downloads together, e.g. "2M" or "50%" of `bandwidth`
+    #[serde(default)]
+    pub bandwidth: String,   // Available bandwidth, e.g. "10M"
+    #[serde(default = "default_deltarpm")]
+    pub deltarpm: bool,      // Rebuild upgrades from deltas of installed builds when smaller
+    #[serde(default)]
+    pub localpkg_gpgcheck: bool, // Check signatures of rpms given as paths or URLs
+    #[serde(default)]
+    pub assumeyes: bool,     // Answer yes instead of asking, e.g. whether to import a repository's key
+    #[serde(flatten)]
+    pub network: NetworkOptions,
+}
+
+fn default_max_parallel_downloads() -> usize {
+    3
+}
+
+fn default_max_connections_per_host() -> usize {
+    2
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_minrate() -> u64 {
+    1000
+}
+
+fn default_deltarpm() -> bool {
+    true
 }
 
 impl Default for Config {
@@ -36,11 +143,14 @@ impl Default for Config {
             "fedora".to_string(),
             Repository {
                 name: "fedora".to_string(),
-                url: format!("https://download.fedoraproject.org/pub/fedora/linux/releases/{}/Everything/{}/os/", releasever, basearch),
+                url: String::new(),
                 enabled: true,
                 gpg_check: true,
                 gpg_key: Some("/etc/pki/rpm-gpg/RPM-GPG-KEY-fedora-39-x86_64".to_string()),
                 metadata_sig: true,
+                mirrorlist: None,
+                metalink: Some(format!("https://mirrors.fedoraproject.org/metalink?repo=fedora-{}&arch={}", releasever, basearch)),
+                network: NetworkOptions::default(),
             },
         );
         
@@ -48,11 +158,14 @@ impl Default for Config {
             "updates".to_string(),
             Repository {
                 name: "updates".to_string(),
-                url: format!("https://download.fedoraproject.org/pub/fedora/linux/updates/{}/Everything/{}/", releasever, basearch),
+                url: String::new(),
                 enabled: true,
                 gpg_check: true,
                 gpg_key: Some("/etc/pki/rpm-gpg/RPM-GPG-KEY-fedora-39-x86_64".to_string()),
                 metadata_sig: true,
+                mirrorlist: None,
+                metalink: Some(format!("https://mirrors.fedoraproject.org/metalink?repo=updates-released-f{}&arch={}", releasever, basearch)),
+                network: NetworkOptions::default(),
             },
         );
         
@@ -60,11 +173,14 @@ impl Default for Config {
             "fedora-modular".to_string(),
             Repository {
                 name: "fedora-modular".to_string(),
-                url: format!("https://download.fedoraproject.org/pub/fedora/linux/releases/{}/Modular/{}/os/", releasever, basearch),
+                url: String::new(),
                 enabled: true,
                 gpg_check: true,
                 gpg_key: Some("/etc/pki/rpm-gpg/RPM-GPG-KEY-fedora-39-x86_64".to_string()),
                 metadata_sig: true,
+                mirrorlist: None,
+                metalink: Some(format!("https://mirrors.fedoraproject.org/metalink?repo=fedora-modular-{}&arch={}", releasever, basearch)),
+                network: NetworkOptions::default(),
             },
         );
 
@@ -75,6 +191,17 @@ impl Default for Config {
             database_dir: PathBuf::from("/var/lib/rust-dnf"),
             releasever: releasever.to_string(),
             basearch: basearch.to_string(),
+            max_parallel_downloads: default_max_parallel_downloads(),
+            max_connections_per_host: default_max_connections_per_host(),
+            retries: default_retries(),
+            timeout: default_timeout(),
+            minrate: default_minrate(),
+            throttle: String::new(),
+            bandwidth: String::new(),
+            deltarpm: default_deltarpm(),
+            localpkg_gpgcheck: false,
+            assumeyes: false,
+            network: NetworkOptions::default(),
         }
     }
 }
@@ -0,0 +1,194 @@
+use crate::checksum;
+use crate::package::{FileEntry, Package};
+use crate::payload;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// File flags of rpm headers
+pub const RPMFILE_CONFIG: u32 = 1 << 0;
+pub const RPMFILE_NOREPLACE: u32 = 1 << 4;
+pub const RPMFILE_GHOST: u32 = 1 << 6;
+
+/// What happens to a %config file already on disk when a package that
+/// ships it is installed. Files without an action are simply replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigAction {
+    Keep,                // The package's version did not change, so the edited file stays
+    CreateNew,           // The file on disk stays; the package's version goes to `.rpmnew`
+    Save(&'static str), // The file on disk is moved aside with this suffix, then replaced
+}
+
+impl ConfigAction {
+    /// What to tell the admin about `path`, if anything
+    pub fn describe(self, path: &str) -> Option<String> {
+        match self {
+            ConfigAction::Keep => None,
+            ConfigAction::CreateNew => Some(format!("{} created as {}.rpmnew", path, path)),
+            ConfigAction::Save(suffix) => Some(format!("{} saved as {}{}", path, path, suffix)),
+        }
+    }
+}
+
+/// Whether `file` is a %config file the package writes. %ghost files are
+/// not in the payload, so there is nothing to keep, save or replace.
+fn is_config(file: &FileEntry) -> bool {
+    file.flags & (RPMFILE_CONFIG | RPMFILE_GHOST) == RPMFILE_CONFIG
+        && u32::from(file.mode) & payload::S_IFMT == payload::S_IFREG
+}
+
+/// Digest of the regular file at `path` by `algo`, or None when there is
+/// no such file or the algorithm is not supported
+fn disk_digest(path: &Path, algo: &str) -> Option<String> {
+    if !checksum::supported(algo) || !fs::symlink_metadata(path).ok()?.is_file() {
+        return None;
+    }
+    checksum::file_digest(path, algo).ok()
+}
+
+/// Decide, like rpm, what to do with the %config files of `package` that
+/// are already on disk, given the build of it that is installed if any.
+/// A file that is unchanged on disk, or already what the package ships, is
+/// replaced. An edited file stays when the package still ships the same
+/// version of it; otherwise a %config(noreplace) file stays and the new
+/// version goes to `.rpmnew`, while a plain %config file is saved as
+/// `.rpmsave` (`.rpmorig` if no package owned it) and replaced.
+pub fn plan(root: &Path, package: &Package, old: Option<&Package>) -> HashMap<String, ConfigAction> {
+    let mut actions = HashMap::new();
+    for file in package.file_entries.iter().filter(|file| is_config(file)) {
+        let Ok(path) = payload::root_path(root, &file.path) else { continue };
+        if !fs::symlink_metadata(&path).map(|metadata| metadata.is_file()).unwrap_or(false) {
+            continue;
+        }
+        if disk_digest(&path, &package.file_digest_algo).is_some_and(|digest| digest.eq_ignore_ascii_case(&file.digest)) {
+            continue;
+        }
+        let noreplace = file.flags & RPMFILE_NOREPLACE != 0;
+        let old_file = old.and_then(|old| {
+            old.file_entries
+                .iter()
+                .find(|old_file| old_file.path == file.path && is_config(old_file))
+                .map(|old_file| (old, old_file))
+        });
+        let action = match old_file {
+            Some((old, old_file)) => {
+                if disk_digest(&path, &old.file_digest_algo).is_some_and(|digest| digest.eq_ignore_ascii_case(&old_file.digest)) {
+                    continue;
+                }
+                if old.file_digest_algo == package.file_digest_algo && old_file.digest == file.digest {
+                    ConfigAction::Keep
+                } else if noreplace {
+                    ConfigAction::CreateNew
+                } else {
+                    ConfigAction::Save(".rpmsave")
+                }
+            }
+            None if noreplace => ConfigAction::CreateNew,
+            None => ConfigAction::Save(".rpmorig"),
+        };
+        actions.insert(file.path.clone(), action);
+    }
+    actions
+}
+
+/// Whether the %config file `path` of the installed `package` was edited
+/// since it was installed, so that removing it must keep a `.rpmsave`.
+/// Files whose digest cannot be checked count as edited.
+pub fn is_modified(root: &Path, package: &Package, path: &str) -> bool {
+    let file = match package.file_entries.iter().find(|file| file.path == path) {
+        Some(file) if is_config(file) => file,
+        _ => return false,
+    };
+    let Ok(disk_path) = payload::root_path(root, path) else { return true };
+    !disk_digest(&disk_path, &package.file_digest_algo).is_some_and(|digest| digest.eq_ignore_ascii_case(&file.digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::{PackageName, Version};
+
+    const MD5_SHIPPED: &str = "5d41402abc4b2a76b9719d911017c592"; // "hello"
+    const MD5_OTHER: &str = "7d793037a0760186574b0282f2f435e7"; // "world"
+
+    fn config(path: &str, digest: &str, flags: u32) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            mode: 0o100644,
+            digest: digest.to_string(),
+            flags: RPMFILE_CONFIG | flags,
+            ..Default::default()
+        }
+    }
+
+    fn package(release: &str, files: Vec<FileEntry>) -> Package {
+        let mut package = Package::new(
+            PackageName::new("setup", "noarch").unwrap(),
+            Version::new(0, "1.0", release).unwrap(),
+            String::new(),
+        );
+        package.file_digest_algo = "md5".to_string();
+        package.file_entries = files;
+        package
+    }
+
+    fn root_with(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        for (path, content) in files {
+            fs::write(root.path().join(path.trim_start_matches('/')), content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn unedited_config_files_are_replaced() {
+        let root = root_with(&[("/etc/shipped", "hello"), ("/etc/old", "world")]);
+        let old = package("1", vec![config("/etc/old", MD5_OTHER, 0)]);
+        let new = package("2", vec![config("/etc/shipped", MD5_SHIPPED, 0), config("/etc/old", MD5_SHIPPED, 0)]);
+
+        assert!(plan(root.path(), &new, Some(&old)).is_empty());
+        assert!(!is_modified(root.path(), &old, "/etc/old"));
+    }
+
+    #[test]
+    fn edited_config_files_are_kept_or_saved() {
+        let root = root_with(&[("/etc/same", "edited"), ("/etc/noreplace", "edited"), ("/etc/plain", "edited"), ("/etc/new", "edited")]);
+        let old = package(
+            "1",
+            vec![
+                config("/etc/same", MD5_SHIPPED, 0),
+                config("/etc/noreplace", MD5_SHIPPED, RPMFILE_NOREPLACE),
+                config("/etc/plain", MD5_SHIPPED, 0),
+            ],
+        );
+        let new = package(
+            "2",
+            vec![
+                config("/etc/same", MD5_SHIPPED, 0),
+                config("/etc/noreplace", MD5_OTHER, RPMFILE_NOREPLACE),
+                config("/etc/plain", MD5_OTHER, 0),
+                config("/etc/new", MD5_OTHER, 0),
+            ],
+        );
+
+        let actions = plan(root.path(), &new, Some(&old));
+        assert_eq!(actions.len(), 4);
+        assert_eq!(actions["/etc/same"], ConfigAction::Keep);
+        assert_eq!(actions["/etc/noreplace"], ConfigAction::CreateNew);
+        assert_eq!(actions["/etc/plain"], ConfigAction::Save(".rpmsave"));
+        assert_eq!(actions["/etc/new"], ConfigAction::Save(".rpmorig"));
+        assert!(is_modified(root.path(), &old, "/etc/plain"));
+    }
+
+    #[test]
+    fn ghost_config_files_are_left_alone() {
+        let root = root_with(&[("/etc/ghost", "edited")]);
+        let old = package("1", vec![config("/etc/ghost", "", RPMFILE_GHOST)]);
+        let new = package("2", vec![config("/etc/ghost", "", RPMFILE_GHOST)]);
+
+        assert!(plan(root.path(), &new, Some(&old)).is_empty());
+        assert!(plan(root.path(), &new, None).is_empty());
+        assert!(!is_modified(root.path(), &old, "/etc/ghost"));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::config_files::RPMFILE_GHOST;
+use crate::db::PackageDatabase;
+use crate::package::{FileEntry, Package};
+use crate::payload::{S_IFDIR, S_IFLNK, S_IFMT, S_IFREG};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A path that two packages ship with different content
+#[derive(Debug)]
+pub struct FileConflict {
+    pub path: String,
+    pub package: String,       // NEVRA of the incoming package
+    pub other: String,         // NEVRA of the package it collides with
+    pub other_installed: bool, // Whether that package is installed or also incoming
+}
+
+impl fmt::Display for FileConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let other = if self.other_installed { "package" } else { "install of" };
+        write!(f, "file {} from install of {} conflicts with file from {} {}", self.path, self.package, other, self.other)
+    }
+}
+
+/// Whether two packages may both own a path, as rpm decides it: shared
+/// directories and %ghost files never collide, nor do 32-bit and 64-bit
+/// builds of a file, of which the 64-bit one is installed. Otherwise the
+/// mode has to match, except for symlinks, and so does the owner and the
+/// content. Identical files are how multilib packages share their data.
+fn compatible(a: &FileEntry, a_algo: &str, b: &FileEntry, b_algo: &str) -> bool {
+    let (a_type, b_type) = (u32::from(a.mode) & S_IFMT, u32::from(b.mode) & S_IFMT);
+    if a_type == S_IFDIR && b_type == S_IFDIR {
+        return true;
+    }
+    if (a.flags | b.flags) & RPMFILE_GHOST != 0 {
+        return true;
+    }
+    if a.color != 0 && b.color != 0 && a.color & b.color == 0 {
+        return true;
+    }
+    if a_type != b_type || (a_type != S_IFLNK && a.mode != b.mode) {
+        return false;
+    }
+    match a_type {
+        S_IFREG => {
+            a.user == b.user
+                && a.group == b.group
+                && a_algo.eq_ignore_ascii_case(b_algo)
+                && a.digest.eq_ignore_ascii_case(&b.digest)
+        }
+        S_IFLNK => a.user == b.user && a.group == b.group && a.link_to == b.link_to,
+        _ => a.rdev == b.rdev,
+    }
+}
+
+/// Paths where the `incoming` packages collide with each other or with
+/// installed packages. Installed packages that go away in the same
+/// transaction, being replaced by an incoming build or among the database
+/// keys `erases`, do not count.
+pub fn find(db: &PackageDatabase, incoming: &[&Package], erases: &[String]) -> Vec<FileConflict> {
+    let mut conflicts = Vec::new();
+    let mut shipped: HashMap<&str, Vec<(&Package, &FileEntry)>> = HashMap::new();
+    for &package in incoming {
+        for file in &package.file_entries {
+            let shippers = shipped.entry(&file.path).or_default();
+            for &(other, other_file) in shippers.iter() {
+                if !compatible(file, &package.file_digest_algo, other_file, &other.file_digest_algo) {
+                    conflicts.push(FileConflict {
+                        path: file.path.clone(),
+                        package: package.nevra(),
+                        other: other.nevra(),
+                        other_installed: false,
+                    });
+                }
+            }
+            shippers.push((package, file));
+        }
+    }
+
+    let leaving: HashSet<String> = incoming
+        .iter()
+        .map(|package| format!("{}.{}", package.name.name, package.name.arch))
+        .chain(erases.iter().cloned())
+        .collect();
+    let mut keys: Vec<&String> = db.installed_packages.keys().filter(|key| !leaving.contains(*key)).collect();
+    keys.sort();
+    for key in keys {
+        let installed = &db.installed_packages[key];
+        let mut entries: Option<HashMap<&str, &FileEntry>> = None; // Built once a path is shared
+        for path in &installed.installed_files {
+            let shippers = match shipped.get(path.as_str()) {
+                Some(shippers) => shippers,
+                None => continue,
+            };
+            let entries = entries.get_or_insert_with(|| {
+                installed.package.file_entries.iter().map(|entry| (entry.path.as_str(), entry)).collect()
+            });
+            let entry = entries.get(path.as_str()).copied();
+            for &(package, file) in shippers {
+                let compatible = match entry {
+                    Some(entry) => compatible(file, &package.file_digest_algo, entry, &installed.package.file_digest_algo),
+                    // Without its header nothing is known of the installed file
+                    None => u32::from(file.mode) & S_IFMT == S_IFDIR,
+                };
+                if !compatible {
+                    conflicts.push(FileConflict {
+                        path: path.clone(),
+                        package: package.nevra(),
+                        other: installed.package.nevra(),
+                        other_installed: true,
+                    });
+                }
+            }
+        }
+    }
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.package.cmp(&b.package)));
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::InstalledPackage;
+    use crate::package::{PackageName, Version};
+
+    fn file(path: &str, mode: u32, digest: &str) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            mode: u16::try_from(mode).unwrap(),
+            user: "root".to_string(),
+            group: "root".to_string(),
+            digest: digest.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn package(name: &str, arch: &str, files: Vec<FileEntry>) -> Package {
+        let mut package = Package::new(
+            PackageName::new(name, arch).unwrap(),
+            Version::new(0, "1.0", "1").unwrap(),
+            String::new(),
+        );
+        package.file_digest_algo = "sha256".to_string();
+        package.file_entries = files;
+        package
+    }
+
+    fn database(installed: Vec<Package>) -> PackageDatabase {
+        let mut db = PackageDatabase::new("/nonexistent".into());
+        for package in installed {
+            let key = format!("{}.{}", package.name.name, package.name.arch);
+            let installed_files = package.file_entries.iter().map(|file| file.path.clone()).collect();
+            db.installed_packages.insert(key, InstalledPackage { package, installed_files, install_time: String::new() });
+        }
+        db
+    }
+
+    #[test]
+    fn shared_directories_and_identical_files_are_compatible() {
+        let compare = |a: &FileEntry, b: &FileEntry| compatible(a, "sha256", b, "sha256");
+        let data = file("/usr/share/doc/data", S_IFREG | 0o644, "aa");
+
+        assert!(compare(&file("/usr/share/doc", S_IFDIR | 0o755, ""), &file("/usr/share/doc", S_IFDIR | 0o700, "")));
+        assert!(compare(&data, &data.clone()));
+        assert!(compare(&data, &FileEntry { digest: "AA".to_string(), ..data.clone() }));
+        assert!(!compare(&data, &FileEntry { digest: "bb".to_string(), ..data.clone() }));
+        assert!(!compare(&data, &file("/usr/share/doc/data", S_IFREG | 0o600, "aa")));
+        assert!(!compare(&data, &FileEntry { user: "nobody".to_string(), ..data.clone() }));
+        assert!(!compare(&data, &file("/usr/share/doc/data", S_IFDIR | 0o644, "")));
+        assert!(!compatible(&data, "sha256", &data, "md5"));
+        assert!(compare(&data, &FileEntry { digest: "bb".to_string(), flags: RPMFILE_GHOST, ..data.clone() }));
+    }
+
+    #[test]
+    fn multilib_builds_of_a_file_are_compatible() {
+        let compare = |a: &FileEntry, b: &FileEntry| compatible(a, "sha256", b, "sha256");
+        let elf32 = FileEntry { color: 1, ..file("/usr/bin/tool", S_IFREG | 0o755, "aa") };
+        let elf64 = FileEntry { color: 2, ..file("/usr/bin/tool", S_IFREG | 0o755, "bb") };
+
+        assert!(compare(&elf32, &elf64));
+        assert!(!compare(&elf64, &FileEntry { digest: "cc".to_string(), ..elf64.clone() }));
+        assert!(!compare(&elf64, &FileEntry { color: 0, ..elf32.clone() }));
+    }
+
+    #[test]
+    fn colliding_paths_are_reported() {
+        let db = database(vec![
+            package("filesystem", "x86_64", vec![file("/etc", S_IFDIR | 0o755, ""), file("/etc/hosts", S_IFREG | 0o644, "aa")]),
+            package("old", "noarch", vec![file("/etc/old", S_IFREG | 0o644, "aa")]),
+        ]);
+        let hosts = package("hosts", "noarch", vec![file("/etc", S_IFDIR | 0o755, ""), file("/etc/hosts", S_IFREG | 0o644, "bb")]);
+        let other = package("other", "noarch", vec![file("/etc/hosts", S_IFREG | 0o644, "cc")]);
+        let replacement = package("replacement", "noarch", vec![file("/etc/old", S_IFREG | 0o644, "bb")]);
+
+        let conflicts = find(&db, &[&hosts, &other, &replacement], &["old.noarch".to_string()]);
+        let found: Vec<_> = conflicts.iter().map(|c| (c.package.as_str(), c.other.as_str(), c.other_installed)).collect();
+        assert_eq!(
+            found,
+            [
+                ("hosts-0:1.0-1.noarch", "filesystem-0:1.0-1.x86_64", true),
+                ("other-0:1.0-1.noarch", "hosts-0:1.0-1.noarch", false),
+                ("other-0:1.0-1.noarch", "filesystem-0:1.0-1.x86_64", true),
+            ]
+        );
+        assert!(conflicts.iter().all(|c| c.path == "/etc/hosts"));
+    }
+}
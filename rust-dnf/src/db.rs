@@ -4,7 +4,6 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use log;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InstalledPackage {
@@ -13,9 +12,29 @@ pub struct InstalledPackage {
     pub install_time: String, // ISO timestamp
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledGroup {
+    pub id: String,
+    pub name: String,
+    pub packages: Vec<String>, // Database keys of the packages the group pulled in
+    pub install_time: String,  // ISO timestamp
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledEnvironment {
+    pub id: String,
+    pub name: String,
+    pub groups: Vec<String>,  // Ids of the groups the environment pulled in
+    pub install_time: String, // ISO timestamp
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageDatabase {
     pub installed_packages: HashMap<String, InstalledPackage>,
+    #[serde(default)]
+    pub installed_groups: HashMap<String, InstalledGroup>,
+    #[serde(default)]
+    pub installed_environments: HashMap<String, InstalledEnvironment>,
     pub database_path: PathBuf,
 }
 
@@ -23,6 +42,8 @@ impl PackageDatabase {
     pub fn new(database_path: PathBuf) -> Self {
         Self {
             installed_packages: HashMap::new(),
+            installed_groups: HashMap::new(),
+            installed_environments: HashMap::new(),
             database_path,
         }
     }
@@ -39,7 +60,10 @@ impl PackageDatabase {
         let db: PackageDatabase = serde_json::from_str(&data)?;
         
         self.installed_packages = db.installed_packages;
-        log::info!("Loaded {} installed packages", self.installed_packages.len());
+        self.installed_groups = db.installed_groups;
+        self.installed_environments = db.installed_environments;
+        log::info!("Loaded {} installed packages, {} groups and {} environments",
+                   self.installed_packages.len(), self.installed_groups.len(), self.installed_environments.len());
         
         Ok(())
     }
@@ -58,12 +82,12 @@ impl PackageDatabase {
         Ok(())
     }
     
-    pub fn install_package(&mut self, package: Package) -> Result<()> {
+    pub fn install_package(&mut self, package: Package, installed_files: Vec<String>) -> Result<()> {
         let package_key = format!("{}.{}", package.name.name, package.name.arch);
         
         let installed_pkg = InstalledPackage {
             package,
-            installed_files: Vec::new(), // We'll populate this during actual installation
+            installed_files,
             install_time: chrono::Utc::now().to_rfc3339(),
         };
         
@@ -95,4 +119,88 @@ impl PackageDatabase {
     pub fn get_installed(&self, package_name: &str) -> Option<&InstalledPackage> {
         self.installed_packages.get(package_name)
     }
+    
+    /// How many builds named `name` are installed, over all architectures
+    pub fn instances(&self, name: &str) -> u32 {
+        self.installed_packages
+            .values()
+            .filter(|installed| installed.package.name.name == name)
+            .count() as u32
+    }
+    
+    pub fn install_group(&mut self, id: &str, name: &str, packages: Vec<String>) -> Result<()> {
+        let group = InstalledGroup {
+            id: id.to_string(),
+            name: name.to_string(),
+            packages,
+            install_time: chrono::Utc::now().to_rfc3339(),
+        };
+        
+        self.installed_groups.insert(id.to_string(), group);
+        self.save()?;
+        
+        log::info!("Group {} added to database", id);
+        Ok(())
+    }
+    
+    /// Forget an installed group and return the packages only it was keeping
+    /// installed, i.e. those not pulled in by any other installed group.
+    pub fn remove_group(&mut self, id: &str) -> Result<Vec<String>> {
+        let group = match self.installed_groups.remove(id) {
+            Some(group) => group,
+            None => anyhow::bail!("Group {} is not installed", id),
+        };
+        self.save()?;
+        
+        let orphaned = group
+            .packages
+            .into_iter()
+            .filter(|key| !self.installed_groups.values().any(|g| g.packages.contains(key)))
+            .collect();
+        
+        log::info!("Group {} removed from database", id);
+        Ok(orphaned)
+    }
+    
+    pub fn get_installed_group(&self, id: &str) -> Option<&InstalledGroup> {
+        self.installed_groups.get(id)
+    }
+    
+    pub fn install_environment(&mut self, id: &str, name: &str, groups: Vec<String>) -> Result<()> {
+        let environment = InstalledEnvironment {
+            id: id.to_string(),
+            name: name.to_string(),
+            groups,
+            install_time: chrono::Utc::now().to_rfc3339(),
+        };
+        
+        self.installed_environments.insert(id.to_string(), environment);
+        self.save()?;
+        
+        log::info!("Environment {} added to database", id);
+        Ok(())
+    }
+    
+    /// Forget an installed environment and return the groups only it was
+    /// keeping installed, i.e. those not pulled in by any other environment.
+    pub fn remove_environment(&mut self, id: &str) -> Result<Vec<String>> {
+        let environment = match self.installed_environments.remove(id) {
+            Some(environment) => environment,
+            None => anyhow::bail!("Environment {} is not installed", id),
+        };
+        self.save()?;
+        
+        let orphaned = environment
+            .groups
+            .into_iter()
+            .filter(|group| !self.installed_environments.values().any(|e| e.groups.contains(group)))
+            .collect();
+        
+        log::info!("Environment {} removed from database", id);
+        Ok(orphaned)
+    }
+    
+    pub fn get_installed_environment(&self, id: &str) -> Option<&InstalledEnvironment> {
+        self.installed_environments.get(id)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,265 @@
+use crate::checksum;
+use crate::package::{Package, Version};
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Once;
+
+/// Rebuilds rpms from deltas, as dnf does
+const APPLYDELTARPM: &str = "/usr/bin/applydeltarpm";
+
+/// A deltarpm from prestodelta metadata, turning one older build of a
+/// package into a newer one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delta {
+    pub base: Version, // The build the delta applies to
+    pub location: String,
+    pub size: u64,
+    pub checksum: Option<(String, String)>, // (type, hex digest) of the drpm
+    pub sequence: String, // Identifies the base build's files, to check them before use
+}
+
+/// Deltas by the nevra of the package they produce
+pub type Deltas = HashMap<String, Vec<Delta>>;
+
+/// Parse prestodelta.xml
+pub fn parse_prestodelta<R: BufRead>(source: R) -> Result<Deltas> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut deltas = Deltas::new();
+    let mut target: Option<String> = None;
+    let mut delta: Option<Delta> = None;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                text.clear();
+                match e.name().as_ref() {
+                    b"newpackage" => {
+                        let mut attrs: HashMap<Vec<u8>, String> = HashMap::new();
+                        for attr in e.attributes() {
+                            let attr = attr?;
+                            attrs.insert(attr.key.as_ref().to_vec(), attr.unescape_value()?.into_owned());
+                        }
+                        let attr = |key: &[u8]| attrs.get(key).cloned().unwrap_or_default();
+                        let epoch = attr(b"epoch");
+                        target = Some(format!(
+                            "{}-{}:{}-{}.{}",
+                            attr(b"name"),
+                            if epoch.is_empty() { "0".to_string() } else { epoch },
+                            attr(b"version"),
+                            attr(b"release"),
+                            attr(b"arch")
+                        ));
+                    }
+                    b"delta" => {
+                        let mut base = Version {
+                            epoch: 0,
+                            version: String::new(),
+                            release: String::new(),
+                        };
+                        for attr in e.attributes() {
+                            let attr = attr?;
+                            match attr.key.as_ref() {
+                                b"oldepoch" => base.epoch = attr.unescape_value()?.parse().unwrap_or(0),
+                                b"oldversion" => base.version = attr.unescape_value()?.into_owned(),
+                                b"oldrelease" => base.release = attr.unescape_value()?.into_owned(),
+                                _ => {}
+                            }
+                        }
+                        delta = Some(Delta {
+                            base,
+                            location: String::new(),
+                            size: 0,
+                            checksum: None,
+                            sequence: String::new(),
+                        });
+                    }
+                    b"checksum" => {
+                        if let (Some(delta), Some(typ)) = (delta.as_mut(), e.try_get_attribute("type")?) {
+                            delta.checksum = Some((typ.unescape_value()?.into_owned(), String::new()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            Event::Text(e) => text.push_str(&e.unescape()?),
+
+            Event::End(e) => {
+                match (e.name().as_ref(), delta.as_mut()) {
+                    (b"filename", Some(delta)) => delta.location = std::mem::take(&mut text),
+                    (b"sequence", Some(delta)) => delta.sequence = std::mem::take(&mut text),
+                    (b"size", Some(delta)) => delta.size = text.trim().parse().unwrap_or(0),
+                    (b"checksum", Some(delta)) => {
+                        if let Some((_, digest)) = delta.checksum.as_mut() {
+                            *digest = std::mem::take(&mut text);
+                        }
+                    }
+                    (b"delta", _) => {
+                        if let (Some(target), Some(delta)) = (&target, delta.take()) {
+                            if !delta.location.is_empty() {
+                                deltas.entry(target.clone()).or_default().push(delta);
+                            }
+                        }
+                    }
+                    (b"newpackage", _) => target = None,
+                    _ => {}
+                }
+                text.clear();
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(deltas)
+}
+
+/// The smallest of `deltas` that applies to the `installed` build, if it
+/// is smaller than the full rpm
+pub fn choose<'a>(deltas: &'a [Delta], installed: &Version, package_size: u64) -> Option<&'a Delta> {
+    deltas
+        .iter()
+        .filter(|delta| delta.base == *installed && delta.size < package_size)
+        .min_by_key(|delta| delta.size)
+}
+
+/// Whether rpms can be rebuilt from deltas on this system. Says once why
+/// not, so that deltarpm=true does not silently do nothing.
+pub fn available() -> bool {
+    static REPORTED: Once = Once::new();
+    let available = Path::new(APPLYDELTARPM).is_file();
+    if !available {
+        REPORTED.call_once(|| log::info!("Not using deltas, {} is not installed", APPLYDELTARPM));
+    }
+    available
+}
+
+/// Rebuild `package` at `dest` from the downloaded `delta_path`. The files
+/// of the base build are taken from its rpm when that is still cached, and
+/// otherwise from the installed system, which is checked against the
+/// delta's sequence first. The result must match the checksum and size
+/// primary gives for `package`, or nothing is left at `dest`.
+pub fn rebuild(package: &Package, delta: &Delta, delta_path: &Path, base_rpm: Option<&Path>, dest: &Path) -> Result<()> {
+    let (typ, expected) = match &package.checksum {
+        Some(checksum) => checksum,
+        None => anyhow::bail!("No checksum to verify a rebuilt {} against", package.nevra()),
+    };
+
+    if base_rpm.is_none() {
+        run(Command::new(APPLYDELTARPM)
+            .arg("-a")
+            .arg(package.name.arch.as_ref())
+            .arg("-c")
+            .arg("-s")
+            .arg(&delta.sequence))
+        .map_err(|e| anyhow::anyhow!("Installed files do not match the delta: {}", e))?;
+    }
+
+    let tmp_path = dest.with_extension("rebuild");
+    let mut command = Command::new(APPLYDELTARPM);
+    command.arg("-a").arg(package.name.arch.as_ref());
+    if let Some(base_rpm) = base_rpm {
+        command.arg("-r").arg(base_rpm);
+    }
+    command.arg(delta_path).arg(&tmp_path);
+
+    let result = run(&mut command).and_then(|()| {
+        let size = fs::metadata(&tmp_path)?.len();
+        if package.size != 0 && size != package.size {
+            anyhow::bail!("Rebuilt rpm is {} bytes, expected {}", size, package.size);
+        }
+        checksum::verify_file(&tmp_path, typ, expected)
+    });
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp_path, dest)?),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "applydeltarpm failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESTODELTA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<prestodelta>
+  <newpackage name="bash" epoch="0" version="5.2.26" release="3.fc40" arch="x86_64">
+    <delta oldepoch="0" oldversion="5.2.26" oldrelease="1.fc40">
+      <filename>drpms/bash-5.2.26-1.fc40_5.2.26-3.fc40.x86_64.drpm</filename>
+      <sequence>bash-5.2.26-1.fc40-d1e4</sequence>
+      <size>182313</size>
+      <checksum type="sha1">0123456789abcdef0123456789abcdef01234567</checksum>
+    </delta>
+    <delta oldepoch="0" oldversion="5.2.21" oldrelease="1.fc40">
+      <filename>drpms/bash-5.2.21-1.fc40_5.2.26-3.fc40.x86_64.drpm</filename>
+      <sequence>bash-5.2.21-1.fc40-77ab</sequence>
+      <size>402112</size>
+      <checksum type="md5">0123456789abcdef0123456789abcdef</checksum>
+    </delta>
+    <delta oldepoch="0" oldversion="5.2.15" oldrelease="1.fc40">
+      <sequence>no-filename</sequence>
+    </delta>
+  </newpackage>
+  <newpackage name="zsh" version="5.9" release="9.fc40" arch="x86_64">
+  </newpackage>
+</prestodelta>
+"#;
+
+    fn version(version: &str, release: &str) -> Version {
+        Version::new(0, version, release).unwrap()
+    }
+
+    #[test]
+    fn prestodelta_by_target_nevra() {
+        let deltas = parse_prestodelta(PRESTODELTA.as_bytes()).unwrap();
+        assert_eq!(deltas.len(), 1);
+        let bash = &deltas["bash-0:5.2.26-3.fc40.x86_64"];
+        assert_eq!(bash.len(), 2);
+        assert_eq!(bash[0].base, version("5.2.26", "1.fc40"));
+        assert_eq!(bash[0].location, "drpms/bash-5.2.26-1.fc40_5.2.26-3.fc40.x86_64.drpm");
+        assert_eq!(bash[0].sequence, "bash-5.2.26-1.fc40-d1e4");
+        assert_eq!(bash[0].size, 182313);
+        assert_eq!(
+            bash[1].checksum,
+            Some(("md5".to_string(), "0123456789abcdef0123456789abcdef".to_string()))
+        );
+    }
+
+    #[test]
+    fn smallest_delta_for_the_installed_build() {
+        let deltas = parse_prestodelta(PRESTODELTA.as_bytes()).unwrap();
+        let bash = &deltas["bash-0:5.2.26-3.fc40.x86_64"];
+        let chosen = choose(bash, &version("5.2.21", "1.fc40"), 1_800_000).unwrap();
+        assert_eq!(chosen.size, 402112);
+        // Not worth it when the rpm is smaller than the delta
+        assert!(choose(bash, &version("5.2.21", "1.fc40"), 400_000).is_none());
+        assert!(choose(bash, &version("5.1.8", "1.fc39"), 1_800_000).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::checksum;
+use crate::openpgp::{self, Certificate, Hasher, Signature};
+use crate::rpm::{self, tag};
+use anyhow::Result;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// A package was signed with a key that has not been imported
+#[derive(Debug, thiserror::Error)]
+#[error("Public key 0x{key_id:08X} of {path:?} is not imported")]
+pub struct MissingKey {
+    pub key_id: u32, // Short key id, as rpm shows it
+    pub path: PathBuf,
+}
+
+/// The public keys trusted to sign packages, one file per key in a
+/// directory under `database_dir`
+#[derive(Debug)]
+pub struct Keyring {
+    dir: PathBuf,
+    pub certificates: Vec<Certificate>,
+}
+
+impl Keyring {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut certificates = Vec::new();
+        if dir.is_dir() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("gpg") {
+                    continue;
+                }
+                match openpgp::parse_certificates(&fs::read(&path)?) {
+                    Ok(found) => certificates.extend(found),
+                    Err(e) => log::warn!("Ignoring unreadable key {:?}: {}", path, e),
+                }
+            }
+        }
+        certificates.sort_by_key(Certificate::name);
+        Ok(Self { dir: dir.to_path_buf(), certificates })
+    }
+
+    fn path(&self, certificate: &Certificate) -> PathBuf {
+        self.dir.join(format!("{}.gpg", certificate.name()))
+    }
+
+    /// Store the keys in binary or armored `data`, returning those that were
+    /// not imported yet
+    pub fn import(&mut self, data: &[u8]) -> Result<Vec<Certificate>> {
+        let imported = self.new_certificates(data)?;
+        for certificate in &imported {
+            self.add(certificate.clone())?;
+        }
+        Ok(imported)
+    }
+
+    /// The keys in binary or armored `data` that are not imported yet
+    pub fn new_certificates(&self, data: &[u8]) -> Result<Vec<Certificate>> {
+        let mut found = openpgp::parse_certificates(data)?;
+        found.retain(|certificate| {
+            let imported = self.certificates.iter().any(|c| c.primary.fingerprint == certificate.primary.fingerprint);
+            if imported {
+                log::debug!("Key {} is already imported", certificate.name());
+            }
+            !imported
+        });
+        Ok(found)
+    }
+
+    /// Store a key, trusting it to sign packages from now on
+    pub fn add(&mut self, certificate: Certificate) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(&certificate);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &certificate.data)?;
+        fs::rename(&tmp_path, &path)?;
+        self.certificates.push(certificate);
+        Ok(())
+    }
+
+    /// Forget the key `id` names, by name, key id or fingerprint
+    pub fn remove(&mut self, id: &str) -> Result<Certificate> {
+        let index = self
+            .certificates
+            .iter()
+            .position(|certificate| certificate.matches(id))
+            .ok_or_else(|| anyhow::anyhow!("No imported key matches {}", id))?;
+        let certificate = self.certificates.remove(index);
+        fs::remove_file(self.path(&certificate))?;
+        Ok(certificate)
+    }
+
+    /// Check the signatures of the rpm at `path` against the imported keys.
+    /// A header signature covers the payload only through the payload
+    /// digest in the header, so without a header+payload signature that
+    /// digest has to be there and match. Fails with `MissingKey` when the
+    /// signing key is not imported.
+    pub fn verify_rpm(&self, path: &Path) -> Result<()> {
+        let rpm_file = rpm::read(path)?;
+        let mut file = fs::File::open(path)?;
+        let mut header = vec![0; (rpm_file.payload_offset - rpm_file.header_offset) as usize];
+        file.seek(SeekFrom::Start(rpm_file.header_offset))?;
+        file.read_exact(&mut header)?;
+
+        let signed = |tag| match rpm_file.signature.get(tag) {
+            Some(rpm::Value::Bin(signature)) => Some(Signature::parse(signature)),
+            _ => None,
+        };
+        let header_signatures: Vec<_> = [tag::SIG_RSA, tag::SIG_DSA].into_iter().filter_map(signed).collect();
+        let full_signatures: Vec<_> = [tag::SIG_PGP, tag::SIG_GPG].into_iter().filter_map(signed).collect();
+        if header_signatures.is_empty() && full_signatures.is_empty() {
+            anyhow::bail!("{:?} is not signed", path);
+        }
+
+        for signature in header_signatures {
+            let signature = signature?;
+            let mut hasher = signature.hash.hasher();
+            hasher.update(&header);
+            self.check(&signature, hasher, path)?;
+        }
+        let payload_signed = !full_signatures.is_empty();
+        for signature in full_signatures {
+            let signature = signature?;
+            let mut hasher = signature.hash.hasher();
+            hasher.update(&header);
+            file.seek(SeekFrom::Start(rpm_file.payload_offset))?;
+            std::io::copy(&mut file, &mut hasher)?;
+            self.check(&signature, hasher, path)?;
+        }
+
+        // The signed header vouches for the payload through its digest
+        match rpm_file.header.string(tag::PAYLOADDIGEST) {
+            Some(expected) => {
+                let algo = rpm::digest_algo(rpm_file.header.int(tag::PAYLOADDIGESTALGO).unwrap_or(8));
+                if checksum::supported(algo) {
+                    file.seek(SeekFrom::Start(rpm_file.payload_offset))?;
+                    let digest = checksum::reader_digest(&mut file, algo)?;
+                    if !digest.eq_ignore_ascii_case(expected) {
+                        anyhow::bail!("{:?} payload {} mismatch: expected {}, got {}", path, algo, expected, digest);
+                    }
+                } else if !payload_signed {
+                    anyhow::bail!("{:?} has a payload digest of unsupported type {}, its payload is not verified", path, algo);
+                }
+            }
+            None if !payload_signed => anyhow::bail!("{:?} has no payload digest, its payload is not covered by its signature", path),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn check(&self, signature: &Signature, hasher: Hasher, path: &Path) -> Result<()> {
+        let key = signature.find_key(&self.certificates).ok_or_else(|| MissingKey {
+            key_id: signature.issuer.map(|issuer| issuer.key_id() as u32).unwrap_or(0),
+            path: path.to_path_buf(),
+        })?;
+        signature
+            .verify(key, hasher)
+            .map_err(|e| anyhow::anyhow!("{:?} has a bad signature: {}", path, e))?;
+        key.check_usable(signature.created)
+            .map_err(|e| anyhow::anyhow!("{:?} is signed, but {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openpgp::tests::{certificate_bytes, packet, subpacket, TestKey, CREATED};
+    use sha2::{Digest, Sha256};
+
+    /// A header structure of string or binary `tags`
+    fn header_bytes(tags: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+        for &(tag, typ, value) in tags {
+            let count = if typ == 7 { value.len() as u32 } else { 1 };
+            for field in [tag, typ, data.len() as u32, count] {
+                index.extend(field.to_be_bytes());
+            }
+            data.extend(value);
+        }
+        let mut bytes = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        bytes.extend((tags.len() as u32).to_be_bytes());
+        bytes.extend((data.len() as u32).to_be_bytes());
+        bytes.extend(index);
+        bytes.extend(data);
+        bytes
+    }
+
+    /// An rpm whose main header, with `payload_digest` if given, is signed
+    /// by `key` at `signed`
+    fn signed_rpm(key: &TestKey, signed: u32, payload_digest: Option<&str>) -> Vec<u8> {
+        let payload = b"payload";
+        let mut tags: Vec<(u32, u32, &[u8])> = vec![(tag::NAME, 6, b"hello\0")];
+        let digest = payload_digest.map(|digest| format!("{}\0", digest));
+        if let Some(digest) = &digest {
+            tags.push((tag::PAYLOADDIGEST, 8, digest.as_bytes()));
+        }
+        let header = header_bytes(&tags);
+        let signature = packet(2, &key.sign(0x00, signed, &[], &[], &[&header]));
+
+        let mut bytes = vec![0; 96];
+        bytes[..4].copy_from_slice(&[0xed, 0xab, 0xee, 0xdb]);
+        bytes[4] = 3;
+        let signature_header = header_bytes(&[(tag::SIG_RSA, 7, &signature)]);
+        bytes.extend(&signature_header);
+        bytes.extend(vec![0; (8 - signature_header.len() % 8) % 8]);
+        bytes.extend(header);
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn keyring_with(dir: &Path, certificate: &[u8]) -> Keyring {
+        let mut keyring = Keyring::load(&dir.join("keys")).unwrap();
+        assert_eq!(keyring.import(certificate).unwrap().len(), 1);
+        keyring
+    }
+
+    fn verify(keyring: &Keyring, dir: &Path, rpm: &[u8]) -> Result<()> {
+        let path = dir.join("hello.rpm");
+        fs::write(&path, rpm).unwrap();
+        keyring.verify_rpm(&path)
+    }
+
+    #[test]
+    fn header_signatures_need_a_payload_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = TestKey::new(1, CREATED);
+        let keyring = keyring_with(dir.path(), &certificate_bytes(&key, &[]));
+        let digest = hex::encode(Sha256::digest(b"payload"));
+
+        assert!(verify(&keyring, dir.path(), &signed_rpm(&key, CREATED, Some(&digest))).is_ok());
+        let error = verify(&keyring, dir.path(), &signed_rpm(&key, CREATED, None)).unwrap_err();
+        assert!(error.to_string().contains("no payload digest"));
+        let error = verify(&keyring, dir.path(), &signed_rpm(&key, CREATED, Some(&"0".repeat(64)))).unwrap_err();
+        assert!(error.to_string().contains("mismatch"));
+
+        let mut tampered = signed_rpm(&key, CREATED, Some(&digest));
+        let in_header = tampered.len() - 7 - 2; // The payload digest ends the header
+        tampered[in_header] ^= 1;
+        assert!(verify(&keyring, dir.path(), &tampered).unwrap_err().to_string().contains("bad signature"));
+    }
+
+    #[test]
+    fn unknown_and_unusable_keys_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = TestKey::new(1, CREATED);
+        let digest = hex::encode(Sha256::digest(b"payload"));
+        let rpm = signed_rpm(&key, CREATED + 10, Some(&digest));
+
+        let other = keyring_with(&dir.path().join("other"), &certificate_bytes(&TestKey::new(2, CREATED), &[]));
+        let error = verify(&other, dir.path(), &rpm).unwrap_err();
+        let missing = error.downcast::<MissingKey>().unwrap();
+        assert_eq!(missing.key_id, u32::from_be_bytes(key.fingerprint[16..].try_into().unwrap()));
+
+        // Signed after the key expired
+        let expiring = certificate_bytes(&key, &[subpacket(9, &5u32.to_be_bytes())]);
+        let keyring = keyring_with(&dir.path().join("expiring"), &expiring);
+        assert!(verify(&keyring, dir.path(), &rpm).unwrap_err().to_string().contains("expired"));
+        assert!(verify(&keyring, dir.path(), &signed_rpm(&key, CREATED + 1, Some(&digest))).is_ok());
+    }
+
+    #[test]
+    fn keys_are_imported_once_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = TestKey::new(1, CREATED);
+        let mut keyring = keyring_with(dir.path(), &certificate_bytes(&key, &[]));
+        assert!(keyring.new_certificates(&certificate_bytes(&key, &[])).unwrap().is_empty());
+
+        let name = keyring.certificates[0].name();
+        assert_eq!(Keyring::load(&dir.path().join("keys")).unwrap().certificates.len(), 1);
+        assert_eq!(keyring.remove(&name).unwrap().name(), name);
+        assert!(Keyring::load(&dir.path().join("keys")).unwrap().certificates.is_empty());
+        assert!(keyring.remove(&name).is_err());
+    }
+}
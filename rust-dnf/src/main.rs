@@ -1,15 +1,45 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
+use std::sync::Arc;
 
+mod advisory;
+mod comps;
+mod compression;
 mod config;
 mod repo;
 mod package;
+mod primary;
+mod primary_sqlite;
+mod progress;
 mod repo_manager;
+mod sack_cache;
 mod db;
+mod download;
+mod module_db;
+mod modulemd;
+mod mirrors;
+mod checksum;
+mod deltarpm;
+mod zchunk;
+mod rpm;
+mod payload;
+mod openpgp;
+mod keyring;
+mod scriptlet;
+mod transaction;
+mod trigger;
+mod config_files;
+mod conflicts;
 
+use crate::advisory::AdvisoryFilter;
 use crate::config::Config;
-use crate::repo_manager::RepositoryManager;
+use crate::repo_manager::{is_rpm_argument, RepositoryManager};
+use crate::comps::PackageReqKind;
 use crate::db::PackageDatabase;
+use crate::keyring::Keyring;
+use crate::module_db::{parse_module_spec, ModuleDatabase};
+use crate::package::Package;
+use crate::transaction::{Install, Transaction};
 
 #[derive(Parser)]
 #[command(name = "rust-dnf")]
@@ -20,18 +50,49 @@ struct Cli {
     
     #[arg(short, long, global = true)]
     verbose: bool,
+    
+    /// Don't show download progress
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    
+    /// Answer yes to all questions, such as whether to import a key
+    #[arg(short = 'y', long, global = true)]
+    assumeyes: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Install packages
+    /// Install packages, by name or as paths or URLs of rpm files
     Install {
         packages: Vec<String>,
     },
+    /// Reinstall packages at their installed version
+    Reinstall {
+        packages: Vec<String>,
+    },
     /// Remove packages
     Remove {
         packages: Vec<String>,
     },
+    /// Upgrade installed packages
+    Upgrade {
+        packages: Vec<String>,
+        /// Only apply updates that fix security advisories
+        #[arg(long)]
+        security: bool,
+        /// Only apply updates that fix bugfix advisories
+        #[arg(long)]
+        bugfix: bool,
+        /// Only apply updates that fix the given advisories
+        #[arg(long = "advisory", value_delimiter = ',')]
+        advisories: Vec<String>,
+        /// Only apply updates that fix the given CVEs
+        #[arg(long = "cve", value_delimiter = ',')]
+        cves: Vec<String>,
+        /// Only apply security updates of the given severity
+        #[arg(long = "sec-severity", value_delimiter = ',')]
+        severities: Vec<String>,
+    },
     /// Update package database
     Update,
     /// Search for packages
@@ -40,10 +101,375 @@ enum Commands {
     },
     /// List installed packages
     List,
-    /// Show package information
+    /// Show package information, by name or for a path or URL of an rpm file
     Info {
         package: String,
     },
+    /// Manage comps groups
+    Group {
+        #[command(subcommand)]
+        command: GroupCommands,
+    },
+    /// Manage module streams
+    Module {
+        #[command(subcommand)]
+        command: ModuleCommands,
+    },
+    /// Manage the keys trusted to sign packages
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// List imported keys
+    List,
+    /// Import keys from files or URLs
+    Import {
+        keys: Vec<String>,
+    },
+    /// Remove keys by name (gpg-pubkey-...), key id or fingerprint
+    Remove {
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModuleCommands {
+    /// List module streams
+    List {
+        /// Only list streams of these modules
+        modules: Vec<String>,
+    },
+    /// Show module stream information
+    Info {
+        /// Module spec in the form name[:stream]
+        module: String,
+    },
+    /// Enable module streams
+    Enable {
+        /// Module specs in the form name[:stream]
+        modules: Vec<String>,
+    },
+    /// Disable modules, hiding all of their streams
+    Disable {
+        modules: Vec<String>,
+    },
+    /// Return modules to their default state
+    Reset {
+        modules: Vec<String>,
+    },
+    /// Enable module streams and install their profiles
+    Install {
+        /// Module specs in the form name[:stream][/profile]
+        modules: Vec<String>,
+    },
+    /// Remove installed module profiles
+    Remove {
+        /// Module specs in the form name[:stream][/profile]
+        modules: Vec<String>,
+    },
+    /// Switch a module to another stream, moving installed packages along
+    SwitchTo {
+        /// Module spec in the form name:stream[/profile]
+        module: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum GroupCommands {
+    /// List available and installed groups
+    List {
+        /// Also show groups that are not user visible
+        #[arg(long)]
+        hidden: bool,
+    },
+    /// Show group, environment or category information
+    Info {
+        group: String,
+    },
+    /// Install groups or environments
+    Install {
+        groups: Vec<String>,
+        /// Also install optional packages
+        #[arg(long)]
+        with_optional: bool,
+    },
+    /// Remove installed groups or environments and the packages they pulled in
+    Remove {
+        groups: Vec<String>,
+    },
+    /// Upgrade installed groups, adding packages new to them
+    Upgrade {
+        groups: Vec<String>,
+    },
+}
+
+/// Fetch the rpms of packages from their repositories, all at once, unpack
+/// them under the installation root and record them as installed
+fn install_packages(repo_manager: &RepositoryManager, pkg_db: &mut PackageDatabase, pkgs: &[&Package]) -> Result<()> {
+    let paths = repo_manager.download_packages(pkgs, pkg_db)?;
+    let mut keyring = Keyring::load(&repo_manager.config.database_dir.join("keys"))?;
+    repo_manager.verify_signatures(pkgs, &paths, &mut keyring)?;
+    let mut installs = Vec::with_capacity(pkgs.len());
+    for (pkg, rpm) in pkgs.iter().zip(paths) {
+        let package = match &rpm {
+            Some(path) => {
+                log::debug!("Installing {} from {:?}", pkg.nevra(), path);
+                header_package(pkg, path)?
+            }
+            None => (*pkg).clone(),
+        };
+        installs.push(Install { package, rpm });
+    }
+    Transaction::new(&repo_manager.config, pkg_db).run(installs, &[])
+}
+
+/// Erase installed packages by their database keys
+fn remove_packages(config: &Config, pkg_db: &mut PackageDatabase, keys: &[String]) -> Result<()> {
+    Transaction::new(config, pkg_db).run(Vec::new(), keys)
+}
+
+/// Forget an installed group and remove the packages only it was keeping
+fn remove_group(config: &Config, pkg_db: &mut PackageDatabase, id: &str) -> Result<()> {
+    let orphaned = pkg_db.remove_group(id)?;
+    let keys: Vec<String> = orphaned.into_iter().filter(|key| pkg_db.is_installed(key)).collect();
+    remove_packages(config, pkg_db, &keys)?;
+    for key in keys {
+        println!("  Package {} removed successfully!", key);
+    }
+    println!("Group {} removed successfully!", id);
+    Ok(())
+}
+
+/// The package as the header of its downloaded rpm describes it, which
+/// must be the build the repository metadata promised
+fn header_package(pkg: &Package, path: &std::path::Path) -> Result<Package> {
+    let mut header_pkg = rpm::read(path)?.to_package()?;
+    if header_pkg.nevra() != pkg.nevra() {
+        anyhow::bail!("{:?} holds {}, not {}", path, header_pkg.nevra(), pkg.nevra());
+    }
+    // Where it came from is only known from the metadata
+    header_pkg.location = pkg.location.clone();
+    header_pkg.checksum = pkg.checksum.clone();
+    Ok(header_pkg)
+}
+
+/// Install the packages of a group, or of every group in an environment,
+/// and record each group and environment so that it can be removed later.
+fn install_group(
+    repo_manager: &RepositoryManager,
+    pkg_db: &mut PackageDatabase,
+    id: &str,
+    with_optional: bool,
+) -> Result<()> {
+    if let Some(env) = repo_manager.find_environment(id) {
+        println!("Installing environment: {}", env.name);
+        // Like packages of a group, only groups the environment installs
+        // belong to it
+        let mut groups = pkg_db.get_installed_environment(&env.id).map(|e| e.groups.clone()).unwrap_or_default();
+        let defaults = env.options.iter().filter(|o| o.default || with_optional).map(|o| &o.group);
+        for group_id in env.groups.iter().chain(defaults) {
+            let group_id = repo_manager.find_group(group_id).map(|g| g.id.as_str()).unwrap_or(group_id);
+            let installed = pkg_db.get_installed_group(group_id).is_some();
+            install_group(repo_manager, pkg_db, group_id, with_optional)?;
+            if !installed && !groups.iter().any(|g| g == group_id) {
+                groups.push(group_id.to_string());
+            }
+        }
+        return pkg_db.install_environment(&env.id, &env.name, groups);
+    }
+    
+    let group = match repo_manager.find_group(id) {
+        Some(group) => group,
+        None => anyhow::bail!("Group {} not found in repositories", id),
+    };
+    let previous = pkg_db.get_installed_group(&group.id).map(|g| g.packages.clone()).unwrap_or_default();
+    
+    println!("Installing group: {}", group.name);
+    let names = group.install_set(with_optional, |name| {
+        pkg_db.list_installed().iter().any(|p| p.package.name.name == name)
+    });
+    
+    // Only packages the group actually installs belong to it, so removing
+    // the group never takes away packages that were installed on their own
+    let mut keys = previous;
+    let mut to_install = Vec::new();
+    for name in names {
+        match repo_manager.find_package(name) {
+            Some(pkg) => {
+                let key = format!("{}.{}", pkg.name.name, pkg.name.arch);
+                if pkg_db.is_installed(&key) {
+                    continue;
+                }
+                to_install.push(pkg);
+                keys.push(key);
+            }
+            None => eprintln!("  Package {} from group {} not found in repositories", name, group.id),
+        }
+    }
+    
+    install_packages(repo_manager, pkg_db, &to_install)?;
+    for pkg in to_install {
+        println!("  Package {} installed successfully!", pkg.name.name);
+    }
+    pkg_db.install_group(&group.id, &group.name, keys)
+}
+
+/// Resolve the stream a module command acts on: the one named in the spec,
+/// otherwise the enabled stream, otherwise the default stream.
+fn resolve_stream(
+    repo_manager: &RepositoryManager,
+    module_db: &ModuleDatabase,
+    name: &str,
+    stream: Option<&str>,
+) -> Result<String> {
+    let stream = match stream {
+        Some(stream) => stream,
+        None => match module_db.enabled_stream(name).or_else(|| repo_manager.default_stream(name)) {
+            Some(stream) => stream,
+            None => anyhow::bail!("Module {} has no default stream, use {}:<stream>", name, name),
+        },
+    };
+    
+    if !repo_manager.module_streams().iter().any(|s| s.name == name && s.stream == stream) {
+        anyhow::bail!("No stream {} found for module {}", stream, name);
+    }
+    Ok(stream.to_string())
+}
+
+/// Package names listed by the given profiles of a stream, over all contexts
+fn module_profile_rpms(repo_manager: &RepositoryManager, name: &str, stream: &str, profiles: &[String]) -> Vec<String> {
+    let mut rpms: Vec<String> = repo_manager
+        .module_streams()
+        .into_iter()
+        .filter(|s| s.name == name && s.stream == stream)
+        .flat_map(|s| s.profiles.iter())
+        .filter(|(profile, _)| profiles.contains(profile))
+        .flat_map(|(_, profile)| profile.rpms.iter().cloned())
+        .collect();
+    rpms.sort();
+    rpms.dedup();
+    rpms
+}
+
+/// Replace rpm paths and URLs among `args` with the names of the packages
+/// they hold, which are then found in the `@commandline` repository
+fn resolve_rpm_arguments(repo_manager: &mut RepositoryManager, args: Vec<String>) -> Result<Vec<String>> {
+    let rpms: Vec<String> = args.iter().filter(|arg| is_rpm_argument(arg)).cloned().collect();
+    if rpms.is_empty() {
+        return Ok(args);
+    }
+    let mut names = repo_manager.add_commandline_rpms(&rpms)?.into_iter();
+    Ok(args
+        .into_iter()
+        .map(|arg| if is_rpm_argument(&arg) { names.next().unwrap_or(arg) } else { arg })
+        .collect())
+}
+
+fn installed_keys(pkg_db: &PackageDatabase, name: &str) -> Vec<String> {
+    pkg_db
+        .installed_packages
+        .iter()
+        .filter(|(_, installed)| installed.package.name.name == name)
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Install profiles of the enabled stream of a module. Without an explicit
+/// profile the stream's default profiles are installed.
+fn install_module_profiles(
+    repo_manager: &RepositoryManager,
+    module_db: &mut ModuleDatabase,
+    pkg_db: &mut PackageDatabase,
+    name: &str,
+    stream: &str,
+    profile: Option<&str>,
+) -> Result<()> {
+    let profiles: Vec<String> = match profile {
+        Some(profile) => vec![profile.to_string()],
+        None => repo_manager.default_profiles(name, stream).into_iter().map(String::from).collect(),
+    };
+    if profiles.is_empty() {
+        anyhow::bail!("No default profiles for module {}:{}, use {}:{}/<profile>", name, stream, name, stream);
+    }
+    
+    let rpms = module_profile_rpms(repo_manager, name, stream, &profiles);
+    if rpms.is_empty() {
+        anyhow::bail!("Profiles {:?} of module {}:{} not found", profiles, name, stream);
+    }
+    
+    println!("Installing module {}:{}/{}", name, stream, profiles.join(","));
+    let mut to_install = Vec::new();
+    for rpm in rpms {
+        if !installed_keys(pkg_db, &rpm).is_empty() {
+            continue;
+        }
+        match repo_manager.find_package(&rpm) {
+            Some(pkg) => to_install.push(pkg),
+            None => eprintln!("  Package {} from module {}:{} not found in repositories", rpm, name, stream),
+        }
+    }
+    
+    install_packages(repo_manager, pkg_db, &to_install)?;
+    for pkg in to_install {
+        println!("  Package {} installed successfully!", pkg.name.name);
+    }
+    
+    module_db.add_profiles(name, &profiles)
+}
+
+fn module_stream_label(repo_manager: &RepositoryManager, module_db: &ModuleDatabase, stream: &modulemd::ModuleStream) -> String {
+    let mut label = stream.stream.clone();
+    if repo_manager.default_stream(&stream.name) == Some(stream.stream.as_str()) {
+        label.push_str(" [d]");
+    }
+    if module_db.enabled_stream(&stream.name) == Some(stream.stream.as_str()) {
+        label.push_str(" [e]");
+    }
+    if module_db.is_disabled(&stream.name) {
+        label.push_str(" [x]");
+    }
+    label
+}
+
+fn print_module_profiles(repo_manager: &RepositoryManager, module_db: &ModuleDatabase, stream: &modulemd::ModuleStream) -> String {
+    let defaults = repo_manager.default_profiles(&stream.name, &stream.stream);
+    let installed: &[String] = match module_db.get(&stream.name) {
+        Some(state) if state.stream.as_deref() == Some(stream.stream.as_str()) => &state.profiles,
+        _ => &[],
+    };
+    stream
+        .profiles
+        .keys()
+        .map(|name| {
+            let mut label = name.clone();
+            if defaults.contains(&name.as_str()) {
+                label.push_str(" [d]");
+            }
+            if installed.contains(name) {
+                label.push_str(" [i]");
+            }
+            label
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn print_package_reqs(group: &comps::Group, kind: PackageReqKind, title: &str) {
+    let reqs: Vec<_> = group.packages.iter().filter(|req| req.kind == kind).collect();
+    if reqs.is_empty() {
+        return;
+    }
+    println!(" {}:", title);
+    for req in reqs {
+        match &req.requires {
+            Some(requires) => println!("   {} (if {})", req.name, requires),
+            None => println!("   {}", req.name),
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -58,40 +484,146 @@ fn main() -> Result<()> {
     env_logger::init();
     
     // Load configuration
-    let config = Config::load()?;
+    let mut config = Config::load()?;
+    config.assumeyes |= cli.assumeyes;
     
     // Initialize repository manager and package database
-    let mut repo_manager = RepositoryManager::new(config.clone());
+    let mut repo_manager = RepositoryManager::new(config.clone())?;
+    if cli.quiet {
+        repo_manager.set_progress(Arc::new(progress::Silent));
+    }
     let mut pkg_db = PackageDatabase::new(config.database_dir.join("packages.json"));
     
+    let mut module_db = ModuleDatabase::new(config.database_dir.join("modules.json"));
+    
     // Load existing data
     repo_manager.load_repositories()?;
     pkg_db.load()?;
+    module_db.load()?;
+    repo_manager.select_module_streams(&module_db);
     
     match cli.command {
         Commands::Install { packages } => {
             println!("Installing packages: {:?}", packages);
+            let packages = resolve_rpm_arguments(&mut repo_manager, packages)?;
+            let mut to_install = Vec::new();
             for pkg_name in packages {
+                if let Some(group_id) = pkg_name.strip_prefix('@') {
+                    if let Err(e) = install_group(&repo_manager, &mut pkg_db, group_id, false) {
+                        eprintln!("{}", e);
+                    }
+                    continue;
+                }
                 if let Some(pkg) = repo_manager.find_package(&pkg_name) {
                     println!("Found package: {} {}", pkg.name.name, pkg.version.version);
-                    // TODO: Implement actual installation
-                    pkg_db.install_package(pkg.clone())?;
-                    println!("Package {} installed successfully!", pkg.name.name);
+                    to_install.push(pkg);
                 } else {
                     eprintln!("Package {} not found in repositories", pkg_name);
                 }
             }
+            
+            install_packages(&repo_manager, &mut pkg_db, &to_install)?;
+            for pkg in to_install {
+                println!("Package {} installed successfully!", pkg.name.name);
+            }
+        }
+        Commands::Reinstall { packages } => {
+            let packages = resolve_rpm_arguments(&mut repo_manager, packages)?;
+            let mut targets = Vec::new();
+            for name in packages {
+                let keys = installed_keys(&pkg_db, &name);
+                if keys.is_empty() {
+                    eprintln!("Package {} is not installed", name);
+                    continue;
+                }
+                for key in keys {
+                    let installed = &pkg_db.installed_packages[&key].package;
+                    match repo_manager.find_package(&key) {
+                        Some(pkg) if pkg.version == installed.version && pkg.name.arch == installed.name.arch => {
+                            println!("Reinstalling {}", pkg.nevra());
+                            targets.push(pkg);
+                        }
+                        _ => eprintln!("Installed package {} is not available", installed.nevra()),
+                    }
+                }
+            }
+            if !targets.is_empty() {
+                install_packages(&repo_manager, &mut pkg_db, &targets)?;
+                println!("Reinstalled {} packages", targets.len());
+            }
         }
         Commands::Remove { packages } => {
             println!("Removing packages: {:?}", packages);
+            let mut keys = Vec::new();
             for pkg_name in packages {
                 if pkg_db.is_installed(&pkg_name) {
-                    pkg_db.remove_package(&pkg_name)?;
-                    println!("Package {} removed successfully!", pkg_name);
+                    keys.push(pkg_name);
                 } else {
                     eprintln!("Package {} is not installed", pkg_name);
                 }
             }
+            remove_packages(&config, &mut pkg_db, &keys)?;
+            for key in keys {
+                println!("Package {} removed successfully!", key);
+            }
+        }
+        Commands::Upgrade { packages, security, bugfix, advisories, cves, severities } => {
+            let filter = AdvisoryFilter {
+                security,
+                bugfix,
+                advisories,
+                cves,
+                severities,
+            };
+            
+            let packages = resolve_rpm_arguments(&mut repo_manager, packages)?;
+            for name in &packages {
+                if repo_manager.commandline_package(name).is_some() && installed_keys(&pkg_db, name).is_empty() {
+                    eprintln!("Package {} is not installed, use install for it", name);
+                }
+            }
+            let installed: Vec<_> = pkg_db
+                .list_installed()
+                .into_iter()
+                .map(|pkg| pkg.package.clone())
+                .filter(|pkg| packages.is_empty() || packages.contains(&pkg.name.name))
+                .collect();
+            
+            let mut targets = Vec::new();
+            for pkg in installed {
+                // An rpm given on the command line is the build to upgrade to
+                let upgrade = match repo_manager.commandline_package(&format!("{}.{}", pkg.name.name, pkg.name.arch)) {
+                    Some(target) if target.version > pkg.version => Some((target, Vec::new())),
+                    Some(target) => {
+                        println!("{} is not newer than installed {}", target.nevra(), pkg.nevra());
+                        None
+                    }
+                    None => repo_manager.find_upgrade(&pkg, &filter),
+                };
+                if let Some((target, fixes)) = upgrade {
+                    println!("Upgrading {} {}-{} -> {}-{}",
+                        pkg.name.name,
+                        pkg.version.version,
+                        pkg.version.release,
+                        target.version.version,
+                        target.version.release
+                    );
+                    for advisory in fixes {
+                        match &advisory.severity {
+                            Some(severity) => println!("    fixes {} ({})", advisory.id, severity),
+                            None => println!("    fixes {}", advisory.id),
+                        }
+                    }
+                    targets.push(target);
+                }
+            }
+            
+            if targets.is_empty() {
+                println!("No packages marked for upgrade");
+            } else {
+                install_packages(&repo_manager, &mut pkg_db, &targets)?;
+                println!("Upgraded {} packages", targets.len());
+            }
         }
         Commands::Update => {
             println!("Updating package database");
@@ -127,6 +659,7 @@ fn main() -> Result<()> {
         }
         Commands::Info { package } => {
             println!("Showing info for: {}", package);
+            let package = resolve_rpm_arguments(&mut repo_manager, vec![package])?.remove(0);
             if let Some(pkg) = repo_manager.find_package(&package) {
                 println!("Package: {}", pkg.name.name);
                 println!("Version: {}-{}", pkg.version.version, pkg.version.release);
@@ -137,6 +670,389 @@ fn main() -> Result<()> {
                 eprintln!("Package {} not found", package);
             }
         }
+        Commands::Group { command } => match command {
+            GroupCommands::List { hidden } => {
+                let installed: Vec<_> = repo_manager
+                    .groups()
+                    .into_iter()
+                    .filter(|g| pkg_db.get_installed_group(&g.id).is_some())
+                    .collect();
+                let available: Vec<_> = repo_manager
+                    .groups()
+                    .into_iter()
+                    .filter(|g| pkg_db.get_installed_group(&g.id).is_none())
+                    .filter(|g| hidden || g.uservisible)
+                    .collect();
+                let environments = repo_manager.environments();
+                
+                if installed.is_empty() && available.is_empty() && environments.is_empty() {
+                    println!("No groups available");
+                }
+                let (installed_environments, available_environments): (Vec<_>, Vec<_>) = environments
+                    .into_iter()
+                    .partition(|env| pkg_db.get_installed_environment(&env.id).is_some());
+                if !installed_environments.is_empty() {
+                    println!("Installed Environment Groups:");
+                    for env in installed_environments {
+                        println!("  {}", env.name);
+                    }
+                }
+                if !available_environments.is_empty() {
+                    println!("Available Environment Groups:");
+                    for env in available_environments {
+                        println!("  {}", env.name);
+                    }
+                }
+                if !installed.is_empty() {
+                    println!("Installed Groups:");
+                    for group in installed {
+                        println!("  {}", group.name);
+                    }
+                }
+                if !available.is_empty() {
+                    println!("Available Groups:");
+                    for group in available {
+                        println!("  {}", group.name);
+                    }
+                }
+            }
+            GroupCommands::Info { group } => {
+                if let Some(g) = repo_manager.find_group(&group) {
+                    println!("Group: {}", g.name);
+                    println!(" Group-Id: {}", g.id);
+                    println!(" Description: {}", g.description);
+                    print_package_reqs(g, PackageReqKind::Mandatory, "Mandatory Packages");
+                    print_package_reqs(g, PackageReqKind::Default, "Default Packages");
+                    print_package_reqs(g, PackageReqKind::Optional, "Optional Packages");
+                    print_package_reqs(g, PackageReqKind::Conditional, "Conditional Packages");
+                } else if let Some(env) = repo_manager.find_environment(&group) {
+                    println!("Environment Group: {}", env.name);
+                    println!(" Environment-Id: {}", env.id);
+                    println!(" Description: {}", env.description);
+                    println!(" Mandatory Groups:");
+                    for id in &env.groups {
+                        println!("   {}", id);
+                    }
+                    if !env.options.is_empty() {
+                        println!(" Optional Groups:");
+                        for option in &env.options {
+                            let marker = if option.default { " (default)" } else { "" };
+                            println!("   {}{}", option.group, marker);
+                        }
+                    }
+                } else if let Some(category) = repo_manager.find_category(&group) {
+                    println!("Category: {}", category.name);
+                    println!(" Category-Id: {}", category.id);
+                    println!(" Description: {}", category.description);
+                    println!(" Groups:");
+                    for id in &category.groups {
+                        println!("   {}", id);
+                    }
+                } else {
+                    eprintln!("Group {} not found", group);
+                }
+            }
+            GroupCommands::Install { groups, with_optional } => {
+                for group in groups {
+                    if let Err(e) = install_group(&repo_manager, &mut pkg_db, &group, with_optional) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+            GroupCommands::Remove { groups } => {
+                for group in groups {
+                    let env_id = repo_manager.find_environment(&group).map(|e| e.id.clone()).unwrap_or_else(|| group.clone());
+                    if pkg_db.get_installed_environment(&env_id).is_some() {
+                        match pkg_db.remove_environment(&env_id) {
+                            Ok(orphaned) => {
+                                for id in orphaned {
+                                    if pkg_db.get_installed_group(&id).is_some() {
+                                        remove_group(&config, &mut pkg_db, &id)?;
+                                    }
+                                }
+                                println!("Environment {} removed successfully!", env_id);
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        }
+                        continue;
+                    }
+                    let id = repo_manager.find_group(&group).map(|g| g.id.clone()).unwrap_or(group);
+                    if let Err(e) = remove_group(&config, &mut pkg_db, &id) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+            GroupCommands::Upgrade { groups } => {
+                let filter = AdvisoryFilter::default();
+                for group in groups {
+                    let id = repo_manager.find_group(&group).map(|g| g.id.clone()).unwrap_or(group);
+                    let keys = match pkg_db.get_installed_group(&id) {
+                        Some(installed) => installed.packages.clone(),
+                        None => {
+                            eprintln!("Group {} is not installed", id);
+                            continue;
+                        }
+                    };
+                    
+                    println!("Upgrading group: {}", id);
+                    let mut targets = Vec::new();
+                    for key in keys {
+                        let installed = match pkg_db.get_installed(&key) {
+                            Some(installed) => installed.package.clone(),
+                            None => continue,
+                        };
+                        if let Some((target, _)) = repo_manager.find_upgrade(&installed, &filter) {
+                            println!("  Upgrading {} {}-{} -> {}-{}",
+                                installed.name.name,
+                                installed.version.version,
+                                installed.version.release,
+                                target.version.version,
+                                target.version.release
+                            );
+                            targets.push(target);
+                        }
+                    }
+                    install_packages(&repo_manager, &mut pkg_db, &targets)?;
+                    
+                    // Pick up packages added to the group since it was installed
+                    if let Err(e) = install_group(&repo_manager, &mut pkg_db, &id, false) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+        },
+        Commands::Module { command } => match command {
+            ModuleCommands::List { modules } => {
+                // Contexts of the same stream are listed once, using the newest build
+                let mut streams: Vec<&modulemd::ModuleStream> = Vec::new();
+                for stream in repo_manager.module_streams() {
+                    if !modules.is_empty() && !modules.contains(&stream.name) {
+                        continue;
+                    }
+                    match streams.last_mut() {
+                        Some(last) if last.name == stream.name && last.stream == stream.stream => *last = stream,
+                        _ => streams.push(stream),
+                    }
+                }
+                
+                if streams.is_empty() {
+                    println!("No matching modules to list");
+                } else {
+                    println!("{:<24} {:<16} {:<32} Summary", "Name", "Stream", "Profiles");
+                    for stream in streams {
+                        println!("{:<24} {:<16} {:<32} {}",
+                            stream.name,
+                            module_stream_label(&repo_manager, &module_db, stream),
+                            print_module_profiles(&repo_manager, &module_db, stream),
+                            stream.summary
+                        );
+                    }
+                    println!();
+                    println!("Hint: [d]efault, [e]nabled, [x]disabled, [i]nstalled");
+                }
+            }
+            ModuleCommands::Info { module } => {
+                let (name, stream_name) = match module.split_once(':') {
+                    Some((name, stream)) => (name, Some(stream)),
+                    None => (module.as_str(), None),
+                };
+                let matches: Vec<_> = repo_manager
+                    .module_streams()
+                    .into_iter()
+                    .filter(|s| s.name == name && stream_name.map(|n| s.stream == n).unwrap_or(true))
+                    .collect();
+                
+                if matches.is_empty() {
+                    eprintln!("Module {} not found", module);
+                }
+                for stream in matches {
+                    println!("Name             : {}", stream.name);
+                    println!("Stream           : {}", module_stream_label(&repo_manager, &module_db, stream));
+                    println!("Version          : {}", stream.version);
+                    println!("Context          : {}", stream.context);
+                    println!("Architecture     : {}", stream.arch);
+                    println!("Profiles         : {}", print_module_profiles(&repo_manager, &module_db, stream));
+                    for (profile_name, profile) in &stream.profiles {
+                        if profile.description.is_empty() {
+                            println!("  {:<15}: {}", profile_name, profile.rpms.join(", "));
+                        } else {
+                            println!("  {:<15}: {} ({})", profile_name, profile.rpms.join(", "), profile.description);
+                        }
+                    }
+                    println!("Summary          : {}", stream.summary);
+                    println!("Description      : {}", stream.description.trim());
+                    println!("Artifacts        : {}", stream.artifacts.join("\n                 : "));
+                    println!();
+                }
+            }
+            ModuleCommands::Enable { modules } => {
+                for spec in modules {
+                    let (name, stream, _) = parse_module_spec(&spec);
+                    match resolve_stream(&repo_manager, &module_db, name, stream)
+                        .and_then(|stream| module_db.enable(name, &stream).map(|_| stream))
+                    {
+                        Ok(stream) => println!("Module {}:{} enabled", name, stream),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+            ModuleCommands::Disable { modules } => {
+                for spec in modules {
+                    let (name, _, _) = parse_module_spec(&spec);
+                    module_db.disable(name)?;
+                    println!("Module {} disabled", name);
+                }
+            }
+            ModuleCommands::Reset { modules } => {
+                for spec in modules {
+                    let (name, _, _) = parse_module_spec(&spec);
+                    module_db.reset(name)?;
+                    println!("Module {} reset", name);
+                }
+            }
+            ModuleCommands::Install { modules } => {
+                for spec in modules {
+                    let (name, stream, profile) = parse_module_spec(&spec);
+                    let result = resolve_stream(&repo_manager, &module_db, name, stream).and_then(|stream| {
+                        module_db.enable(name, &stream)?;
+                        repo_manager.select_module_streams(&module_db);
+                        install_module_profiles(&repo_manager, &mut module_db, &mut pkg_db, name, &stream, profile)
+                    });
+                    if let Err(e) = result {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+            ModuleCommands::Remove { modules } => {
+                for spec in modules {
+                    let (name, _, profile) = parse_module_spec(&spec);
+                    let state = match module_db.get(name) {
+                        Some(state) if !state.profiles.is_empty() => state.clone(),
+                        _ => {
+                            eprintln!("Module {} has no installed profiles", name);
+                            continue;
+                        }
+                    };
+                    let stream = state.stream.clone().unwrap_or_default();
+                    let removed: Vec<String> = match profile {
+                        Some(profile) => vec![profile.to_string()],
+                        None => state.profiles.clone(),
+                    };
+                    let kept: Vec<String> = state.profiles.iter().filter(|p| !removed.contains(p)).cloned().collect();
+                    
+                    // Packages still listed by a remaining profile stay installed
+                    let kept_rpms = module_profile_rpms(&repo_manager, name, &stream, &kept);
+                    println!("Removing module {}:{}/{}", name, stream, removed.join(","));
+                    let keys: Vec<String> = module_profile_rpms(&repo_manager, name, &stream, &removed)
+                        .into_iter()
+                        .filter(|rpm| !kept_rpms.contains(rpm))
+                        .flat_map(|rpm| installed_keys(&pkg_db, &rpm))
+                        .collect();
+                    remove_packages(&config, &mut pkg_db, &keys)?;
+                    for key in keys {
+                        println!("  Package {} removed successfully!", key);
+                    }
+                    module_db.remove_profiles(name, &removed)?;
+                }
+            }
+            ModuleCommands::SwitchTo { module } => {
+                let (name, stream, profile) = parse_module_spec(&module);
+                let new_stream = resolve_stream(&repo_manager, &module_db, name, stream)?;
+                let old_stream = module_db
+                    .enabled_stream(name)
+                    .or_else(|| repo_manager.default_stream(name))
+                    .map(String::from);
+                let profiles = module_db.get(name).map(|state| state.profiles.clone()).unwrap_or_default();
+                
+                // Names of every package the old stream shipped
+                let mut old_names: Vec<String> = repo_manager
+                    .module_streams()
+                    .into_iter()
+                    .filter(|s| Some(&s.stream) == old_stream.as_ref() && s.name == name)
+                    .flat_map(|s| s.artifacts.iter())
+                    .filter_map(|artifact| package::parse_nevra(artifact).ok())
+                    .map(|(pkg_name, _)| pkg_name.name)
+                    .collect();
+                old_names.sort();
+                old_names.dedup();
+                
+                module_db.switch_stream(name, &new_stream)?;
+                repo_manager.select_module_streams(&module_db);
+                println!("Switching module {} to stream {}", name, new_stream);
+                
+                // Installed packages follow the module to the new stream, which
+                // may well be an older version
+                let mut targets = Vec::new();
+                for old_name in old_names {
+                    for key in installed_keys(&pkg_db, &old_name) {
+                        let installed = pkg_db.get_installed(&key).map(|p| p.package.clone());
+                        let (installed, target) = match (installed, repo_manager.find_package(&old_name)) {
+                            (Some(installed), Some(target)) => (installed, target),
+                            _ => continue,
+                        };
+                        if target.version != installed.version {
+                            println!("  Switching {} {}-{} -> {}-{}",
+                                old_name,
+                                installed.version.version,
+                                installed.version.release,
+                                target.version.version,
+                                target.version.release
+                            );
+                            targets.push(target);
+                        }
+                    }
+                }
+                install_packages(&repo_manager, &mut pkg_db, &targets)?;
+                
+                if let Some(profile) = profile {
+                    install_module_profiles(&repo_manager, &mut module_db, &mut pkg_db, name, &new_stream, Some(profile))?;
+                } else {
+                    for profile in profiles {
+                        if let Err(e) = install_module_profiles(&repo_manager, &mut module_db, &mut pkg_db, name, &new_stream, Some(&profile)) {
+                            eprintln!("{}", e);
+                        }
+                    }
+                }
+            }
+        },
+        Commands::Key { command } => {
+            let mut keyring = Keyring::load(&config.database_dir.join("keys"))?;
+            match command {
+                KeyCommands::List => {
+                    if keyring.certificates.is_empty() {
+                        println!("No keys imported");
+                    }
+                    for certificate in &keyring.certificates {
+                        println!("{}  {}", certificate.name(), certificate.user_id());
+                        println!("    {}", certificate.primary.fingerprint_hex());
+                    }
+                }
+                KeyCommands::Import { keys } => {
+                    for source in keys {
+                        let imported = repo_manager
+                            .read_key(&source, repo_manager::COMMANDLINE_REPO)
+                            .and_then(|data| keyring.import(&data));
+                        match imported {
+                            Ok(certificates) if certificates.is_empty() => println!("Keys from {} are already imported", source),
+                            Ok(certificates) => {
+                                for certificate in certificates {
+                                    println!("Imported {}  {}", certificate.name(), certificate.user_id());
+                                }
+                            }
+                            Err(e) => eprintln!("Cannot import {}: {}", source, e),
+                        }
+                    }
+                }
+                KeyCommands::Remove { keys } => {
+                    for id in keys {
+                        match keyring.remove(&id) {
+                            Ok(certificate) => println!("Removed {}  {}", certificate.name(), certificate.user_id()),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    }
+                }
+            }
+        }
     }
     
     Ok(())
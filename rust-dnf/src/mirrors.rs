@@ -0,0 +1,268 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+
+/// A mirror's base URL, i.e. the directory containing `repodata/`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    pub url: String,
+    pub preference: u32, // Higher is better, as in metalink
+}
+
+/// What a metalink says about one repository's repomd.xml
+#[derive(Debug, Clone, Default)]
+pub struct Metalink {
+    pub mirrors: Vec<Mirror>,
+    pub repomd_size: Option<u64>,
+    pub repomd_hashes: Vec<(String, String)>, // (hash type, hex digest)
+}
+
+impl Metalink {
+    /// Check a downloaded repomd.xml against the size and the strongest
+    /// hash the metalink lists. A mismatch usually means a stale mirror.
+    pub fn verify_repomd(&self, path: &Path) -> Result<()> {
+        if let Some(size) = self.repomd_size {
+            let actual = fs::metadata(path)?.len();
+            if actual != size {
+                anyhow::bail!("repomd.xml is {} bytes, metalink expects {}", actual, size);
+            }
+        }
+
+        let hash = ["sha512", "sha256"]
+            .iter()
+            .find_map(|typ| self.repomd_hashes.iter().find(|(t, _)| t == typ));
+        let (typ, expected) = match hash {
+            Some(hash) => hash,
+            None => {
+                log::debug!("Metalink has no usable repomd.xml hash");
+                return Ok(());
+            }
+        };
+
+        let data = fs::read(path)?;
+        let actual = match typ.as_str() {
+            "sha512" => hex::encode(Sha512::digest(&data)),
+            _ => hex::encode(Sha256::digest(&data)),
+        };
+        if !actual.eq_ignore_ascii_case(expected) {
+            anyhow::bail!("repomd.xml {} mismatch: expected {}, got {}", typ, expected, actual);
+        }
+        Ok(())
+    }
+}
+
+/// Sort mirrors best first. The sort is stable, so mirrors of equal
+/// preference keep the order the list gave them in.
+pub fn order_by_preference(mirrors: &mut [Mirror]) {
+    mirrors.sort_by_key(|mirror| std::cmp::Reverse(mirror.preference));
+}
+
+/// Parse a plain mirrorlist: one base URL per line, best first
+pub fn parse_mirrorlist(content: &str) -> Vec<Mirror> {
+    let urls: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let count = urls.len() as u32;
+
+    urls.into_iter()
+        .enumerate()
+        .map(|(i, url)| Mirror {
+            url: url.trim_end_matches('/').to_string(),
+            preference: count - i as u32,
+        })
+        .collect()
+}
+
+/// Parse the metalink document a mirror manager returns for a repository's
+/// repomd.xml. Only http(s) mirrors are kept, ordered by preference.
+pub fn parse_metalink<R: BufRead>(source: R) -> Result<Metalink> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut metalink = Metalink::default();
+    let mut in_repomd = false;
+    let mut in_alternate = false; // Older repomd.xml versions, not checked against
+    let mut hash_type: Option<String> = None;
+    let mut mirror: Option<Mirror> = None;
+    let mut in_size = false;
+    let mut text = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                text.clear();
+                match e.local_name().as_ref() {
+                    b"file" => {
+                        in_repomd = match e.try_get_attribute("name")? {
+                            Some(name) => name.unescape_value()? == "repomd.xml",
+                            None => false,
+                        };
+                    }
+                    b"alternate" => in_alternate = true,
+                    b"size" if in_repomd && !in_alternate => in_size = true,
+                    b"hash" if in_repomd && !in_alternate => {
+                        hash_type = match e.try_get_attribute("type")? {
+                            Some(typ) => Some(typ.unescape_value()?.to_lowercase()),
+                            None => None,
+                        };
+                    }
+                    b"url" if in_repomd => {
+                        let protocol = match e.try_get_attribute("protocol")? {
+                            Some(protocol) => protocol.unescape_value()?.into_owned(),
+                            None => String::new(),
+                        };
+                        let preference = match e.try_get_attribute("preference")? {
+                            Some(pref) => pref.unescape_value()?.parse().unwrap_or(0),
+                            None => 0,
+                        };
+                        mirror = if protocol.is_empty() || protocol == "http" || protocol == "https" {
+                            Some(Mirror {
+                                url: String::new(),
+                                preference,
+                            })
+                        } else {
+                            None
+                        };
+                    }
+                    _ => {}
+                }
+            }
+
+            Event::Text(e) => text.push_str(&e.unescape()?),
+
+            Event::End(e) => {
+                match e.local_name().as_ref() {
+                    b"file" => in_repomd = false,
+                    b"alternate" => in_alternate = false,
+                    b"size" if in_size => {
+                        metalink.repomd_size = text.trim().parse().ok();
+                        in_size = false;
+                    }
+                    b"hash" => {
+                        if let Some(typ) = hash_type.take() {
+                            metalink.repomd_hashes.push((typ, text.trim().to_string()));
+                        }
+                    }
+                    b"url" => {
+                        if let Some(mut m) = mirror.take() {
+                            // Mirror URLs point at repomd.xml itself
+                            let url = text.trim();
+                            let base = url.strip_suffix("repodata/repomd.xml").unwrap_or(url);
+                            m.url = base.trim_end_matches('/').to_string();
+                            if m.url.starts_with("http://") || m.url.starts_with("https://") {
+                                metalink.mirrors.push(m);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    order_by_preference(&mut metalink.mirrors);
+    Ok(metalink)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METALINK: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/" xmlns:mm0="http://fedorahosted.org/mirrormanager">
+ <files>
+  <file name="repomd.xml">
+   <mm0:alternates>
+    <mm0:alternate>
+     <size>1000</size>
+     <verification><hash type="sha256">0000</hash></verification>
+    </mm0:alternate>
+   </mm0:alternates>
+   <size>5</size>
+   <verification>
+    <hash type="md5">1111</hash>
+    <hash type="sha256">2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae</hash>
+   </verification>
+   <resources maxconnections="1">
+    <url protocol="rsync" type="rsync" location="US" preference="100">rsync://mirror.example/fedora/repodata/repomd.xml</url>
+    <url protocol="http" type="http" location="DE" preference="90">http://de.example/fedora/repodata/repomd.xml</url>
+    <url protocol="https" type="https" location="US" preference="99">https://us.example/fedora/repodata/repomd.xml</url>
+    <url protocol="https" type="https" location="FR" preference="90">https://fr.example/fedora/</url>
+   </resources>
+  </file>
+ </files>
+</metalink>
+"#;
+
+    #[test]
+    fn mirrorlist_keeps_order_and_skips_comments() {
+        let mirrors = parse_mirrorlist("# fedora\n\nhttp://a.example/f/\n  https://b.example/f  \n#http://c.example\n");
+        assert_eq!(
+            mirrors,
+            vec![
+                Mirror { url: "http://a.example/f".to_string(), preference: 2 },
+                Mirror { url: "https://b.example/f".to_string(), preference: 1 },
+            ]
+        );
+        assert!(parse_mirrorlist("").is_empty());
+    }
+
+    #[test]
+    fn metalink_mirrors_by_preference() {
+        let metalink = parse_metalink(METALINK.as_bytes()).unwrap();
+        let urls: Vec<&str> = metalink.mirrors.iter().map(|m| m.url.as_str()).collect();
+        // rsync is dropped, equal preferences keep document order
+        assert_eq!(urls, ["https://us.example/fedora", "http://de.example/fedora", "https://fr.example/fedora"]);
+        assert_eq!(metalink.repomd_size, Some(5));
+        // Hashes of alternate, older repomd.xml files are not kept
+        assert_eq!(metalink.repomd_hashes.len(), 2);
+        assert_eq!(metalink.repomd_hashes[0], ("md5".to_string(), "1111".to_string()));
+    }
+
+    #[test]
+    fn metalink_for_other_files_gives_nothing() {
+        let metalink = parse_metalink(METALINK.replace("repomd.xml\"", "other.xml\"").as_bytes()).unwrap();
+        assert!(metalink.mirrors.is_empty());
+        assert!(metalink.repomd_size.is_none());
+        assert!(metalink.repomd_hashes.is_empty());
+    }
+
+    #[test]
+    fn malformed_metalink_is_an_error() {
+        assert!(parse_metalink("<metalink><files></metalink>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn repomd_checked_against_size_and_strongest_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repomd.xml");
+        let metalink = parse_metalink(METALINK.as_bytes()).unwrap();
+
+        // The listed sha256 is that of "foo"
+        fs::write(&path, "foo").unwrap();
+        assert!(metalink.verify_repomd(&path).is_err(), "size differs");
+        fs::write(&path, "foo\n\n").unwrap();
+        assert!(metalink.verify_repomd(&path).is_err(), "hash differs");
+        let metalink = Metalink {
+            repomd_size: Some(3),
+            ..metalink
+        };
+        fs::write(&path, "foo").unwrap();
+        metalink.verify_repomd(&path).unwrap();
+
+        let unchecked = Metalink::default();
+        unchecked.verify_repomd(&path).unwrap();
+    }
+}
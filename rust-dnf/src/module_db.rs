@@ -0,0 +1,231 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ModuleStatus {
+    Enabled,
+    Disabled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleState {
+    pub name: String,
+    pub stream: Option<String>,
+    pub status: ModuleStatus,
+    pub profiles: Vec<String>, // Installed profiles of `stream`
+}
+
+/// Persistent module state. Modules without an entry are in their default
+/// state: the default stream (if any) is active and nothing is installed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModuleDatabase {
+    pub modules: HashMap<String, ModuleState>,
+    pub database_path: PathBuf,
+}
+
+/// Split a `name[:stream][/profile]` module spec
+pub fn parse_module_spec(spec: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, profile) = match spec.split_once('/') {
+        Some((rest, profile)) => (rest, Some(profile)),
+        None => (spec, None),
+    };
+    match rest.split_once(':') {
+        Some((name, stream)) => (name, Some(stream), profile),
+        None => (rest, None, profile),
+    }
+}
+
+impl ModuleDatabase {
+    pub fn new(database_path: PathBuf) -> Self {
+        Self {
+            modules: HashMap::new(),
+            database_path,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<()> {
+        log::info!("Loading module database from {:?}", self.database_path);
+
+        if !self.database_path.exists() {
+            log::debug!("Module database does not exist, all modules are in their default state");
+            return Ok(());
+        }
+
+        let data = fs::read_to_string(&self.database_path)?;
+        let db: ModuleDatabase = serde_json::from_str(&data)?;
+
+        self.modules = db.modules;
+        log::info!("Loaded state of {} modules", self.modules.len());
+
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        log::debug!("Saving module database to {:?}", self.database_path);
+
+        if let Some(parent) = self.database_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = serde_json::to_string_pretty(&self)?;
+        fs::write(&self.database_path, data)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModuleState> {
+        self.modules.get(name)
+    }
+
+    pub fn enabled_stream(&self, name: &str) -> Option<&str> {
+        match self.modules.get(name) {
+            Some(state) if state.status == ModuleStatus::Enabled => state.stream.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn is_disabled(&self, name: &str) -> bool {
+        matches!(self.modules.get(name), Some(state) if state.status == ModuleStatus::Disabled)
+    }
+
+    /// Enable `stream` of a module. Changing an already enabled stream needs
+    /// `switch_stream` since installed packages have to follow.
+    pub fn enable(&mut self, name: &str, stream: &str) -> Result<()> {
+        if let Some(enabled) = self.enabled_stream(name) {
+            if enabled != stream {
+                anyhow::bail!(
+                    "Module {} already has stream {} enabled, use switch-to to change it",
+                    name,
+                    enabled
+                );
+            }
+            return Ok(());
+        }
+
+        self.modules.insert(
+            name.to_string(),
+            ModuleState {
+                name: name.to_string(),
+                stream: Some(stream.to_string()),
+                status: ModuleStatus::Enabled,
+                profiles: Vec::new(),
+            },
+        );
+        self.save()?;
+
+        log::info!("Module {}:{} enabled", name, stream);
+        Ok(())
+    }
+
+    pub fn switch_stream(&mut self, name: &str, stream: &str) -> Result<()> {
+        self.modules.insert(
+            name.to_string(),
+            ModuleState {
+                name: name.to_string(),
+                stream: Some(stream.to_string()),
+                status: ModuleStatus::Enabled,
+                profiles: Vec::new(),
+            },
+        );
+        self.save()?;
+
+        log::info!("Module {} switched to stream {}", name, stream);
+        Ok(())
+    }
+
+    pub fn disable(&mut self, name: &str) -> Result<()> {
+        self.modules.insert(
+            name.to_string(),
+            ModuleState {
+                name: name.to_string(),
+                stream: None,
+                status: ModuleStatus::Disabled,
+                profiles: Vec::new(),
+            },
+        );
+        self.save()?;
+
+        log::info!("Module {} disabled", name);
+        Ok(())
+    }
+
+    /// Return a module to its default state, forgetting installed profiles
+    pub fn reset(&mut self, name: &str) -> Result<()> {
+        if self.modules.remove(name).is_some() {
+            self.save()?;
+            log::info!("Module {} reset", name);
+        }
+        Ok(())
+    }
+
+    pub fn add_profiles(&mut self, name: &str, profiles: &[String]) -> Result<()> {
+        let state = match self.modules.get_mut(name) {
+            Some(state) if state.status == ModuleStatus::Enabled => state,
+            _ => anyhow::bail!("Module {} has no enabled stream", name),
+        };
+        for profile in profiles {
+            if !state.profiles.contains(profile) {
+                state.profiles.push(profile.clone());
+            }
+        }
+        self.save()
+    }
+
+    pub fn remove_profiles(&mut self, name: &str, profiles: &[String]) -> Result<()> {
+        if let Some(state) = self.modules.get_mut(name) {
+            state.profiles.retain(|profile| !profiles.contains(profile));
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_specs_are_split() {
+        assert_eq!(parse_module_spec("nodejs"), ("nodejs", None, None));
+        assert_eq!(parse_module_spec("nodejs:18"), ("nodejs", Some("18"), None));
+        assert_eq!(parse_module_spec("nodejs:18/development"), ("nodejs", Some("18"), Some("development")));
+        assert_eq!(parse_module_spec("nodejs/common"), ("nodejs", None, Some("common")));
+    }
+
+    #[test]
+    fn module_state_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modules/modules.json");
+        let reopen = || {
+            let mut db = ModuleDatabase::new(path.clone());
+            db.load().unwrap();
+            db
+        };
+
+        let mut db = reopen();
+        assert!(db.modules.is_empty());
+        db.enable("nodejs", "18").unwrap();
+        db.add_profiles("nodejs", &["common".to_string(), "development".to_string()]).unwrap();
+        db.remove_profiles("nodejs", &["development".to_string()]).unwrap();
+        db.disable("postgresql").unwrap();
+
+        let mut db = reopen();
+        assert_eq!(db.enabled_stream("nodejs"), Some("18"));
+        assert_eq!(db.get("nodejs").unwrap().profiles, ["common"]);
+        assert!(db.is_disabled("postgresql") && db.enabled_stream("postgresql").is_none());
+        assert!(db.enable("nodejs", "18").is_ok());
+        assert!(db.enable("nodejs", "20").is_err());
+        assert!(db.add_profiles("postgresql", &["server".to_string()]).is_err());
+
+        db.switch_stream("nodejs", "20").unwrap();
+        db.reset("postgresql").unwrap();
+
+        let db = reopen();
+        assert_eq!(db.enabled_stream("nodejs"), Some("20"));
+        assert!(db.get("nodejs").unwrap().profiles.is_empty());
+        assert!(db.get("postgresql").is_none());
+    }
+}
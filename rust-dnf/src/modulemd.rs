@@ -0,0 +1,253 @@
+use crate::package::parse_nevra;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub description: String,
+    pub rpms: Vec<String>,
+}
+
+/// One built context of a module stream (a `modulemd` document)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleStream {
+    pub name: String,
+    pub stream: String,
+    pub version: u64,
+    pub context: String,
+    pub arch: String,
+    pub summary: String,
+    pub description: String,
+    pub profiles: BTreeMap<String, Profile>,
+    pub artifacts: Vec<String>, // Normalized NEVRAs, always with an epoch
+}
+
+/// A `modulemd-defaults` document
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleDefaults {
+    pub module: String,
+    pub stream: Option<String>,
+    pub profiles: HashMap<String, Vec<String>>, // stream -> default profiles
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleMetadata {
+    pub streams: Vec<ModuleStream>,
+    pub defaults: HashMap<String, ModuleDefaults>,
+}
+
+impl ModuleMetadata {
+    pub fn default_stream(&self, module: &str) -> Option<&str> {
+        self.defaults.get(module)?.stream.as_deref()
+    }
+
+    pub fn default_profiles(&self, module: &str, stream: &str) -> &[String] {
+        self.defaults
+            .get(module)
+            .and_then(|defaults| defaults.profiles.get(stream))
+            .map(|profiles| profiles.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+/// Scalars in modulemd are often unquoted, so streams like `18` arrive as
+/// numbers rather than strings.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn string_list(value: &Value) -> Vec<String> {
+    value
+        .as_sequence()
+        .map(|seq| seq.iter().map(scalar).collect())
+        .unwrap_or_default()
+}
+
+fn parse_stream(data: &Value) -> ModuleStream {
+    let mut stream = ModuleStream {
+        name: scalar(&data["name"]),
+        stream: scalar(&data["stream"]),
+        version: data["version"].as_u64().unwrap_or(0),
+        context: scalar(&data["context"]),
+        arch: scalar(&data["arch"]),
+        summary: scalar(&data["summary"]),
+        description: scalar(&data["description"]),
+        ..Default::default()
+    };
+
+    if let Some(profiles) = data["profiles"].as_mapping() {
+        for (name, profile) in profiles {
+            stream.profiles.insert(
+                scalar(name),
+                Profile {
+                    description: scalar(&profile["description"]),
+                    rpms: string_list(&profile["rpms"]),
+                },
+            );
+        }
+    }
+
+    for artifact in string_list(&data["artifacts"]["rpms"]) {
+        match parse_nevra(&artifact) {
+            Ok((name, version)) => stream.artifacts.push(format!(
+                "{}-{}:{}-{}.{}",
+                name.name, version.epoch, version.version, version.release, name.arch
+            )),
+            Err(e) => log::debug!("Skipping artifact of {}:{}: {}", stream.name, stream.stream, e),
+        }
+    }
+
+    stream
+}
+
+fn parse_defaults(data: &Value) -> ModuleDefaults {
+    let mut defaults = ModuleDefaults {
+        module: scalar(&data["module"]),
+        stream: data.get("stream").map(scalar),
+        ..Default::default()
+    };
+
+    if let Some(profiles) = data["profiles"].as_mapping() {
+        for (stream, names) in profiles {
+            defaults.profiles.insert(scalar(stream), string_list(names));
+        }
+    }
+
+    defaults
+}
+
+/// Parse a modules.yaml stream of modulemd and modulemd-defaults documents.
+/// Other document types (obsoletes, translations) are ignored.
+pub fn parse_modules<R: Read>(source: R) -> Result<ModuleMetadata> {
+    let mut metadata = ModuleMetadata::default();
+
+    for document in serde_yaml::Deserializer::from_reader(source) {
+        let value = Value::deserialize(document)?;
+        let data = &value["data"];
+
+        match value["document"].as_str() {
+            Some("modulemd") => metadata.streams.push(parse_stream(data)),
+            Some("modulemd-defaults") => {
+                let defaults = parse_defaults(data);
+                metadata.defaults.insert(defaults.module.clone(), defaults);
+            }
+            Some(other) => log::debug!("Ignoring {} document", other),
+            None => log::warn!("modules.yaml document without a type"),
+        }
+    }
+
+    log::info!(
+        "Parsed {} module streams and {} module defaults",
+        metadata.streams.len(),
+        metadata.defaults.len()
+    );
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULES: &str = r#"---
+document: modulemd
+version: 2
+data:
+  name: nodejs
+  stream: 18
+  version: 3920230801
+  context: f27b74a8
+  arch: x86_64
+  summary: Javascript runtime
+  description: >-
+    Node.js is a platform built on Chrome's JavaScript runtime.
+  profiles:
+    common:
+      description: Common installation
+      rpms:
+      - nodejs
+      - npm
+    development:
+      rpms:
+      - nodejs
+      - nodejs-devel
+  artifacts:
+    rpms:
+    - nodejs-1:18.17.1-1.module_f39+17500+a1b2c3d4.x86_64
+    - npm-9.6.7-1.18.17.1.1.module_f39+17500+a1b2c3d4.x86_64
+    - not-a-nevra
+...
+---
+document: modulemd-defaults
+version: 1
+data:
+  module: nodejs
+  stream: 18
+  profiles:
+    18: [common]
+    20: [common, development]
+...
+---
+document: modulemd-obsoletes
+version: 1
+data:
+  module: nodejs
+...
+---
+document: modulemd
+version: 2
+data:
+  name: nodejs
+  stream: "20"
+  version: 3920231001
+  context: 9a8b7c6d
+  arch: x86_64
+  summary: Javascript runtime
+...
+"#;
+
+    #[test]
+    fn module_streams_are_parsed() {
+        let metadata = parse_modules(MODULES.as_bytes()).unwrap();
+        assert_eq!(metadata.streams.len(), 2);
+
+        let stream = &metadata.streams[0];
+        assert_eq!((stream.name.as_str(), stream.stream.as_str()), ("nodejs", "18"));
+        assert_eq!((stream.version, stream.context.as_str(), stream.arch.as_str()), (3920230801, "f27b74a8", "x86_64"));
+        assert_eq!(stream.summary, "Javascript runtime");
+        assert!(stream.description.starts_with("Node.js is a platform"));
+        assert_eq!(stream.profiles.keys().collect::<Vec<_>>(), ["common", "development"]);
+        assert_eq!(stream.profiles["common"].description, "Common installation");
+        assert_eq!(stream.profiles["development"].rpms, ["nodejs", "nodejs-devel"]);
+        // Artifacts always carry an epoch; ones that are no NEVRA are dropped
+        assert_eq!(
+            stream.artifacts,
+            [
+                "nodejs-1:18.17.1-1.module_f39+17500+a1b2c3d4.x86_64",
+                "npm-0:9.6.7-1.18.17.1.1.module_f39+17500+a1b2c3d4.x86_64",
+            ]
+        );
+
+        assert_eq!(metadata.streams[1].stream, "20");
+        assert!(metadata.streams[1].profiles.is_empty() && metadata.streams[1].artifacts.is_empty());
+    }
+
+    #[test]
+    fn defaults_resolve_streams_and_profiles() {
+        let metadata = parse_modules(MODULES.as_bytes()).unwrap();
+
+        assert_eq!(metadata.default_stream("nodejs"), Some("18"));
+        assert_eq!(metadata.default_profiles("nodejs", "18"), ["common"]);
+        assert_eq!(metadata.default_profiles("nodejs", "20"), ["common", "development"]);
+        assert!(metadata.default_profiles("nodejs", "16").is_empty());
+        assert_eq!(metadata.default_stream("postgresql"), None);
+        assert!(metadata.default_profiles("postgresql", "15").is_empty());
+    }
+}
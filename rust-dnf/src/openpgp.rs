@@ -0,0 +1,998 @@
+use anyhow::Result;
+use base64::Engine;
+use ed25519_dalek::Verifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+// Packet tags
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+// Public key algorithms
+const ALGO_RSA: u8 = 1;
+const ALGO_RSA_SIGN: u8 = 3;
+const ALGO_EDDSA_LEGACY: u8 = 22;
+const ALGO_ED25519: u8 = 27;
+
+// Signature types
+const SIG_CERTIFICATIONS: std::ops::RangeInclusive<u8> = 0x10..=0x13; // Of a user id
+const SIG_SUBKEY_BINDING: u8 = 0x18;
+const SIG_PRIMARY_KEY_BINDING: u8 = 0x19; // The back signature of a signing subkey
+const SIG_DIRECT_KEY: u8 = 0x1f;
+const SIG_KEY_REVOCATION: u8 = 0x20;
+const SIG_SUBKEY_REVOCATION: u8 = 0x28;
+
+// Signature subpackets
+const SUBPACKET_CREATION_TIME: u8 = 2;
+const SUBPACKET_KEY_EXPIRATION: u8 = 9;
+const SUBPACKET_ISSUER: u8 = 16;
+const SUBPACKET_KEY_FLAGS: u8 = 27;
+const SUBPACKET_EMBEDDED_SIGNATURE: u8 = 32;
+const SUBPACKET_ISSUER_FINGERPRINT: u8 = 33;
+
+const KEY_FLAG_SIGN: u8 = 0x02;
+
+const ED25519_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01];
+
+const ARMOR_BEGIN: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----";
+const ARMOR_END: &str = "-----END PGP PUBLIC KEY BLOCK-----";
+
+/// Hash algorithms signatures are made over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn from_id(id: u8) -> Result<Self> {
+        Ok(match id {
+            2 => Self::Sha1,
+            8 => Self::Sha256,
+            9 => Self::Sha384,
+            10 => Self::Sha512,
+            11 => Self::Sha224,
+            other => anyhow::bail!("Unsupported signature hash algorithm {}", other),
+        })
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha224 => Hasher::Sha224(Sha224::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha384 => Hasher::Sha384(Sha384::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn pkcs1v15(self) -> Pkcs1v15Sign {
+        match self {
+            Self::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+            Self::Sha224 => Pkcs1v15Sign::new::<Sha224>(),
+            Self::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+            Self::Sha384 => Pkcs1v15Sign::new::<Sha384>(),
+            Self::Sha512 => Pkcs1v15Sign::new::<Sha512>(),
+        }
+    }
+}
+
+/// Hashes signed data as it is read
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha224(Sha224),
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(h) => h.update(data),
+            Self::Sha224(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha384(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha1(h) => h.finalize().to_vec(),
+            Self::Sha224(h) => h.finalize().to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha384(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+        }
+    }
+}
+
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Packet<'a> {
+    tag: u8,
+    body: &'a [u8],
+    raw: &'a [u8], // Header and body
+}
+
+/// Splits binary OpenPGP data into packets
+fn packets(mut data: &[u8]) -> Result<Vec<Packet<'_>>> {
+    let truncated = || anyhow::anyhow!("Truncated OpenPGP packet");
+    let mut packets = Vec::new();
+    while let Some((&first, rest)) = data.split_first() {
+        if first & 0x80 == 0 {
+            anyhow::bail!("Invalid OpenPGP packet header");
+        }
+        let (tag, len, rest) = if first & 0x40 != 0 {
+            // New format
+            let (len, rest) = match *rest.first().ok_or_else(truncated)? {
+                octet @ 0..=191 => (octet as usize, &rest[1..]),
+                octet @ 192..=223 => {
+                    let second = *rest.get(1).ok_or_else(truncated)? as usize;
+                    (((octet as usize - 192) << 8) + second + 192, &rest[2..])
+                }
+                255 => (be_u32(rest.get(1..5).ok_or_else(truncated)?) as usize, &rest[5..]),
+                _ => anyhow::bail!("Partial OpenPGP packet lengths are not supported in keys and signatures"),
+            };
+            (first & 0x3f, len, rest)
+        } else {
+            // Old format
+            let (len, rest) = match first & 0x03 {
+                0 => (*rest.first().ok_or_else(truncated)? as usize, &rest[1..]),
+                1 => (be_u16(rest.get(..2).ok_or_else(truncated)?) as usize, &rest[2..]),
+                2 => (be_u32(rest.get(..4).ok_or_else(truncated)?) as usize, &rest[4..]),
+                _ => (rest.len(), rest),
+            };
+            ((first >> 2) & 0x0f, len, rest)
+        };
+        let body = rest.get(..len).ok_or_else(truncated)?;
+        let header_len = data.len() - rest.len();
+        packets.push(Packet { tag, body, raw: &data[..header_len + len] });
+        data = &rest[len..];
+    }
+    Ok(packets)
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Reads the fields of one packet body in order
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("Truncated OpenPGP packet");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(be_u16(self.take(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(be_u32(self.take(4)?))
+    }
+
+    /// A multiprecision integer: a bit count, then big-endian bytes
+    fn mpi(&mut self) -> Result<&'a [u8]> {
+        let bits = self.u16()? as usize;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+/// Key material this crate can verify signatures with
+#[derive(Debug, Clone)]
+enum KeyMaterial {
+    Rsa(RsaPublicKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+    Unsupported(u8),
+}
+
+/// A primary key or subkey
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub fingerprint: [u8; 20],
+    pub created: u32,         // Seconds since the epoch
+    pub expires: Option<u32>, // Per its newest self-signature, if it expires at all
+    pub revoked: bool,
+    material: KeyMaterial,
+}
+
+impl PublicKey {
+    fn parse(body: &[u8]) -> Result<Self> {
+        let mut fields = Fields { data: body };
+        let version = fields.byte()?;
+        if version != 4 {
+            anyhow::bail!("OpenPGP keys of version {} are not supported", version);
+        }
+        let created = fields.u32()?;
+        let algorithm = fields.byte()?;
+        let material = match algorithm {
+            ALGO_RSA | ALGO_RSA_SIGN => {
+                let n = BigUint::from_bytes_be(fields.mpi()?);
+                let e = BigUint::from_bytes_be(fields.mpi()?);
+                KeyMaterial::Rsa(RsaPublicKey::new(n, e)?)
+            }
+            ALGO_EDDSA_LEGACY => {
+                let oid_len = fields.byte()? as usize;
+                let oid = fields.take(oid_len)?;
+                let point = fields.mpi()?;
+                match (oid == ED25519_OID, point.split_first()) {
+                    // Points are prefixed with 0x40 for "native" encoding
+                    (true, Some((0x40, point))) => KeyMaterial::Ed25519(ed25519_key(point)?),
+                    _ => KeyMaterial::Unsupported(algorithm),
+                }
+            }
+            ALGO_ED25519 => KeyMaterial::Ed25519(ed25519_key(fields.take(32)?)?),
+            other => KeyMaterial::Unsupported(other),
+        };
+
+        let mut hasher = Sha1::new();
+        hasher.update([0x99]);
+        hasher.update((body.len() as u16).to_be_bytes());
+        hasher.update(body);
+        Ok(Self {
+            fingerprint: hasher.finalize().into(),
+            created,
+            expires: None,
+            revoked: false,
+            material,
+        })
+    }
+
+    /// Whether a signature made at `time` with this key can be trusted
+    pub fn check_usable(&self, time: u32) -> Result<()> {
+        if self.revoked {
+            anyhow::bail!("key {} is revoked", self.fingerprint_hex());
+        }
+        if time < self.created {
+            anyhow::bail!("the signature predates key {}", self.fingerprint_hex());
+        }
+        if self.expires.is_some_and(|expires| time >= expires) {
+            anyhow::bail!("key {} had expired when the signature was made", self.fingerprint_hex());
+        }
+        Ok(())
+    }
+
+    pub fn key_id(&self) -> u64 {
+        u64::from_be_bytes(self.fingerprint[12..].try_into().expect("8 bytes"))
+    }
+
+    pub fn fingerprint_hex(&self) -> String {
+        hex::encode_upper(self.fingerprint)
+    }
+}
+
+fn ed25519_key(point: &[u8]) -> Result<ed25519_dalek::VerifyingKey> {
+    let point: &[u8; 32] = point
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Ed25519 public key of {} bytes", point.len()))?;
+    Ok(ed25519_dalek::VerifyingKey::from_bytes(point)?)
+}
+
+/// A transferable public key: a primary key with its user ids and subkeys.
+/// Trust in the primary key comes from the user importing it; its
+/// self-signatures then decide which subkeys belong to it, and when keys
+/// expire or were revoked.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub primary: PublicKey,
+    pub subkeys: Vec<PublicKey>,
+    pub user_ids: Vec<String>,
+    pub data: Vec<u8>, // The certificate's packets, as read
+}
+
+impl Certificate {
+    /// The name rpm gives an imported key: gpg-pubkey-<short key id>-<creation time>
+    pub fn name(&self) -> String {
+        format!("gpg-pubkey-{:08x}-{:08x}", self.primary.key_id() as u32, self.primary.created)
+    }
+
+    pub fn user_id(&self) -> &str {
+        self.user_ids.first().map(String::as_str).unwrap_or("")
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &PublicKey> {
+        std::iter::once(&self.primary).chain(&self.subkeys)
+    }
+
+    /// Whether `id` names this certificate: its name, or the key id or
+    /// fingerprint of any of its keys, in hex with or without "0x"
+    pub fn matches(&self, id: &str) -> bool {
+        let id = id.trim_start_matches("0x").to_uppercase();
+        if id == self.name().to_uppercase() {
+            return true;
+        }
+        self.keys().any(|key| {
+            let fingerprint = key.fingerprint_hex();
+            id.len() >= 8 && fingerprint.ends_with(&id)
+        })
+    }
+}
+
+/// Parse one or more certificates from binary or ASCII-armored data
+pub fn parse_certificates(data: &[u8]) -> Result<Vec<Certificate>> {
+    let dearmored;
+    let data = match std::str::from_utf8(data) {
+        Ok(text) if text.contains(ARMOR_BEGIN) => {
+            dearmored = dearmor(text)?;
+            &dearmored[..]
+        }
+        _ => data,
+    };
+
+    let mut grouped: Vec<Vec<Packet>> = Vec::new();
+    for packet in packets(data)? {
+        if packet.tag == TAG_PUBLIC_KEY {
+            grouped.push(Vec::new());
+        }
+        grouped
+            .last_mut()
+            .ok_or_else(|| anyhow::anyhow!("OpenPGP data does not start with a public key"))?
+            .push(packet);
+    }
+    if grouped.is_empty() {
+        anyhow::bail!("No OpenPGP public key found");
+    }
+    grouped.iter().map(|packets| certificate(packets)).collect()
+}
+
+/// What the signatures that follow a packet of a certificate are about
+#[derive(Clone, Copy)]
+enum Signed<'a> {
+    Primary,
+    UserId(&'a [u8]),
+    Subkey(usize),
+    Other,
+}
+
+/// A certificate from its packets, the first being the primary key
+fn certificate(packets: &[Packet]) -> Result<Certificate> {
+    let (first, rest) = packets.split_first().expect("grouped by primary key");
+    let mut primary = PublicKey::parse(first.body)?;
+    let primary_data = key_hash_data(first.body);
+    let mut data = first.raw.to_vec();
+    let mut user_ids = Vec::new();
+    let mut self_signatures = Vec::new(); // With the user id they certify, if any
+    let mut subkeys: Vec<(PublicKey, Vec<u8>, Vec<Signature>)> = Vec::new(); // With their hashed form
+
+    let mut signed = Signed::Primary;
+    for packet in rest {
+        data.extend_from_slice(packet.raw);
+        match packet.tag {
+            TAG_USER_ID => {
+                user_ids.push(String::from_utf8_lossy(packet.body).into_owned());
+                signed = Signed::UserId(packet.body);
+            }
+            // Subkeys of unknown versions are skipped rather than rejecting the whole key
+            TAG_PUBLIC_SUBKEY => match PublicKey::parse(packet.body) {
+                Ok(subkey) => {
+                    subkeys.push((subkey, key_hash_data(packet.body), Vec::new()));
+                    signed = Signed::Subkey(subkeys.len() - 1);
+                }
+                Err(e) => {
+                    log::debug!("Skipping subkey: {}", e);
+                    signed = Signed::Other;
+                }
+            },
+            TAG_SIGNATURE => {
+                let signature = match Signature::parse_body(packet.body) {
+                    Ok(signature) => signature,
+                    Err(e) => {
+                        log::debug!("Skipping signature of key {}: {}", primary.fingerprint_hex(), e);
+                        continue;
+                    }
+                };
+                match signed {
+                    Signed::Primary => self_signatures.push((signature, None)),
+                    Signed::UserId(user_id) => self_signatures.push((signature, Some(user_id))),
+                    Signed::Subkey(i) => subkeys[i].2.push(signature),
+                    Signed::Other => {}
+                }
+            }
+            _ => signed = Signed::Other,
+        }
+    }
+
+    // The newest valid self-signature says when the primary key expires
+    let mut newest: Option<&Signature> = None;
+    for (signature, user_id) in &self_signatures {
+        let valid = match (signature.kind, user_id) {
+            (SIG_KEY_REVOCATION | SIG_DIRECT_KEY, None) => signature.verify_by(&primary, &[&primary_data]),
+            (kind, Some(user_id)) if SIG_CERTIFICATIONS.contains(&kind) && signature.version == 4 => {
+                let mut user_id_data = vec![0xb4];
+                user_id_data.extend_from_slice(&(user_id.len() as u32).to_be_bytes());
+                user_id_data.extend_from_slice(user_id);
+                signature.verify_by(&primary, &[&primary_data, &user_id_data])
+            }
+            _ => false,
+        };
+        if !valid {
+            continue;
+        }
+        if signature.kind == SIG_KEY_REVOCATION {
+            primary.revoked = true;
+        } else if newest.is_none_or(|newest| signature.created >= newest.created) {
+            newest = Some(signature);
+        }
+    }
+    primary.expires = newest.and_then(|signature| signature.key_expiry(&primary));
+
+    // Subkeys only count with a valid binding signature from the primary
+    // key and, for signing, a back signature from the subkey itself
+    let mut bound = Vec::new();
+    for (mut subkey, subkey_data, signatures) in subkeys {
+        let signed_data = [primary_data.as_slice(), subkey_data.as_slice()];
+        let mut binding: Option<&Signature> = None;
+        for signature in &signatures {
+            match signature.kind {
+                SIG_SUBKEY_REVOCATION if signature.verify_by(&primary, &signed_data) => subkey.revoked = true,
+                SIG_SUBKEY_BINDING
+                    if binding.is_none_or(|binding| signature.created >= binding.created)
+                        && signature.verify_by(&primary, &signed_data) =>
+                {
+                    binding = Some(signature);
+                }
+                _ => {}
+            }
+        }
+        let Some(binding) = binding else {
+            log::debug!("Skipping subkey {} without a valid binding signature", subkey.fingerprint_hex());
+            continue;
+        };
+        if binding.key_flags.is_some_and(|flags| flags & KEY_FLAG_SIGN == 0) {
+            log::debug!("Skipping subkey {}, it is not for signing", subkey.fingerprint_hex());
+            continue;
+        }
+        let back_signed = binding
+            .embedded
+            .as_deref()
+            .is_some_and(|back| back.kind == SIG_PRIMARY_KEY_BINDING && back.verify_by(&subkey, &signed_data));
+        if !back_signed {
+            log::debug!("Skipping signing subkey {} without a valid back signature", subkey.fingerprint_hex());
+            continue;
+        }
+        subkey.expires = match (binding.key_expiry(&subkey), primary.expires) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        subkey.revoked |= primary.revoked;
+        bound.push(subkey);
+    }
+
+    Ok(Certificate { primary, subkeys: bound, user_ids, data })
+}
+
+/// A key packet body as signatures over keys hash it
+fn key_hash_data(body: &[u8]) -> Vec<u8> {
+    let mut data = vec![0x99];
+    data.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    data.extend_from_slice(body);
+    data
+}
+
+/// The binary data of an ASCII-armored public key block
+fn dearmor(text: &str) -> Result<Vec<u8>> {
+    let begin = text
+        .find(ARMOR_BEGIN)
+        .ok_or_else(|| anyhow::anyhow!("No OpenPGP public key block found"))?;
+    let block = &text[begin + ARMOR_BEGIN.len()..];
+    let block = &block[..block.find(ARMOR_END).ok_or_else(|| anyhow::anyhow!("Unterminated OpenPGP public key block"))?];
+
+    let mut lines = block.lines().map(str::trim).skip_while(|line| line.is_empty()).peekable();
+    // Armor headers, like "Version: ...", end at an empty line
+    if lines.peek().map(|line| line.contains(": ")).unwrap_or(false) {
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+        }
+    }
+    // The checksum line starts with '='
+    let body: String = lines.take_while(|line| !line.starts_with('=')).collect();
+    Ok(base64::engine::general_purpose::STANDARD.decode(body)?)
+}
+
+/// Who made a signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Issuer {
+    KeyId(u64),
+    Fingerprint([u8; 20]),
+}
+
+impl Issuer {
+    pub fn key_id(&self) -> u64 {
+        match self {
+            Self::KeyId(id) => *id,
+            Self::Fingerprint(fingerprint) => u64::from_be_bytes(fingerprint[12..].try_into().expect("8 bytes")),
+        }
+    }
+
+    fn matches(&self, key: &PublicKey) -> bool {
+        match self {
+            Self::KeyId(id) => key.key_id() == *id,
+            Self::Fingerprint(fingerprint) => key.fingerprint == *fingerprint,
+        }
+    }
+}
+
+/// A signature over some data, such as an rpm header
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub hash: HashAlgorithm,
+    pub issuer: Option<Issuer>,
+    pub kind: u8,     // Signature type, e.g. 0x00 for binary data
+    pub created: u32, // Seconds since the epoch
+    version: u8,
+    key_expiry: Option<u32>, // Seconds after the signed key's creation, in self-signatures
+    key_flags: Option<u8>,   // What a key binding signature allows the key
+    embedded: Option<Box<Signature>>, // The back signature of a signing subkey binding
+    algorithm: u8,
+    trailer: Vec<u8>, // Hashed after the signed data
+    left16: [u8; 2],  // First bytes of the digest, a quick check
+    values: Vec<Vec<u8>>,
+}
+
+impl Signature {
+    /// Parse a binary signature packet, as rpm stores them
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let packets = packets(data)?;
+        match packets.as_slice() {
+            [Packet { tag: TAG_SIGNATURE, body, .. }] => Self::parse_body(body),
+            _ => anyhow::bail!("Not an OpenPGP signature"),
+        }
+    }
+
+    fn parse_body(body: &[u8]) -> Result<Self> {
+        let mut fields = Fields { data: body };
+        let version = fields.byte()?;
+        let mut key_expiry = None;
+        let mut key_flags = None;
+        let mut embedded = None;
+        let (kind, created, algorithm, hash, trailer, issuer) = match version {
+            3 => {
+                if fields.byte()? != 5 {
+                    anyhow::bail!("Malformed version 3 signature");
+                }
+                let hashed = fields.take(5)?; // Signature type and creation time
+                let key_id = u64::from_be_bytes(fields.take(8)?.try_into()?);
+                let algorithm = fields.byte()?;
+                let hash = HashAlgorithm::from_id(fields.byte()?)?;
+                (hashed[0], be_u32(&hashed[1..]), algorithm, hash, hashed.to_vec(), Some(Issuer::KeyId(key_id)))
+            }
+            4 => {
+                let kind = fields.byte()?;
+                let algorithm = fields.byte()?;
+                let hash = HashAlgorithm::from_id(fields.byte()?)?;
+                let hashed_len = fields.u16()? as usize;
+                let hashed = subpackets(fields.take(hashed_len)?)?;
+                let unhashed_len = fields.u16()? as usize;
+                let unhashed = subpackets(fields.take(unhashed_len)?)?;
+
+                let mut trailer = body[..6 + hashed_len].to_vec();
+                trailer.extend_from_slice(&[0x04, 0xff]);
+                trailer.extend_from_slice(&((6 + hashed_len) as u32).to_be_bytes());
+                let issuer = issuer(&hashed)?.or(issuer(&unhashed)?);
+
+                // Only what is hashed is vouched for, except for the
+                // embedded signature, which is itself signed
+                let mut created = None;
+                for &(kind, data) in &hashed {
+                    match kind {
+                        SUBPACKET_CREATION_TIME if data.len() == 4 => created = Some(be_u32(data)),
+                        SUBPACKET_KEY_EXPIRATION if data.len() == 4 => key_expiry = Some(be_u32(data)),
+                        SUBPACKET_KEY_FLAGS => key_flags = data.first().copied(),
+                        _ => {}
+                    }
+                }
+                if let Some(&(_, data)) = hashed.iter().chain(&unhashed).find(|(kind, _)| *kind == SUBPACKET_EMBEDDED_SIGNATURE) {
+                    embedded = Some(Box::new(Self::parse_body(data)?));
+                }
+                let created = created.ok_or_else(|| anyhow::anyhow!("Signature has no creation time"))?;
+                (kind, created, algorithm, hash, trailer, issuer)
+            }
+            other => anyhow::bail!("OpenPGP signatures of version {} are not supported", other),
+        };
+        let left16 = fields.take(2)?.try_into()?;
+        let values = match algorithm {
+            ALGO_RSA | ALGO_RSA_SIGN => vec![fields.mpi()?.to_vec()],
+            ALGO_EDDSA_LEGACY => vec![fields.mpi()?.to_vec(), fields.mpi()?.to_vec()],
+            ALGO_ED25519 => vec![fields.take(64)?.to_vec()],
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            hash,
+            issuer,
+            kind,
+            created,
+            version,
+            key_expiry,
+            key_flags,
+            embedded,
+            algorithm,
+            trailer,
+            left16,
+            values,
+        })
+    }
+
+    /// Whether this is a valid signature by `key` over the concatenation
+    /// of `data`, as the self-signatures of a certificate are
+    fn verify_by(&self, key: &PublicKey, data: &[&[u8]]) -> bool {
+        if self.issuer.is_some_and(|issuer| !issuer.matches(key)) {
+            return false;
+        }
+        let mut hasher = self.hash.hasher();
+        data.iter().for_each(|part| hasher.update(part));
+        self.verify(key, hasher).is_ok()
+    }
+
+    /// When the key this self-signature is over expires, if it does
+    fn key_expiry(&self, key: &PublicKey) -> Option<u32> {
+        self.key_expiry
+            .filter(|&seconds| seconds != 0)
+            .map(|seconds| key.created.saturating_add(seconds))
+    }
+
+    /// Check the signature given `hasher`, which has hashed the signed data
+    pub fn verify(&self, key: &PublicKey, mut hasher: Hasher) -> Result<()> {
+        hasher.update(&self.trailer);
+        let digest = hasher.finalize();
+        if digest[..2] != self.left16 {
+            anyhow::bail!("Signature does not match the data");
+        }
+        match (&key.material, self.algorithm) {
+            (KeyMaterial::Rsa(rsa), ALGO_RSA | ALGO_RSA_SIGN) => {
+                // Signatures lose leading zero bytes as integers; PKCS #1 needs them back
+                let size = rsa::traits::PublicKeyParts::size(rsa);
+                let value = &self.values[0];
+                let mut padded = vec![0; size.saturating_sub(value.len())];
+                padded.extend_from_slice(value);
+                rsa.verify(self.hash.pkcs1v15(), &digest, &padded)
+                    .map_err(|_| anyhow::anyhow!("Bad RSA signature"))
+            }
+            (KeyMaterial::Ed25519(ed25519), ALGO_EDDSA_LEGACY | ALGO_ED25519) => {
+                let mut bytes = [0u8; 64];
+                if self.algorithm == ALGO_ED25519 {
+                    bytes.copy_from_slice(&self.values[0]);
+                } else {
+                    let (r, s) = (&self.values[0], &self.values[1]);
+                    if r.len() > 32 || s.len() > 32 {
+                        anyhow::bail!("Malformed EdDSA signature");
+                    }
+                    bytes[32 - r.len()..32].copy_from_slice(r);
+                    bytes[64 - s.len()..].copy_from_slice(s);
+                }
+                ed25519
+                    .verify(&digest, &ed25519_dalek::Signature::from_bytes(&bytes))
+                    .map_err(|_| anyhow::anyhow!("Bad EdDSA signature"))
+            }
+            (KeyMaterial::Unsupported(algorithm), _) => {
+                anyhow::bail!("Keys of public key algorithm {} are not supported", algorithm)
+            }
+            _ => anyhow::bail!("Signature algorithm {} does not match the key", self.algorithm),
+        }
+    }
+
+    /// The key in `certificates` this signature was made with
+    pub fn find_key<'a>(&self, certificates: impl IntoIterator<Item = &'a Certificate>) -> Option<&'a PublicKey> {
+        let issuer = self.issuer?;
+        certificates
+            .into_iter()
+            .flat_map(Certificate::keys)
+            .find(|key| issuer.matches(key))
+    }
+}
+
+/// The (type, data) subpackets of a v4 signature's subpacket area
+fn subpackets(mut area: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let truncated = || anyhow::anyhow!("Truncated signature subpacket");
+    let mut found = Vec::new();
+    while let Some((&first, rest)) = area.split_first() {
+        let (len, rest) = match first {
+            0..=191 => (first as usize, rest),
+            192..=254 => {
+                let second = *rest.first().ok_or_else(truncated)? as usize;
+                (((first as usize - 192) << 8) + second + 192, &rest[1..])
+            }
+            255 => (be_u32(rest.get(..4).ok_or_else(truncated)?) as usize, &rest[4..]),
+        };
+        let subpacket = rest.get(..len).ok_or_else(truncated)?;
+        area = &rest[len..];
+        if let Some((&kind, data)) = subpacket.split_first() {
+            found.push((kind & 0x7f, data)); // Without the critical bit
+        }
+    }
+    Ok(found)
+}
+
+/// The issuer named by a v4 signature's subpackets, if any
+fn issuer(subpackets: &[(u8, &[u8])]) -> Result<Option<Issuer>> {
+    let mut found = None;
+    for &(kind, data) in subpackets {
+        match kind {
+            SUBPACKET_ISSUER_FINGERPRINT if data.len() == 21 && data[0] == 4 => {
+                // A fingerprint is more specific than a key id
+                return Ok(Some(Issuer::Fingerprint(data[1..].try_into()?)));
+            }
+            SUBPACKET_ISSUER if data.len() == 8 => found = Some(Issuer::KeyId(u64::from_be_bytes(data.try_into()?))),
+            _ => {}
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    pub(crate) const CREATED: u32 = 1_700_000_000;
+
+    pub(crate) fn packet(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0xc0 | tag, 255];
+        packet.extend((body.len() as u32).to_be_bytes());
+        packet.extend(body);
+        packet
+    }
+
+    pub(crate) fn subpacket(kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut subpacket = vec![data.len() as u8 + 1, kind];
+        subpacket.extend(data);
+        subpacket
+    }
+
+    /// A v4 Ed25519 key that can make signatures
+    pub(crate) struct TestKey {
+        secret: SigningKey,
+        pub body: Vec<u8>,
+        pub fingerprint: [u8; 20],
+    }
+
+    impl TestKey {
+        pub(crate) fn new(seed: u8, created: u32) -> Self {
+            let secret = SigningKey::from_bytes(&[seed; 32]);
+            let mut body = vec![4];
+            body.extend(created.to_be_bytes());
+            body.push(ALGO_ED25519);
+            body.extend(secret.verifying_key().as_bytes());
+            let fingerprint = PublicKey::parse(&body).unwrap().fingerprint;
+            Self { secret, body, fingerprint }
+        }
+
+        /// The body of a SHA-256 signature of `kind` made at `created` over
+        /// the concatenation of `data`
+        pub(crate) fn sign(&self, kind: u8, created: u32, hashed: &[Vec<u8>], unhashed: &[Vec<u8>], data: &[&[u8]]) -> Vec<u8> {
+            let mut hashed_area = subpacket(SUBPACKET_CREATION_TIME, &created.to_be_bytes());
+            let mut issuer = vec![4];
+            issuer.extend(self.fingerprint);
+            hashed_area.extend(subpacket(SUBPACKET_ISSUER_FINGERPRINT, &issuer));
+            hashed.iter().for_each(|subpacket| hashed_area.extend(subpacket));
+            let unhashed_area = unhashed.concat();
+
+            let mut body = vec![4, kind, ALGO_ED25519, 8];
+            body.extend((hashed_area.len() as u16).to_be_bytes());
+            body.extend(&hashed_area);
+            let mut hasher = Sha256::new();
+            data.iter().for_each(|part| hasher.update(part));
+            hasher.update(&body);
+            hasher.update([0x04, 0xff]);
+            hasher.update((body.len() as u32).to_be_bytes());
+            let digest = hasher.finalize();
+
+            body.extend((unhashed_area.len() as u16).to_be_bytes());
+            body.extend(unhashed_area);
+            body.extend(&digest[..2]);
+            body.extend(self.secret.sign(&digest).to_bytes());
+            body
+        }
+
+        fn hash_data(&self) -> Vec<u8> {
+            key_hash_data(&self.body)
+        }
+    }
+
+    pub(crate) const USER_ID: &[u8] = b"Test Key <test@example.com>";
+
+    /// A certificate of `primary` with one user id, whose self-signature
+    /// carries the `hashed` subpackets
+    pub(crate) fn certificate_bytes(primary: &TestKey, hashed: &[Vec<u8>]) -> Vec<u8> {
+        let mut user_id_data = vec![0xb4];
+        user_id_data.extend((USER_ID.len() as u32).to_be_bytes());
+        user_id_data.extend(USER_ID);
+        let certification = primary.sign(0x13, CREATED, hashed, &[], &[&primary.hash_data(), &user_id_data]);
+
+        let mut bytes = packet(TAG_PUBLIC_KEY, &primary.body);
+        bytes.extend(packet(TAG_USER_ID, USER_ID));
+        bytes.extend(packet(TAG_SIGNATURE, &certification));
+        bytes
+    }
+
+    /// `subkey` with a binding signature from `signer` allowing `flags`,
+    /// and a back signature if `back_signed`
+    fn subkey_bytes(primary: &TestKey, signer: &TestKey, subkey: &TestKey, flags: u8, back_signed: bool) -> Vec<u8> {
+        let data = [primary.hash_data(), subkey.hash_data()];
+        let data = [data[0].as_slice(), data[1].as_slice()];
+        let mut unhashed = Vec::new();
+        if back_signed {
+            let back = subkey.sign(SIG_PRIMARY_KEY_BINDING, CREATED, &[], &[], &data);
+            unhashed.push([vec![back.len() as u8 + 1, SUBPACKET_EMBEDDED_SIGNATURE], back].concat());
+        }
+        let binding = signer.sign(SIG_SUBKEY_BINDING, CREATED, &[subpacket(SUBPACKET_KEY_FLAGS, &[flags])], &unhashed, &data);
+        let mut bytes = packet(TAG_PUBLIC_SUBKEY, &subkey.body);
+        bytes.extend(packet(TAG_SIGNATURE, &binding));
+        bytes
+    }
+
+    fn armor(data: &[u8]) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+        let lines: Vec<&str> = encoded.as_bytes().chunks(64).map(|line| std::str::from_utf8(line).unwrap()).collect();
+        format!("{}\nVersion: test\n\n{}\n=AAAA\n{}\n", ARMOR_BEGIN, lines.join("\n"), ARMOR_END)
+    }
+
+    #[test]
+    fn certificates_are_parsed() {
+        let primary = TestKey::new(1, CREATED);
+        let bytes = certificate_bytes(&primary, &[]);
+
+        for data in [bytes.clone(), armor(&bytes).into_bytes()] {
+            let certificates = parse_certificates(&data).unwrap();
+            let [certificate] = certificates.as_slice() else { panic!("one certificate expected") };
+            assert_eq!(certificate.user_id(), "Test Key <test@example.com>");
+            assert_eq!(certificate.primary.fingerprint, primary.fingerprint);
+            assert_eq!(certificate.primary.created, CREATED);
+            assert_eq!(certificate.primary.expires, None);
+            assert_eq!(certificate.data, bytes);
+            assert_eq!(certificate.name(), format!("gpg-pubkey-{:08x}-{:08x}", certificate.primary.key_id() as u32, CREATED));
+            assert!(certificate.matches(&certificate.name()));
+            assert!(certificate.matches(&format!("0x{:08X}", certificate.primary.key_id() as u32)));
+            assert!(certificate.matches(&certificate.primary.fingerprint_hex().to_lowercase()));
+            assert!(!certificate.matches("0x1234"));
+        }
+
+        let two = [bytes.clone(), certificate_bytes(&TestKey::new(2, CREATED), &[])].concat();
+        assert_eq!(parse_certificates(&two).unwrap().len(), 2);
+        assert!(parse_certificates(&packet(TAG_USER_ID, USER_ID)).is_err());
+        assert!(parse_certificates(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse_certificates(b"").is_err());
+    }
+
+    #[test]
+    fn subkeys_need_binding_and_back_signatures() {
+        let primary = TestKey::new(1, CREATED);
+        let other = TestKey::new(2, CREATED);
+        let subkeys: Vec<TestKey> = (10..15).map(|seed| TestKey::new(seed, CREATED)).collect();
+        let mut bytes = certificate_bytes(&primary, &[]);
+        bytes.extend(subkey_bytes(&primary, &primary, &subkeys[0], KEY_FLAG_SIGN, true));
+        bytes.extend(subkey_bytes(&primary, &other, &subkeys[1], KEY_FLAG_SIGN, true)); // Bound by another key
+        bytes.extend(subkey_bytes(&primary, &primary, &subkeys[2], KEY_FLAG_SIGN, false)); // No back signature
+        bytes.extend(subkey_bytes(&primary, &primary, &subkeys[3], 0x0c, false)); // Encryption only
+        bytes.extend(packet(TAG_PUBLIC_SUBKEY, &subkeys[4].body)); // Not bound at all
+
+        let certificate = parse_certificates(&bytes).unwrap().remove(0);
+        let fingerprints: Vec<[u8; 20]> = certificate.subkeys.iter().map(|key| key.fingerprint).collect();
+        assert_eq!(fingerprints, [subkeys[0].fingerprint]);
+        assert_eq!(certificate.keys().count(), 2);
+    }
+
+    #[test]
+    fn keys_expire_and_are_revoked() {
+        let primary = TestKey::new(1, CREATED);
+        let subkey = TestKey::new(10, CREATED);
+        let mut bytes = certificate_bytes(&primary, &[subpacket(SUBPACKET_KEY_EXPIRATION, &1000u32.to_be_bytes())]);
+        bytes.extend(subkey_bytes(&primary, &primary, &subkey, KEY_FLAG_SIGN, true));
+
+        let certificate = parse_certificates(&bytes).unwrap().remove(0);
+        assert_eq!(certificate.primary.expires, Some(CREATED + 1000));
+        // A subkey does not outlive its primary key
+        assert_eq!(certificate.subkeys[0].expires, Some(CREATED + 1000));
+        assert!(certificate.primary.check_usable(CREATED + 10).is_ok());
+        assert!(certificate.primary.check_usable(CREATED + 1000).unwrap_err().to_string().contains("expired"));
+        assert!(certificate.primary.check_usable(CREATED - 1).unwrap_err().to_string().contains("predates"));
+
+        // Revocations only count when made by the key itself. Signatures
+        // after a subkey are about the subkey, so these go first.
+        let primary_packet = packet(TAG_PUBLIC_KEY, &primary.body);
+        let revoked_by = |key: &TestKey| {
+            let revocation = key.sign(SIG_KEY_REVOCATION, CREATED, &[], &[], &[&primary.hash_data()]);
+            [primary_packet.clone(), packet(TAG_SIGNATURE, &revocation), bytes[primary_packet.len()..].to_vec()].concat()
+        };
+        let with_forged = revoked_by(&TestKey::new(2, CREATED));
+        let revoked = revoked_by(&primary);
+
+        assert!(!parse_certificates(&with_forged).unwrap()[0].primary.revoked);
+        let certificate = parse_certificates(&revoked).unwrap().remove(0);
+        assert!(certificate.primary.revoked);
+        assert!(certificate.subkeys[0].revoked);
+        assert!(certificate.primary.check_usable(CREATED + 10).unwrap_err().to_string().contains("revoked"));
+
+        let data = [primary.hash_data(), subkey.hash_data()].concat();
+        let subkey_revocation = primary.sign(SIG_SUBKEY_REVOCATION, CREATED, &[], &[], &[&data]);
+        let certificate = parse_certificates(&[bytes, packet(TAG_SIGNATURE, &subkey_revocation)].concat()).unwrap().remove(0);
+        assert!(!certificate.primary.revoked);
+        assert!(certificate.subkeys[0].revoked);
+    }
+
+    #[test]
+    fn signatures_verify_data() {
+        let key = TestKey::new(1, CREATED);
+        let certificates = parse_certificates(&certificate_bytes(&key, &[])).unwrap();
+        let data = b"signed data";
+        let signature = Signature::parse(&packet(TAG_SIGNATURE, &key.sign(0x00, CREATED + 5, &[], &[], &[data]))).unwrap();
+
+        assert_eq!(signature.kind, 0x00);
+        assert_eq!(signature.created, CREATED + 5);
+        assert_eq!(signature.hash, HashAlgorithm::Sha256);
+        assert_eq!(signature.issuer, Some(Issuer::Fingerprint(key.fingerprint)));
+        let found = signature.find_key(&certificates).unwrap();
+        assert_eq!(found.fingerprint, key.fingerprint);
+
+        let mut hasher = signature.hash.hasher();
+        hasher.update(data);
+        assert!(signature.verify(found, hasher).is_ok());
+        let mut hasher = signature.hash.hasher();
+        hasher.update(b"other data");
+        assert!(signature.verify(found, hasher).is_err());
+
+        let other = parse_certificates(&certificate_bytes(&TestKey::new(2, CREATED), &[])).unwrap();
+        assert!(signature.find_key(&other).is_none());
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        let key = TestKey::new(1, CREATED);
+        let body = key.sign(0x00, CREATED, &[], &[], &[b"data"]);
+        let error = |body: &[u8]| Signature::parse(&packet(TAG_SIGNATURE, body)).unwrap_err().to_string();
+
+        assert!(Signature::parse(&packet(TAG_PUBLIC_KEY, &key.body)).unwrap_err().to_string().contains("Not an OpenPGP signature"));
+        assert!(Signature::parse(&packet(TAG_SIGNATURE, &body)[..20]).is_err());
+        assert!(error(&body[..body.len() - 1]).contains("Truncated"));
+
+        let mut version = body.clone();
+        version[0] = 5;
+        assert!(error(&version).contains("version 5"));
+        let mut hash = body.clone();
+        hash[3] = 1; // MD5
+        assert!(error(&hash).contains("hash algorithm 1"));
+
+        // Without a creation time in the hashed area
+        let mut no_time = vec![4, 0x00, ALGO_ED25519, 8, 0, 0, 0, 0, 0, 0];
+        no_time.extend([0; 64]);
+        assert!(error(&no_time).contains("creation time"));
+
+        let mut bad_subpacket = body.clone();
+        bad_subpacket[6] = 200; // Length of the first subpacket, past the area
+        assert!(Signature::parse(&packet(TAG_SIGNATURE, &bad_subpacket)).is_err());
+    }
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::Arc;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -12,7 +14,7 @@ pub enum PackageError {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct PackageName {
     pub name: String,
-    pub arch: String,
+    pub arch: Arc<str>, // Interned, a repository only has a handful of distinct values
 }
 
 impl PackageName {
@@ -22,7 +24,7 @@ impl PackageName {
         }
         Ok(Self {
             name: name.to_string(),
-            arch: arch.to_string(),
+            arch: Arc::from(arch),
         })
     }
     
@@ -36,7 +38,7 @@ impl PackageName {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Version {
     pub epoch: u32,
     pub version: String,
@@ -55,6 +57,7 @@ impl Version {
         })
     }
     
+    #[cfg(test)]
     pub fn parse(s: &str) -> Result<Self, PackageError> {
         // Simple version parser - you'll want to improve this
         let parts: Vec<&str> = s.split('-').collect();
@@ -74,13 +77,273 @@ impl Version {
     }
 }
 
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| rpmvercmp(&self.version, &other.version))
+            .then_with(|| rpmvercmp(&self.release, &other.release))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Compare two version or release strings the way rpm does: alternating
+/// numeric and alphabetic segments, `~` sorting before anything and `^`
+/// sorting after the base version.
+pub fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let mut a = a.as_bytes();
+    let mut b = b.as_bytes();
+
+    loop {
+        // Skip separators that are not part of any segment
+        while let Some(&c) = a.first() {
+            if c.is_ascii_alphanumeric() || c == b'~' || c == b'^' {
+                break;
+            }
+            a = &a[1..];
+        }
+        while let Some(&c) = b.first() {
+            if c.is_ascii_alphanumeric() || c == b'~' || c == b'^' {
+                break;
+            }
+            b = &b[1..];
+        }
+
+        // Tilde sorts before everything, even the end of the string
+        match (a.first() == Some(&b'~'), b.first() == Some(&b'~')) {
+            (true, true) => {
+                a = &a[1..];
+                b = &b[1..];
+                continue;
+            }
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+
+        // Caret sorts after the end of the string but before anything else
+        match (a.first() == Some(&b'^'), b.first() == Some(&b'^')) {
+            (true, true) => {
+                a = &a[1..];
+                b = &b[1..];
+                continue;
+            }
+            (true, false) => {
+                return if b.is_empty() { Ordering::Greater } else { Ordering::Less };
+            }
+            (false, true) => {
+                return if a.is_empty() { Ordering::Less } else { Ordering::Greater };
+            }
+            (false, false) => {}
+        }
+
+        if a.is_empty() || b.is_empty() {
+            break;
+        }
+
+        let numeric = a[0].is_ascii_digit();
+        let segment_len = |s: &[u8]| {
+            s.iter()
+                .take_while(|c| if numeric { c.is_ascii_digit() } else { c.is_ascii_alphabetic() })
+                .count()
+        };
+        let (seg_a, rest_a) = a.split_at(segment_len(a));
+        let (seg_b, rest_b) = b.split_at(segment_len(b));
+
+        // Segments of different types: numeric is always newer
+        if seg_b.is_empty() {
+            return if numeric { Ordering::Greater } else { Ordering::Less };
+        }
+
+        let ordering = if numeric {
+            let trim = |s: &[u8]| s.iter().take_while(|&&c| c == b'0').count();
+            let seg_a = &seg_a[trim(seg_a)..];
+            let seg_b = &seg_b[trim(seg_b)..];
+            seg_a.len().cmp(&seg_b.len()).then_with(|| seg_a.cmp(seg_b))
+        } else {
+            seg_a.cmp(seg_b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        a = rest_a;
+        b = rest_b;
+    }
+
+    if a.is_empty() && b.is_empty() {
+        Ordering::Equal
+    } else if a.is_empty() {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dependency {
-    pub name: String,
+    pub name: Arc<str>, // Interned, the same names recur across thousands of packages
     pub version: Option<String>,
     pub comparator: Option<String>, // ">", ">=", "=", etc.
 }
 
+impl Dependency {
+    /// Whether the package `name` at `version` satisfies this dependency.
+    /// Only the package name counts, not what it provides, as for triggers.
+    /// A version without a release matches any release.
+    pub fn matches_package(&self, name: &str, version: &Version) -> bool {
+        if *self.name != *name {
+            return false;
+        }
+        let (comparator, wanted) = match (&self.comparator, &self.version) {
+            (Some(comparator), Some(wanted)) => (comparator, wanted),
+            _ => return true,
+        };
+        let (epoch, rest) = match wanted.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, wanted.as_str()),
+        };
+        let (wanted_version, wanted_release) = rest.split_once('-').unwrap_or((rest, ""));
+        let mut ordering = version.epoch.cmp(&epoch).then_with(|| rpmvercmp(&version.version, wanted_version));
+        if !wanted_release.is_empty() {
+            ordering = ordering.then_with(|| rpmvercmp(&version.release, wanted_release));
+        }
+        match comparator.as_str() {
+            "=" => ordering == Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            ">=" => ordering != Ordering::Less,
+            _ => false,
+        }
+    }
+}
+
+/// One file of a package, as its rpm header describes it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub mode: u16,
+    pub user: String,
+    pub group: String,
+    pub size: u64,
+    pub mtime: u32,
+    pub digest: String,  // Hex, by the package's `file_digest_algo`; empty for non-regular files
+    pub link_to: String, // Target of a symlink
+    pub flags: u32,      // RPMFILE_CONFIG, RPMFILE_NOREPLACE, ...
+    pub inode: u32,      // Regular files sharing inode and device are hardlinks
+    pub device: u32,
+    pub rdev: u16,
+    pub caps: String,    // File capabilities in cap_from_text(3) form
+    pub lang: String,
+    #[serde(default)]
+    pub color: u32,      // 1 for 32-bit ELF files, 2 for 64-bit ones, 0 for the rest
+}
+
+/// When a scriptlet runs relative to the installation of its package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScriptletKind {
+    PreTrans,
+    Pre,
+    Post,
+    PreUn,
+    PostUn,
+    PostTrans,
+}
+
+impl ScriptletKind {
+    /// The spec file section, e.g. "%post"
+    pub fn name(self) -> &'static str {
+        match self {
+            ScriptletKind::PreTrans => "%pretrans",
+            ScriptletKind::Pre => "%pre",
+            ScriptletKind::Post => "%post",
+            ScriptletKind::PreUn => "%preun",
+            ScriptletKind::PostUn => "%postun",
+            ScriptletKind::PostTrans => "%posttrans",
+        }
+    }
+}
+
+/// A script from an rpm header and what runs it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Script {
+    pub interpreter: Vec<String>, // Program and arguments, e.g. ["/bin/sh"]
+    pub body: Option<String>,     // None for `-p <program>` scriptlets
+    pub flags: u32,               // RPMSCRIPT_FLAG_EXPAND, ...
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scriptlet {
+    pub kind: ScriptletKind,
+    pub script: Script,
+}
+
+/// When a trigger fires relative to the package that sets it off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TriggerKind {
+    PreIn,
+    In,
+    Un,
+    PostUn,
+}
+
+impl TriggerKind {
+    /// The end of the spec file section, e.g. "in" for %triggerin
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TriggerKind::PreIn => "prein",
+            TriggerKind::In => "in",
+            TriggerKind::Un => "un",
+            TriggerKind::PostUn => "postun",
+        }
+    }
+}
+
+/// A %trigger script, run when a package matching any of `conditions` is
+/// installed or removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trigger {
+    pub kind: TriggerKind,
+    pub conditions: Vec<Dependency>,
+    pub script: Script,
+}
+
+/// A %filetrigger or %transfiletrigger script, run for packages that
+/// install or remove files under any of `prefixes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileTrigger {
+    pub kind: TriggerKind,
+    pub transaction: bool, // Runs once per transaction rather than per package
+    pub prefixes: Vec<String>,
+    pub priority: u32,
+    pub script: Script,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub time: u32, // Seconds since the epoch
+    pub author: String,
+    pub text: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Package {
     pub name: PackageName,
@@ -88,12 +351,39 @@ pub struct Package {
     pub description: String,
     pub summary: String,
     pub dependencies: Vec<Dependency>,
-    pub conflicts: Vec<String>,
-    pub provides: Vec<String>,
+    pub conflicts: Vec<Dependency>,
+    pub provides: Vec<Dependency>,
+    #[serde(default)]
+    pub obsoletes: Vec<Dependency>,
     pub files: Vec<String>,
     pub size: u64,
-    pub license: String,
+    pub license: Arc<str>,
     pub url: String,
+    #[serde(default)]
+    pub location: String, // Path of the rpm relative to its repository
+    #[serde(default)]
+    pub checksum: Option<(String, String)>, // (type, hex digest) of the rpm
+    #[serde(default)]
+    pub recommends: Vec<Dependency>,
+    #[serde(default)]
+    pub suggests: Vec<Dependency>,
+    #[serde(default)]
+    pub supplements: Vec<Dependency>,
+    #[serde(default)]
+    pub enhances: Vec<Dependency>,
+    // The rest is only known from the rpm header, not from repository metadata
+    #[serde(default)]
+    pub file_entries: Vec<FileEntry>,
+    #[serde(default)]
+    pub file_digest_algo: String, // "sha256", "md5", ...
+    #[serde(default)]
+    pub scriptlets: Vec<Scriptlet>,
+    #[serde(default)]
+    pub triggers: Vec<Trigger>,
+    #[serde(default)]
+    pub file_triggers: Vec<FileTrigger>,
+    #[serde(default)]
+    pub changelog: Vec<ChangelogEntry>,
 }
 
 impl Package {
@@ -110,10 +400,138 @@ impl Package {
             dependencies: Vec::new(),
             conflicts: Vec::new(),
             provides: Vec::new(),
+            obsoletes: Vec::new(),
             files: Vec::new(),
             size: 0,
-            license: String::new(),
+            license: Arc::from(""),
             url: String::new(),
+            location: String::new(),
+            checksum: None,
+            recommends: Vec::new(),
+            suggests: Vec::new(),
+            supplements: Vec::new(),
+            enhances: Vec::new(),
+            file_entries: Vec::new(),
+            file_digest_algo: String::new(),
+            scriptlets: Vec::new(),
+            triggers: Vec::new(),
+            file_triggers: Vec::new(),
+            changelog: Vec::new(),
         }
     }
-}
\ No newline at end of file
+    
+    pub fn scriptlet(&self, kind: ScriptletKind) -> Option<&Script> {
+        self.scriptlets.iter().find(|scriptlet| scriptlet.kind == kind).map(|scriptlet| &scriptlet.script)
+    }
+    
+    /// Full name-epoch:version-release.arch string, always with the epoch
+    pub fn nevra(&self) -> String {
+        format!("{}-{}:{}-{}.{}",
+            self.name.name, self.version.epoch, self.version.version, self.version.release, self.name.arch)
+    }
+}
+
+/// Parse a `name-[epoch:]version-release.arch` string
+pub fn parse_nevra(s: &str) -> Result<(PackageName, Version), PackageError> {
+    let invalid = || PackageError::InvalidName(s.to_string());
+    
+    let (rest, arch) = s.rsplit_once('.').ok_or_else(invalid)?;
+    let (rest, release) = rest.rsplit_once('-').ok_or_else(invalid)?;
+    let (name, evr) = rest.rsplit_once('-').ok_or_else(invalid)?;
+    let (epoch, version) = match evr.split_once(':') {
+        Some((epoch, version)) => (epoch.parse().map_err(|_| PackageError::InvalidVersion(evr.to_string()))?, version),
+        None => (0, evr),
+    };
+    
+    Ok((PackageName::new(name, arch)?, Version::new(epoch, version, release)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(epoch: u32, version: &str, release: &str) -> Version {
+        Version::new(epoch, version, release).unwrap()
+    }
+
+    #[test]
+    fn versions_compare_like_rpm() {
+        use Ordering::*;
+        // Cases from rpm's own rpmvercmp tests
+        let cases = [
+            ("1.0", "1.0", Equal),
+            ("1.0", "2.0", Less),
+            ("2.0.1", "2.0", Greater),
+            ("2.0.1a", "2.0.1", Greater),
+            ("5.5p1", "5.5p2", Less),
+            ("5.5p10", "5.5p1", Greater),
+            ("10xyz", "10.1xyz", Less),
+            ("xyz10", "xyz10.1", Less),
+            ("a", "b", Less),
+            ("1.0", "1", Greater),
+            ("001", "1", Equal),
+            ("fc4", "fc.4", Equal),
+            ("2a", "2.0", Less),
+            ("1.0", "1.0~rc1", Greater),
+            ("1.0~rc1", "1.0~rc2", Less),
+            ("1.0~rc1~git123", "1.0~rc1", Less),
+            ("1.0^", "1.0", Greater),
+            ("1.0^git1", "1.01", Less),
+            ("1.0^git1", "1.0.1", Less),
+            ("1.0^20160101^git1", "1.0^20160101", Greater),
+            ("1.0~rc1^git1", "1.0~rc1", Greater),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(rpmvercmp(a, b), expected, "{} vs {}", a, b);
+            assert_eq!(rpmvercmp(b, a), expected.reverse(), "{} vs {}", b, a);
+        }
+    }
+
+    #[test]
+    fn versions_order_by_epoch_then_version_then_release() {
+        assert!(version(1, "1.0", "1") > version(0, "2.0", "1"));
+        assert!(version(0, "1.10", "1") > version(0, "1.9", "5"));
+        assert!(version(0, "1.0", "2") > version(0, "1.0", "1.fc39"));
+        assert_eq!(version(0, "1.0", "01"), version(0, "1.0", "1"));
+        assert!(Version::new(0, "", "1").is_err());
+    }
+
+    #[test]
+    fn nevras_are_parsed() {
+        let (name, version) = parse_nevra("bash-5.2.15-5.fc39.x86_64").unwrap();
+        assert_eq!((name.name.as_str(), &*name.arch), ("bash", "x86_64"));
+        assert_eq!((version.epoch, version.version.as_str(), version.release.as_str()), (0, "5.2.15", "5.fc39"));
+
+        let (name, version) = parse_nevra("perl-Data-Dumper-1:2.188-501.fc39.noarch").unwrap();
+        assert_eq!((name.name.as_str(), &*name.arch), ("perl-Data-Dumper", "noarch"));
+        assert_eq!((version.epoch, version.version.as_str(), version.release.as_str()), (1, "2.188", "501.fc39"));
+
+        assert!(matches!(parse_nevra("bash"), Err(PackageError::InvalidName(_))));
+        assert!(matches!(parse_nevra("bash-5.2.x86_64"), Err(PackageError::InvalidName(_))));
+        assert!(matches!(parse_nevra("bash-x:5.2-1.x86_64"), Err(PackageError::InvalidVersion(_))));
+        assert!(matches!(parse_nevra("-5.2-1.x86_64"), Err(PackageError::InvalidName(_))));
+    }
+
+    #[test]
+    fn dependencies_match_package_versions() {
+        let dependency = |name: &str, comparator: Option<&str>, version: Option<&str>| Dependency {
+            name: Arc::from(name),
+            version: version.map(str::to_string),
+            comparator: comparator.map(str::to_string),
+        };
+        let bash = version(0, "5.2.15", "5.fc39");
+
+        assert!(dependency("bash", None, None).matches_package("bash", &bash));
+        assert!(!dependency("bash", None, None).matches_package("zsh", &bash));
+        assert!(dependency("bash", Some("="), Some("5.2.15")).matches_package("bash", &bash));
+        assert!(dependency("bash", Some("="), Some("5.2.15-5.fc39")).matches_package("bash", &bash));
+        assert!(!dependency("bash", Some("="), Some("5.2.15-6.fc39")).matches_package("bash", &bash));
+        assert!(dependency("bash", Some("<"), Some("5.2.15-6")).matches_package("bash", &bash));
+        assert!(dependency("bash", Some("<="), Some("5.2.15")).matches_package("bash", &bash));
+        assert!(!dependency("bash", Some(">"), Some("5.2.15")).matches_package("bash", &bash));
+        assert!(dependency("bash", Some(">="), Some("5.2")).matches_package("bash", &bash));
+        assert!(!dependency("bash", Some(">="), Some("1:1.0")).matches_package("bash", &bash));
+        assert!(dependency("bash", Some("<"), Some("1:1.0")).matches_package("bash", &bash));
+        assert!(!dependency("bash", Some("~"), Some("5.2.15")).matches_package("bash", &bash));
+    }
+}
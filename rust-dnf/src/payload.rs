@@ -0,0 +1,746 @@
+use crate::checksum;
+use crate::compression;
+use crate::config_files::ConfigAction;
+use crate::package::{FileEntry, Package};
+use crate::rpm;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const CPIO_MAGIC: &[u8] = b"070701";
+const CPIO_MAGIC_CRC: &[u8] = b"070702";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+/// PATH_MAX, the longest member name or symlink target a payload may have
+const CPIO_MAX_PATH: u32 = 4096;
+
+// File types of st_mode
+pub(crate) const S_IFMT: u32 = 0o170000;
+pub(crate) const S_IFDIR: u32 = 0o040000;
+pub(crate) const S_IFREG: u32 = 0o100000;
+pub(crate) const S_IFLNK: u32 = 0o120000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFIFO: u32 = 0o010000;
+
+/// Capability names by number, as cap_to_text(3) writes them
+const CAPABILITIES: &[&str] = &[
+    "chown", "dac_override", "dac_read_search", "fowner", "fsetid", "kill", "setgid", "setuid", "setpcap",
+    "linux_immutable", "net_bind_service", "net_broadcast", "net_admin", "net_raw", "ipc_lock", "ipc_owner",
+    "sys_module", "sys_rawio", "sys_chroot", "sys_ptrace", "sys_pacct", "sys_admin", "sys_boot", "sys_nice",
+    "sys_resource", "sys_time", "sys_tty_config", "mknod", "lease", "audit_write", "audit_control", "setfcap",
+    "mac_override", "mac_admin", "syslog", "wake_alarm", "block_suspend", "audit_read", "perfmon", "bpf",
+    "checkpoint_restore",
+];
+const VFS_CAP_REVISION_2: u32 = 0x0200_0000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x1;
+
+/// File color that wins where multilib packages ship the same path, as
+/// rpm prefers 64-bit files on 64-bit systems
+const PREFERRED_COLOR: u32 = 2;
+
+/// Distinguishes the temporary files of concurrent writes to one path
+static TEMP_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// One member of a cpio archive in the "new ASCII" format rpm uses
+#[derive(Debug)]
+struct CpioEntry {
+    name: String,
+    mode: u32,
+    inode: u32,
+    nlink: u32,
+    size: u64,
+}
+
+/// Reads a cpio archive, keeping track of the 4-byte alignment its headers
+/// and data are padded to
+struct CpioReader<R: Read> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> CpioReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.inner
+            .read_exact(buf)
+            .map_err(|e| anyhow::anyhow!("Truncated rpm payload: {}", e))?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn skip(&mut self, mut count: u64) -> Result<()> {
+        let mut buf = [0; 8192];
+        while count > 0 {
+            let chunk = count.min(buf.len() as u64) as usize;
+            self.read_exact(&mut buf[..chunk])?;
+            count -= chunk as u64;
+        }
+        Ok(())
+    }
+
+    fn align(&mut self) -> Result<()> {
+        self.skip((4 - self.position % 4) % 4)
+    }
+
+    /// The next member's header, or None at the trailer
+    fn next_entry(&mut self) -> Result<Option<CpioEntry>> {
+        let mut header = [0; CPIO_HEADER_SIZE];
+        self.read_exact(&mut header)?;
+        if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_MAGIC_CRC {
+            anyhow::bail!("Bad cpio header in rpm payload");
+        }
+        let field = |i: usize| -> Result<u32> {
+            let hex = std::str::from_utf8(&header[6 + i * 8..14 + i * 8])?;
+            Ok(u32::from_str_radix(hex, 16)?)
+        };
+        let (inode, mode, nlink, size, name_size) = (field(0)?, field(1)?, field(4)?, field(6)?, field(11)?);
+        if name_size > CPIO_MAX_PATH {
+            anyhow::bail!("cpio member name of {} bytes in rpm payload", name_size);
+        }
+
+        let mut name = vec![0; name_size as usize];
+        self.read_exact(&mut name)?;
+        self.align()?;
+        name.pop(); // The terminating NUL
+        let name = String::from_utf8_lossy(&name).into_owned();
+        if name == CPIO_TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(CpioEntry { name, mode, inode, nlink, size: u64::from(size) }))
+    }
+
+    /// The data of the current member, which must be read to its end
+    fn data(&mut self, size: u64) -> impl Read + '_ {
+        (&mut self.inner).take(size)
+    }
+
+    /// Account for data read through `data`, and skip its padding
+    fn finish_data(&mut self, size: u64) -> Result<()> {
+        self.position += size;
+        self.align()
+    }
+}
+
+/// User and group ids by name, from the passwd and group files of the
+/// installation root, which is what the files will be looked up in
+struct Owners {
+    users: HashMap<String, u32>,
+    groups: HashMap<String, u32>,
+}
+
+impl Owners {
+    fn load(root: &Path) -> Self {
+        let ids = |file: &str| -> HashMap<String, u32> {
+            let mut ids = HashMap::from([("root".to_string(), 0)]);
+            if let Ok(content) = fs::read_to_string(root.join("etc").join(file)) {
+                for line in content.lines() {
+                    let fields: Vec<&str> = line.split(':').collect();
+                    if let (Some(name), Some(Ok(id))) = (fields.first(), fields.get(2).map(|id| id.parse())) {
+                        ids.insert(name.to_string(), id);
+                    }
+                }
+            }
+            ids
+        };
+        Self { users: ids("passwd"), groups: ids("group") }
+    }
+
+    /// Ids for `file`, or root's for names the root does not know, as rpm does
+    fn ids(&self, file: &FileEntry) -> (u32, u32) {
+        let uid = self.users.get(&file.user).copied().unwrap_or_else(|| {
+            log::warn!("User {} does not exist, using root for {}", file.user, file.path);
+            0
+        });
+        let gid = self.groups.get(&file.group).copied().unwrap_or_else(|| {
+            log::warn!("Group {} does not exist, using root for {}", file.group, file.path);
+            0
+        });
+        (uid, gid)
+    }
+}
+
+/// Writes the members of one payload under the installation root
+struct Extractor<'a> {
+    root: &'a Path,
+    package: &'a Package,
+    files: HashMap<&'a str, &'a FileEntry>,
+    owners: Owners,
+    privileged: bool, // Ownership and capabilities can only be set by root
+    digest_algo: Option<&'a str>,
+    config: &'a HashMap<String, ConfigAction>, // %config files already on disk that are not simply replaced
+    written: Vec<String>,
+    pending_links: HashMap<u32, Vec<&'a FileEntry>>, // Hardlinks whose data comes with a later member
+    directories: Vec<(&'a FileEntry, PathBuf)>,      // Their times are set once nothing more is written into them
+}
+
+impl<'a> Extractor<'a> {
+    fn target(&self, path: &str) -> Result<PathBuf> {
+        root_path(self.root, path)
+    }
+
+    fn extract<R: Read>(&mut self, cpio: &mut CpioReader<R>) -> Result<()> {
+        while let Some(entry) = cpio.next_entry()? {
+            let path = entry.name.trim_start_matches('.');
+            let file = *self
+                .files
+                .get(path)
+                .ok_or_else(|| anyhow::anyhow!("{} is in the payload of {} but not in its header", path, self.package.nevra()))?;
+            if entry.mode & S_IFMT != u32::from(file.mode) & S_IFMT {
+                anyhow::bail!("{} has a different file type in the payload of {} than in its header", path, self.package.nevra());
+            }
+            let target = self.target(&file.path)?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            match u32::from(file.mode) & S_IFMT {
+                S_IFREG if entry.nlink > 1 && entry.size == 0 => {
+                    // cpio stores the data of a set of hardlinks with its last member
+                    self.pending_links.entry(entry.inode).or_default().push(file);
+                }
+                S_IFREG => {
+                    {
+                        let mut data = cpio.data(entry.size);
+                        self.write_file(file, &target, &mut data)?;
+                        std::io::copy(&mut data, &mut std::io::sink())?;
+                    }
+                    cpio.finish_data(entry.size)?;
+                    for link in self.pending_links.remove(&entry.inode).unwrap_or_default() {
+                        self.write_hardlink(link, &target)?;
+                    }
+                }
+                S_IFDIR => {
+                    cpio.skip(entry.size)?;
+                    cpio.align()?;
+                    // Not is_dir(), a symlink to a directory elsewhere would
+                    // have its mode and times changed
+                    match fs::symlink_metadata(&target) {
+                        Ok(metadata) if metadata.is_dir() => {}
+                        Ok(_) => anyhow::bail!("{} is not a directory", target.display()),
+                        Err(_) => fs::create_dir(&target)?,
+                    }
+                    self.set_metadata(file, &target)?;
+                    self.directories.push((file, target));
+                    self.written.push(file.path.clone());
+                }
+                S_IFLNK => {
+                    if entry.size > u64::from(CPIO_MAX_PATH) {
+                        anyhow::bail!("{} in {} links to a target of {} bytes", file.path, self.package.nevra(), entry.size);
+                    }
+                    let mut link_to = vec![0; entry.size as usize];
+                    cpio.read_exact(&mut link_to)?;
+                    cpio.align()?;
+                    let link_to = String::from_utf8_lossy(&link_to).into_owned();
+                    self.replace(file, &target, |tmp| Ok(std::os::unix::fs::symlink(&link_to, tmp)?))?;
+                }
+                S_IFCHR | S_IFBLK | S_IFIFO => {
+                    cpio.skip(entry.size)?;
+                    cpio.align()?;
+                    self.replace(file, &target, |tmp| mknod(tmp, u32::from(file.mode), u64::from(file.rdev)))?;
+                }
+                _ => anyhow::bail!("{} in {} has an unsupported file type", file.path, self.package.nevra()),
+            }
+        }
+
+        // Hardlink sets whose members all have no data
+        for (_, links) in std::mem::take(&mut self.pending_links) {
+            let (first, rest) = links.split_first().expect("pending hardlinks are never empty");
+            let target = self.target(&first.path)?;
+            self.write_file(first, &target, &mut std::io::empty())?;
+            for link in rest {
+                self.write_hardlink(link, &target)?;
+            }
+        }
+
+        for (file, target) in std::mem::take(&mut self.directories) {
+            set_times(&target, file.mtime)?;
+        }
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &FileEntry, target: &Path, data: &mut dyn Read) -> Result<()> {
+        if file.color != 0 && file.color & PREFERRED_COLOR == 0 && elf_color(target) & PREFERRED_COLOR != 0 {
+            log::debug!("Keeping the 64-bit {} instead of the one of {}", file.path, self.package.nevra());
+            self.written.push(file.path.clone());
+            return Ok(());
+        }
+        let mut target = target.to_path_buf();
+        match self.config.get(&file.path) {
+            Some(ConfigAction::Keep) => {
+                self.written.push(file.path.clone());
+                return Ok(());
+            }
+            Some(ConfigAction::CreateNew) => target.as_mut_os_string().push(".rpmnew"),
+            Some(ConfigAction::Save(suffix)) => {
+                let mut saved = target.clone().into_os_string();
+                saved.push(suffix);
+                fs::rename(&target, saved)?;
+            }
+            None => {}
+        }
+        let digest_algo = self.digest_algo.filter(|_| !file.digest.is_empty());
+        self.replace(file, &target, |tmp| {
+            let mut out = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(tmp)?;
+            std::io::copy(data, &mut out)?;
+            if let Some(algo) = digest_algo {
+                checksum::verify_file(tmp, algo, &file.digest)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_hardlink(&mut self, file: &FileEntry, existing: &Path) -> Result<()> {
+        let target = self.target(&file.path)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = temp_path(&target);
+        fs::hard_link(existing, &tmp)?;
+        fs::rename(&tmp, &target)?;
+        self.written.push(file.path.clone());
+        Ok(())
+    }
+
+    /// Create the file next to `target` with `create`, give it its metadata,
+    /// then rename it into place, so the old file is replaced atomically
+    /// and nothing half-written is ever left at `target`
+    fn replace(&mut self, file: &FileEntry, target: &Path, create: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+        let tmp = temp_path(target);
+        let result = create(&tmp)
+            .and_then(|()| self.set_metadata(file, &tmp))
+            .and_then(|()| set_times(&tmp, file.mtime))
+            .and_then(|()| Ok(fs::rename(&tmp, target)?));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp);
+            return Err(anyhow::anyhow!("Cannot install {}: {}", file.path, e));
+        }
+        self.written.push(file.path.clone());
+        Ok(())
+    }
+
+    fn set_metadata(&self, file: &FileEntry, path: &Path) -> Result<()> {
+        let symlink = u32::from(file.mode) & S_IFMT == S_IFLNK;
+        if self.privileged {
+            let (uid, gid) = self.owners.ids(file);
+            std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+        }
+        // After chown, which clears setuid and setgid bits
+        if !symlink {
+            fs::set_permissions(path, fs::Permissions::from_mode(u32::from(file.mode) & 0o7777))?;
+        }
+        if !file.caps.is_empty() {
+            if self.privileged {
+                set_capabilities(path, &file.caps)?;
+            } else {
+                log::warn!("Not running as root, {} is installed without its capabilities", file.path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the file a package names `path` is under `root`. Paths that could
+/// lead out of it are refused: those with `..` or other special parts,
+/// and those going through a directory that is a symlink, which an
+/// earlier member of the payload may have put there.
+pub fn root_path(root: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative.as_os_str().is_empty() || !relative.components().all(|part| matches!(part, Component::Normal(_))) {
+        anyhow::bail!("Refusing file path {:?}", path);
+    }
+    let target = root.join(relative);
+    let mut dir = root.to_path_buf();
+    for part in relative.parent().into_iter().flat_map(Path::components) {
+        dir.push(part);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                anyhow::bail!("Refusing {:?}, {} is a symlink", path, dir.display())
+            }
+            Ok(_) => {}
+            Err(_) => break, // Nor does anything below it exist
+        }
+    }
+    Ok(target)
+}
+
+/// The color of the ELF file at `path`, from its class, or 0 when it is no
+/// regular ELF file
+fn elf_color(path: &Path) -> u32 {
+    if !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        return 0;
+    }
+    let mut ident = [0u8; 5];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut ident)) {
+        Ok(()) if ident.starts_with(b"\x7fELF") && matches!(ident[4], 1 | 2) => u32::from(ident[4]),
+        _ => 0,
+    }
+}
+
+/// A path in the directory of `target` for writing it before the rename
+fn temp_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(";{:x}{:x}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    target.with_file_name(name)
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Set the access and modification times of `path`, not following symlinks
+fn set_times(path: &Path, mtime: u32) -> Result<()> {
+    let time = libc::timespec { tv_sec: mtime as libc::time_t, tv_nsec: 0 };
+    let times = [time, time];
+    let path_c = c_path(path)?;
+    // SAFETY: path_c is NUL-terminated and times holds the two entries utimensat reads
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path_c.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) } != 0 {
+        anyhow::bail!("Cannot set times of {:?}: {}", path, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn mknod(path: &Path, mode: u32, rdev: u64) -> Result<()> {
+    let path_c = c_path(path)?;
+    // rpm stores the old 16-bit encoding: major in the high byte
+    let dev = libc::makedev((rdev >> 8) as u32, (rdev & 0xff) as u32);
+    // SAFETY: path_c is NUL-terminated
+    if unsafe { libc::mknod(path_c.as_ptr(), mode as libc::mode_t, dev) } != 0 {
+        anyhow::bail!("Cannot create {:?}: {}", path, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Parse capabilities in cap_from_text(3) form, like "cap_net_raw=ep" or
+/// "cap_chown,cap_fowner+ep cap_kill+i", into (permitted, inheritable,
+/// effective) bit sets
+fn parse_capabilities(text: &str) -> Result<(u64, u64, u64)> {
+    let all = (1u64 << CAPABILITIES.len()) - 1;
+    let (mut permitted, mut inheritable, mut effective) = (0u64, 0u64, 0u64);
+    for clause in text.split_whitespace() {
+        let op_at = clause
+            .find(['=', '+', '-'])
+            .ok_or_else(|| anyhow::anyhow!("Bad capability clause {}", clause))?;
+        let names = &clause[..op_at];
+        let mut caps = 0u64;
+        for name in names.split(',').filter(|name| !name.is_empty()) {
+            let name = name.to_lowercase();
+            if name == "all" {
+                caps = all;
+                continue;
+            }
+            let bare = name.strip_prefix("cap_").unwrap_or(&name);
+            let bit = CAPABILITIES
+                .iter()
+                .position(|&cap| cap == bare)
+                .ok_or_else(|| anyhow::anyhow!("Unknown capability {}", name))?;
+            caps |= 1 << bit;
+        }
+        // "=ep" with no names means all capabilities
+        if names.is_empty() {
+            caps = all;
+        }
+
+        let mut rest = &clause[op_at..];
+        while let Some(op) = rest.chars().next() {
+            let flags_end = rest[1..].find(['=', '+', '-']).map(|i| i + 1).unwrap_or(rest.len());
+            let flags = &rest[1..flags_end];
+            if op == '=' {
+                permitted &= !caps;
+                inheritable &= !caps;
+                effective &= !caps;
+            }
+            for flag in flags.chars() {
+                let set = match flag {
+                    'p' => &mut permitted,
+                    'i' => &mut inheritable,
+                    'e' => &mut effective,
+                    _ => anyhow::bail!("Bad capability flag {} in {}", flag, clause),
+                };
+                if op == '-' {
+                    *set &= !caps;
+                } else {
+                    *set |= caps;
+                }
+            }
+            rest = &rest[flags_end..];
+        }
+    }
+    Ok((permitted, inheritable, effective))
+}
+
+/// Store file capabilities in the security.capability attribute, in the
+/// revision 2 layout the kernel reads
+fn set_capabilities(path: &Path, text: &str) -> Result<()> {
+    let (permitted, inheritable, effective) = parse_capabilities(text)?;
+    let magic = VFS_CAP_REVISION_2 | if effective != 0 { VFS_CAP_FLAGS_EFFECTIVE } else { 0 };
+    let mut value = Vec::with_capacity(20);
+    for word in [magic, permitted as u32, inheritable as u32, (permitted >> 32) as u32, (inheritable >> 32) as u32] {
+        value.extend_from_slice(&word.to_le_bytes());
+    }
+
+    let path_c = c_path(path)?;
+    let name = CString::new("security.capability")?;
+    // SAFETY: both strings are NUL-terminated and value outlives the call
+    let result = unsafe { libc::lsetxattr(path_c.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    if result != 0 {
+        anyhow::bail!("Cannot set capabilities {} on {:?}: {}", text, path, std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Unpack the payload of the rpm at `path` under `root`, giving each file
+/// the mode, ownership, times and capabilities `package`'s header lists,
+/// and treating %config files as `config` says. Returns the paths the
+/// package now owns, as it names them.
+pub fn extract(path: &Path, package: &Package, root: &Path, config: &HashMap<String, ConfigAction>) -> Result<Vec<String>> {
+    let rpm_file = rpm::read(path)?;
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(rpm_file.payload_offset))?;
+    let mut cpio = CpioReader::new(compression::decoder(BufReader::new(file))?);
+
+    let digest_algo = Some(package.file_digest_algo.as_str()).filter(|algo| checksum::supported(algo));
+    // SAFETY: geteuid has no preconditions
+    let privileged = unsafe { libc::geteuid() } == 0;
+    let mut extractor = Extractor {
+        root,
+        package,
+        files: package.file_entries.iter().map(|file| (file.path.as_str(), file)).collect(),
+        owners: Owners::load(root),
+        privileged,
+        digest_algo,
+        config,
+        written: Vec::new(),
+        pending_links: HashMap::new(),
+        directories: Vec::new(),
+    };
+    extractor
+        .extract(&mut cpio)
+        .map_err(|e| anyhow::anyhow!("Unpacking {} failed: {}", package.nevra(), e))?;
+    Ok(extractor.written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A "new ASCII" cpio member, padded as rpm writes it
+    fn cpio_entry(name: &str, mode: u32, inode: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = CPIO_MAGIC.to_vec();
+        let fields = [inode, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+        for field in fields {
+            bytes.extend(format!("{:08x}", field).into_bytes());
+        }
+        bytes.extend(name.as_bytes());
+        bytes.push(0);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes.extend(data);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+
+    fn trailer() -> Vec<u8> {
+        cpio_entry(CPIO_TRAILER, 0, 0, b"")
+    }
+
+    fn file(path: &str, mode: u32) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            mode: mode as u16,
+            user: "root".to_string(),
+            group: "root".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Unpack `archive` under `root` as the payload of a package with `files`
+    fn unpack(root: &Path, files: Vec<FileEntry>, archive: &[u8]) -> Result<Vec<String>> {
+        let mut package = Package::new(
+            crate::package::PackageName::new("hello", "x86_64").unwrap(),
+            crate::package::Version::new(0, "1.0", "1").unwrap(),
+            String::new(),
+        );
+        package.file_entries = files;
+        let config = HashMap::new();
+        let mut extractor = Extractor {
+            root,
+            package: &package,
+            files: package.file_entries.iter().map(|file| (file.path.as_str(), file)).collect(),
+            owners: Owners::load(root),
+            privileged: false,
+            digest_algo: None,
+            config: &config,
+            written: Vec::new(),
+            pending_links: HashMap::new(),
+            directories: Vec::new(),
+        };
+        extractor.extract(&mut CpioReader::new(archive))?;
+        Ok(extractor.written)
+    }
+
+    #[test]
+    fn payloads_are_unpacked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = cpio_entry("./usr/share/hello", S_IFDIR | 0o755, 1, b"");
+        archive.extend(cpio_entry("./usr/share/hello/greeting", S_IFREG | 0o644, 2, b"hello"));
+        archive.extend(cpio_entry("./usr/share/hello/link", S_IFLNK | 0o777, 3, b"greeting"));
+        archive.extend(trailer());
+        let files = vec![
+            file("/usr/share/hello", S_IFDIR | 0o755),
+            file("/usr/share/hello/greeting", S_IFREG | 0o644),
+            file("/usr/share/hello/link", S_IFLNK | 0o777),
+        ];
+
+        let written = unpack(dir.path(), files, &archive).unwrap();
+        assert_eq!(written.len(), 3);
+        let hello = dir.path().join("usr/share/hello");
+        assert_eq!(fs::read_to_string(hello.join("greeting")).unwrap(), "hello");
+        assert_eq!(fs::read_link(hello.join("link")).unwrap(), Path::new("greeting"));
+        assert_eq!(fs::metadata(hello.join("greeting")).unwrap().permissions().mode() & 0o7777, 0o644);
+    }
+
+    #[test]
+    fn multilib_files_keep_the_64_bit_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let elf = |class: u8| [b"\x7fELF".as_slice(), &[class]].concat();
+        let colored = |color| FileEntry { color, ..file("/usr/bin/hello", S_IFREG | 0o755) };
+        let archive = |class| [cpio_entry("./usr/bin/hello", S_IFREG | 0o755, 1, &elf(class)), trailer()].concat();
+        let installed = || fs::read(dir.path().join("usr/bin/hello")).unwrap();
+
+        unpack(dir.path(), vec![colored(2)], &archive(2)).unwrap();
+        let written = unpack(dir.path(), vec![colored(1)], &archive(1)).unwrap();
+        assert_eq!(written, ["/usr/bin/hello"]);
+        assert_eq!(installed(), elf(2));
+
+        fs::write(dir.path().join("usr/bin/hello"), elf(1)).unwrap();
+        unpack(dir.path(), vec![colored(2)], &archive(2)).unwrap();
+        assert_eq!(installed(), elf(2));
+    }
+
+    #[test]
+    fn parent_directory_paths_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let mut archive = cpio_entry("./usr/../../escaped", S_IFREG | 0o644, 1, b"outside");
+        archive.extend(trailer());
+
+        let error = unpack(&root, vec![file("/usr/../../escaped", S_IFREG | 0o644)], &archive).unwrap_err();
+        assert!(error.to_string().contains("Refusing"));
+        assert!(!dir.path().join("escaped").exists());
+    }
+
+    #[test]
+    fn symlinked_directories_are_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, outside) = (dir.path().join("root"), dir.path().join("outside"));
+        fs::create_dir(&outside).unwrap();
+        // An earlier member plants a symlink for a later one to go through
+        let mut archive = cpio_entry("./usr/lib/evil", S_IFLNK | 0o777, 1, outside.as_os_str().as_bytes());
+        archive.extend(cpio_entry("./usr/lib/evil/payload", S_IFREG | 0o644, 2, b"outside"));
+        archive.extend(trailer());
+        let files = vec![file("/usr/lib/evil", S_IFLNK | 0o777), file("/usr/lib/evil/payload", S_IFREG | 0o644)];
+
+        let error = unpack(&root, files, &archive).unwrap_err();
+        assert!(error.to_string().contains("is a symlink"));
+        assert!(fs::read_dir(&outside).unwrap().next().is_none());
+
+        // Nor does a directory member change one that is a symlink
+        let mut archive = cpio_entry("./usr/lib/evil", S_IFDIR | 0o777, 1, b"");
+        archive.extend(trailer());
+        assert!(unpack(&root, vec![file("/usr/lib/evil", S_IFDIR | 0o777)], &archive).is_err());
+        assert_ne!(fs::metadata(&outside).unwrap().permissions().mode() & 0o7777, 0o777);
+    }
+
+    #[test]
+    fn capabilities_are_parsed() {
+        let all = (1u64 << CAPABILITIES.len()) - 1;
+        let (chown, fowner, kill, net_bind_service, net_raw, sys_admin, setfcap) =
+            (1 << 0, 1 << 3, 1 << 5, 1 << 10, 1 << 13, 1 << 21, 1 << 31);
+        let cases = [
+            ("cap_net_raw=ep", (net_raw, 0, net_raw)),
+            ("cap_chown,cap_fowner+ep cap_kill+i", (chown | fowner, kill, chown | fowner)),
+            ("CAP_NET_BIND_SERVICE=pe", (net_bind_service, 0, net_bind_service)),
+            ("cap_sys_admin+ep-e", (sys_admin, 0, 0)),
+            ("cap_net_raw=ep cap_net_raw=i", (0, net_raw, 0)),
+            ("=ep", (all, 0, all)),
+            ("all=p cap_setfcap-p", (all & !setfcap, 0, 0)),
+            ("cap_checkpoint_restore=p", (1 << 40, 0, 0)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_capabilities(text).unwrap(), expected, "{}", text);
+        }
+
+        assert!(parse_capabilities("cap_bogus=ep").is_err());
+        assert!(parse_capabilities("cap_chown").is_err());
+        assert!(parse_capabilities("cap_chown=px").is_err());
+    }
+
+    #[test]
+    fn root_paths_stay_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        assert_eq!(root_path(root, "/etc/hosts").unwrap(), root.join("etc/hosts"));
+        for path in ["", "/", "/etc/../../hosts", "../hosts", "/./etc/hosts"] {
+            assert!(root_path(root, path).is_err(), "{:?} was allowed", path);
+        }
+
+        fs::create_dir(root.join("real")).unwrap();
+        std::os::unix::fs::symlink("real", root.join("link")).unwrap();
+        assert!(root_path(root, "/real/file").is_ok());
+        assert!(root_path(root, "/link/file").is_err());
+        // The file itself may be a symlink, it is replaced rather than followed
+        assert!(root_path(root, "/link").is_ok());
+    }
+
+    #[test]
+    fn cpio_members_are_read() {
+        let mut archive = cpio_entry("./usr/bin/hello", S_IFREG | 0o755, 7, b"hello");
+        archive.extend(cpio_entry("./usr/share/hello", S_IFDIR | 0o755, 8, b""));
+        archive.extend(trailer());
+        let mut cpio = CpioReader::new(&archive[..]);
+
+        let entry = cpio.next_entry().unwrap().unwrap();
+        assert_eq!((entry.name.as_str(), entry.mode, entry.inode, entry.size), ("./usr/bin/hello", S_IFREG | 0o755, 7, 5));
+        let mut data = String::new();
+        cpio.data(entry.size).read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello");
+        cpio.finish_data(entry.size).unwrap();
+
+        let entry = cpio.next_entry().unwrap().unwrap();
+        assert_eq!(entry.name, "./usr/share/hello");
+        cpio.skip(entry.size).unwrap();
+        cpio.align().unwrap();
+        assert!(cpio.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_cpio_is_rejected() {
+        let mut bad_magic = trailer();
+        bad_magic[5] = b'9';
+        assert!(CpioReader::new(&bad_magic[..]).next_entry().unwrap_err().to_string().contains("Bad cpio header"));
+
+        let mut bad_number = trailer();
+        bad_number[6] = b'x';
+        assert!(CpioReader::new(&bad_number[..]).next_entry().is_err());
+
+        // A name size is checked before anything is allocated for it
+        let mut huge_name = trailer();
+        huge_name[6 + 11 * 8..6 + 12 * 8].copy_from_slice(b"ffffffff");
+        assert!(CpioReader::new(&huge_name[..]).next_entry().unwrap_err().to_string().contains("name of"));
+
+        let archive = cpio_entry("./usr/bin/hello", S_IFREG | 0o755, 7, b"hello");
+        assert!(CpioReader::new(&archive[..50]).next_entry().unwrap_err().to_string().contains("Truncated"));
+        let mut cpio = CpioReader::new(&archive[..archive.len() - 4]);
+        let entry = cpio.next_entry().unwrap().unwrap();
+        assert!(cpio.skip(entry.size).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use crate::package::{Dependency, Package, PackageName, Version};
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::sync::Arc;
+
+/// Hands out shared copies of strings that repeat across packages, such as
+/// architectures, licenses and dependency names.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(s);
+        self.strings.insert(Arc::clone(&interned));
+        interned
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DependencyList {
+    None,
+    Requires,
+    Provides,
+    Conflicts,
+    Obsoletes,
+    Recommends,
+    Suggests,
+    Supplements,
+    Enhances,
+}
+
+fn comparator(flags: &[u8]) -> Option<&'static str> {
+    match flags {
+        b"EQ" => Some("="),
+        b"LT" => Some("<"),
+        b"LE" => Some("<="),
+        b"GT" => Some(">"),
+        b"GE" => Some(">="),
+        _ => None,
+    }
+}
+
+/// Build a dependency from the attributes metadata uses for it: rpm's
+/// `EQ`/`GE`/... flags and a separate epoch, version and release.
+pub fn dependency(
+    name: &str,
+    flags: Option<&str>,
+    epoch: Option<String>,
+    ver: Option<String>,
+    rel: Option<String>,
+    interner: &mut Interner,
+) -> Dependency {
+    let version = ver.map(|ver| {
+        let mut evr = match epoch {
+            Some(epoch) if epoch != "0" => format!("{}:{}", epoch, ver),
+            _ => ver,
+        };
+        if let Some(rel) = rel {
+            evr.push('-');
+            evr.push_str(&rel);
+        }
+        evr
+    });
+
+    Dependency {
+        name: interner.intern(name),
+        version,
+        comparator: flags.and_then(|flags| comparator(flags.as_bytes())).map(String::from),
+    }
+}
+
+fn parse_entry(e: &BytesStart, interner: &mut Interner) -> Result<Dependency> {
+    let mut name = String::new();
+    let mut flags = None;
+    let mut epoch = None;
+    let mut ver = None;
+    let mut rel = None;
+
+    for attr in e.attributes() {
+        let attr = attr?;
+        match attr.key.as_ref() {
+            b"name" => name = attr.unescape_value()?.into_owned(),
+            b"flags" => flags = Some(attr.unescape_value()?.into_owned()),
+            b"epoch" => epoch = Some(attr.unescape_value()?.into_owned()),
+            b"ver" => ver = Some(attr.unescape_value()?.into_owned()),
+            b"rel" => rel = Some(attr.unescape_value()?.into_owned()),
+            _ => {}
+        }
+    }
+
+    Ok(dependency(&name, flags.as_deref(), epoch, ver, rel, interner))
+}
+
+/// A package whose fields are filled in as metadata is read
+pub fn empty_package(interner: &mut Interner) -> Package {
+    let mut pkg = Package::new(
+        PackageName {
+            name: String::new(),
+            arch: interner.intern(""),
+        },
+        Version {
+            epoch: 0,
+            version: String::new(),
+            release: String::new(),
+        },
+        String::new(),
+    );
+    pkg.license = interner.intern("");
+    pkg
+}
+
+/// Stream packages out of a primary.xml document. Each package is handed to
+/// `emit` as soon as its closing tag is read, so memory use is bounded by a
+/// single package rather than the whole document. Returns the number of
+/// packages parsed.
+pub fn parse_primary<R: BufRead>(
+    source: R,
+    interner: &mut Interner,
+    mut emit: impl FnMut(Package),
+) -> Result<usize> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut current: Option<Package> = None;
+    let mut current_text = String::new();
+    let mut list = DependencyList::None;
+    let mut count = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                current_text.clear();
+                match e.name().as_ref() {
+                    b"package" => current = Some(empty_package(interner)),
+                    b"checksum" => {
+                        if let (Some(pkg), Some(typ)) = (current.as_mut(), e.try_get_attribute("type")?) {
+                            pkg.checksum = Some((typ.unescape_value()?.into_owned(), String::new()));
+                        }
+                    }
+                    b"rpm:requires" => list = DependencyList::Requires,
+                    b"rpm:provides" => list = DependencyList::Provides,
+                    b"rpm:conflicts" => list = DependencyList::Conflicts,
+                    b"rpm:obsoletes" => list = DependencyList::Obsoletes,
+                    b"rpm:recommends" => list = DependencyList::Recommends,
+                    b"rpm:suggests" => list = DependencyList::Suggests,
+                    b"rpm:supplements" => list = DependencyList::Supplements,
+                    b"rpm:enhances" => list = DependencyList::Enhances,
+                    _ => {}
+                }
+            }
+
+            Event::Empty(e) => {
+                if let Some(pkg) = current.as_mut() {
+                    match e.name().as_ref() {
+                        b"version" => {
+                            for attr in e.attributes() {
+                                let attr = attr?;
+                                match attr.key.as_ref() {
+                                    b"epoch" => pkg.version.epoch = attr.unescape_value()?.parse().unwrap_or(0),
+                                    b"ver" => pkg.version.version = attr.unescape_value()?.into_owned(),
+                                    b"rel" => pkg.version.release = attr.unescape_value()?.into_owned(),
+                                    _ => {}
+                                }
+                            }
+                        }
+                        b"location" => {
+                            if let Some(href) = e.try_get_attribute("href")? {
+                                pkg.location = href.unescape_value()?.into_owned();
+                            }
+                        }
+                        b"size" => {
+                            if let Some(size) = e.try_get_attribute("package")? {
+                                pkg.size = attr_u64(&size.value);
+                            }
+                        }
+                        b"rpm:entry" => {
+                            let target = match list {
+                                DependencyList::Requires => Some(&mut pkg.dependencies),
+                                DependencyList::Provides => Some(&mut pkg.provides),
+                                DependencyList::Conflicts => Some(&mut pkg.conflicts),
+                                DependencyList::Obsoletes => Some(&mut pkg.obsoletes),
+                                DependencyList::Recommends => Some(&mut pkg.recommends),
+                                DependencyList::Suggests => Some(&mut pkg.suggests),
+                                DependencyList::Supplements => Some(&mut pkg.supplements),
+                                DependencyList::Enhances => Some(&mut pkg.enhances),
+                                DependencyList::None => None,
+                            };
+                            if let Some(target) = target {
+                                target.push(parse_entry(&e, interner)?);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            Event::Text(e) => {
+                current_text.push_str(&e.unescape()?);
+            }
+
+            Event::End(e) => {
+                let tag = e.name();
+                let tag = tag.as_ref();
+
+                if let Some(pkg) = current.as_mut() {
+                    match tag {
+                        b"name" => pkg.name.name = std::mem::take(&mut current_text),
+                        b"arch" => pkg.name.arch = interner.intern(&current_text),
+                        b"summary" => pkg.summary = std::mem::take(&mut current_text),
+                        b"description" => pkg.description = std::mem::take(&mut current_text),
+                        b"url" => pkg.url = std::mem::take(&mut current_text),
+                        b"rpm:license" => pkg.license = interner.intern(&current_text),
+                        b"file" => pkg.files.push(std::mem::take(&mut current_text)),
+                        b"checksum" => {
+                            if let Some((_, digest)) = pkg.checksum.as_mut() {
+                                *digest = std::mem::take(&mut current_text);
+                            }
+                        }
+                        b"rpm:requires" | b"rpm:provides" | b"rpm:conflicts" | b"rpm:obsoletes" | b"rpm:recommends"
+                        | b"rpm:suggests" | b"rpm:supplements" | b"rpm:enhances" => {
+                            list = DependencyList::None;
+                        }
+                        b"package" => {
+                            if let Some(pkg) = current.take() {
+                                if !pkg.name.name.is_empty() {
+                                    count += 1;
+                                    emit(pkg);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                current_text.clear();
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(count)
+}
+
+fn attr_u64(value: &[u8]) -> u64 {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Stream the packages of a filelists.xml document to `emit`. Only the
+/// name, architecture, version and complete file list of each are set.
+/// Returns the number of packages parsed.
+pub fn parse_filelists<R: BufRead>(
+    source: R,
+    interner: &mut Interner,
+    mut emit: impl FnMut(Package),
+) -> Result<usize> {
+    let mut reader = Reader::from_reader(source);
+    reader.trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut current: Option<Package> = None;
+    let mut current_text = String::new();
+    let mut count = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                current_text.clear();
+                if e.name().as_ref() == b"package" {
+                    let mut pkg = empty_package(interner);
+                    for attr in e.attributes() {
+                        let attr = attr?;
+                        match attr.key.as_ref() {
+                            b"name" => pkg.name.name = attr.unescape_value()?.into_owned(),
+                            b"arch" => pkg.name.arch = interner.intern(&attr.unescape_value()?),
+                            _ => {}
+                        }
+                    }
+                    current = Some(pkg);
+                }
+            }
+
+            Event::Empty(e) => {
+                if let (Some(pkg), b"version") = (current.as_mut(), e.name().as_ref()) {
+                    for attr in e.attributes() {
+                        let attr = attr?;
+                        match attr.key.as_ref() {
+                            b"epoch" => pkg.version.epoch = attr.unescape_value()?.parse().unwrap_or(0),
+                            b"ver" => pkg.version.version = attr.unescape_value()?.into_owned(),
+                            b"rel" => pkg.version.release = attr.unescape_value()?.into_owned(),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            Event::Text(e) => {
+                current_text.push_str(&e.unescape()?);
+            }
+
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"file" => {
+                        if let Some(pkg) = current.as_mut() {
+                            pkg.files.push(std::mem::take(&mut current_text));
+                        }
+                    }
+                    b"package" => {
+                        if let Some(pkg) = current.take() {
+                            if !pkg.name.name.is_empty() {
+                                count += 1;
+                                emit(pkg);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                current_text.clear();
+            }
+
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="3">
+<package type="rpm">
+  <name>nano</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="7.2" rel="4.fc39"/>
+  <checksum type="sha256" pkgid="YES">0123abcd</checksum>
+  <summary>A small text editor</summary>
+  <description>GNU nano is a small &amp; friendly text editor.</description>
+  <url>https://www.nano-editor.org</url>
+  <size package="631234" installed="2712345" archive="2716000"/>
+  <location href="Packages/n/nano-7.2-4.fc39.x86_64.rpm"/>
+  <format>
+    <rpm:license>GPL-3.0-or-later</rpm:license>
+    <rpm:entry name="stray"/>
+    <rpm:provides>
+      <rpm:entry name="nano" flags="EQ" epoch="0" ver="7.2" rel="4.fc39"/>
+      <rpm:entry name="nano(x86-64)" flags="EQ" epoch="2" ver="7.2"/>
+    </rpm:provides>
+    <rpm:requires>
+      <rpm:entry name="libc.so.6(GLIBC_2.34)(64bit)"/>
+      <rpm:entry name="ncurses-libs" flags="GE" ver="6.4" rel="1"/>
+      <rpm:entry name="odd" flags="XX" ver="1"/>
+    </rpm:requires>
+    <rpm:entry name="also-stray"/>
+    <rpm:recommends>
+      <rpm:entry name="nano-default-editor"/>
+    </rpm:recommends>
+    <file>/usr/bin/nano</file>
+  </format>
+</package>
+<package type="rpm">
+  <name></name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="1" rel="1"/>
+</package>
+<package type="rpm">
+  <name>vim-common</name>
+  <arch>x86_64</arch>
+  <version epoch="2" ver="9.0.2120" rel="1.fc39"/>
+  <format>
+    <rpm:license>Vim AND MIT</rpm:license>
+    <rpm:conflicts>
+      <rpm:entry name="nano" flags="LT" epoch="0" ver="1"/>
+    </rpm:conflicts>
+  </format>
+</package>
+</metadata>
+"#;
+
+    fn parse(xml: &str) -> (Vec<Package>, usize, Interner) {
+        let mut interner = Interner::default();
+        let mut packages = Vec::new();
+        let count = parse_primary(xml.as_bytes(), &mut interner, |pkg| packages.push(pkg)).unwrap();
+        (packages, count, interner)
+    }
+
+    fn deps(list: &[Dependency]) -> Vec<(&str, Option<&str>, Option<&str>)> {
+        list.iter().map(|dep| (&*dep.name, dep.comparator.as_deref(), dep.version.as_deref())).collect()
+    }
+
+    #[test]
+    fn primary_packages_are_read() {
+        let (packages, count, _) = parse(PRIMARY);
+        assert_eq!(count, 2);
+        let nano = &packages[0];
+        assert_eq!(nano.nevra(), "nano-0:7.2-4.fc39.x86_64");
+        assert_eq!(nano.checksum, Some(("sha256".to_string(), "0123abcd".to_string())));
+        assert_eq!(nano.summary, "A small text editor");
+        assert_eq!(nano.description, "GNU nano is a small & friendly text editor.");
+        assert_eq!(nano.url, "https://www.nano-editor.org");
+        assert_eq!(nano.size, 631234);
+        assert_eq!(nano.location, "Packages/n/nano-7.2-4.fc39.x86_64.rpm");
+        assert_eq!(&*nano.license, "GPL-3.0-or-later");
+        assert_eq!(nano.files, ["/usr/bin/nano"]);
+
+        let vim = &packages[1];
+        assert_eq!(vim.nevra(), "vim-common-2:9.0.2120-1.fc39.x86_64");
+        assert_eq!((vim.checksum.as_ref(), vim.size), (None, 0));
+        assert_eq!(deps(&vim.conflicts), [("nano", Some("<"), Some("1"))]);
+    }
+
+    #[test]
+    fn dependencies_join_epoch_version_and_release() {
+        let (packages, _, _) = parse(PRIMARY);
+        let nano = &packages[0];
+        assert_eq!(deps(&nano.provides), [("nano", Some("="), Some("7.2-4.fc39")), ("nano(x86-64)", Some("="), Some("2:7.2"))]);
+        assert_eq!(
+            deps(&nano.dependencies),
+            [("libc.so.6(GLIBC_2.34)(64bit)", None, None), ("ncurses-libs", Some(">="), Some("6.4-1")), ("odd", None, Some("1"))]
+        );
+        // Entries outside of a dependency list belong to none
+        assert_eq!(deps(&nano.recommends), [("nano-default-editor", None, None)]);
+        assert!(nano.conflicts.is_empty() && nano.obsoletes.is_empty() && nano.suggests.is_empty());
+    }
+
+    #[test]
+    fn repeated_strings_are_interned() {
+        let (packages, _, interner) = parse(PRIMARY);
+        assert!(Arc::ptr_eq(&packages[0].name.arch, &packages[1].name.arch));
+        assert!(Arc::ptr_eq(&packages[0].provides[0].name, &packages[1].conflicts[0].name));
+        // "" for new packages, x86_64, both licenses and six distinct
+        // dependency names; stray entries are not even read
+        assert_eq!(interner.len(), 10);
+    }
+
+    #[test]
+    fn filelists_are_read() {
+        let filelists = r#"<?xml version="1.0" encoding="UTF-8"?>
+<filelists xmlns="http://linux.duke.edu/metadata/filelists" packages="2">
+<package pkgid="0123abcd" name="nano" arch="x86_64">
+  <version epoch="0" ver="7.2" rel="4.fc39"/>
+  <file>/usr/bin/nano</file>
+  <file type="dir">/usr/share/nano</file>
+  <file type="ghost">/etc/nanorc.rpmnew</file>
+</package>
+<package pkgid="ffff" arch="x86_64">
+  <version epoch="0" ver="1" rel="1"/>
+  <file>/nameless</file>
+</package>
+</filelists>
+"#;
+        let mut interner = Interner::default();
+        let mut packages = Vec::new();
+        let count = parse_filelists(filelists.as_bytes(), &mut interner, |pkg| packages.push(pkg)).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(packages[0].nevra(), "nano-0:7.2-4.fc39.x86_64");
+        assert_eq!(packages[0].files, ["/usr/bin/nano", "/usr/share/nano", "/etc/nanorc.rpmnew"]);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::package::{Dependency, Package};
+use crate::primary::{self, Interner};
+use anyhow::Result;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, Row, Rows};
+use std::path::Path;
+
+/// Walks one child table (requires, files, ...) ordered by `pkgKey` in step
+/// with the packages table, so only one package's rows are held at a time.
+struct Cursor<'s, T> {
+    rows: Rows<'s>,
+    pending: Option<(i64, T)>,
+    done: bool,
+}
+
+impl<'s, T> Cursor<'s, T> {
+    fn new(rows: Rows<'s>) -> Self {
+        Self {
+            rows,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Collect the rows belonging to `pkg_key`. Each row's first column must
+    /// be its `pkgKey`.
+    fn take_for(
+        &mut self,
+        pkg_key: i64,
+        interner: &mut Interner,
+        map: impl Fn(&Row, &mut Interner) -> rusqlite::Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        loop {
+            if self.pending.is_none() && !self.done {
+                match self.rows.next()? {
+                    Some(row) => self.pending = Some((row.get(0)?, map(row, interner)?)),
+                    None => self.done = true,
+                }
+            }
+            match self.pending.take() {
+                // Rows of packages that no longer exist are skipped
+                Some((key, _)) if key < pkg_key => {}
+                Some((key, item)) if key == pkg_key => items.push(item),
+                Some(pending) => {
+                    self.pending = Some(pending);
+                    break;
+                }
+                None => break,
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Read a column as text. Columns are declared TEXT but older createrepo
+/// versions store epochs and some versions as integers.
+fn text(row: &Row, index: usize) -> rusqlite::Result<Option<String>> {
+    Ok(match row.get_ref(index)? {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(t) | ValueRef::Blob(t) => Some(String::from_utf8_lossy(t).into_owned()),
+    })
+}
+
+fn dependency_row(row: &Row, interner: &mut Interner) -> rusqlite::Result<Dependency> {
+    let name = text(row, 1)?.unwrap_or_default();
+    let flags = text(row, 2)?;
+    Ok(primary::dependency(
+        &name,
+        flags.as_deref(),
+        text(row, 3)?,
+        text(row, 4)?,
+        text(row, 5)?,
+        interner,
+    ))
+}
+
+fn dependency_query(table: &str) -> String {
+    format!(
+        "SELECT pkgKey, name, flags, epoch, version, release FROM {} ORDER BY pkgKey",
+        table
+    )
+}
+
+/// Weak dependency tables only exist in databases from newer createrepo
+/// versions; without one the query yields no rows.
+fn weak_dependency_query(conn: &Connection, table: &str) -> Result<String> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(match exists {
+        true => dependency_query(table),
+        false => "SELECT 0, NULL, NULL, NULL, NULL, NULL WHERE 0".to_string(),
+    })
+}
+
+/// Read packages from an uncompressed primary.sqlite database, as written by
+/// createrepo with `--database`. Packages are handed to `emit` one at a time
+/// just like the XML parser does. Returns the number of packages read.
+pub fn parse_primary_sqlite(
+    path: &Path,
+    interner: &mut Interner,
+    mut emit: impl FnMut(Package),
+) -> Result<usize> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut packages_stmt = conn.prepare(
+        "SELECT pkgKey, name, arch, epoch, version, release, summary, description, \
+                url, rpm_license, size_package, location_href, checksum_type, pkgId \
+         FROM packages ORDER BY pkgKey",
+    )?;
+    let mut requires_stmt = conn.prepare(&dependency_query("requires"))?;
+    let mut provides_stmt = conn.prepare(&dependency_query("provides"))?;
+    let mut conflicts_stmt = conn.prepare(&dependency_query("conflicts"))?;
+    let mut obsoletes_stmt = conn.prepare(&dependency_query("obsoletes"))?;
+    let mut recommends_stmt = conn.prepare(&weak_dependency_query(&conn, "recommends")?)?;
+    let mut suggests_stmt = conn.prepare(&weak_dependency_query(&conn, "suggests")?)?;
+    let mut supplements_stmt = conn.prepare(&weak_dependency_query(&conn, "supplements")?)?;
+    let mut enhances_stmt = conn.prepare(&weak_dependency_query(&conn, "enhances")?)?;
+    let mut files_stmt = conn.prepare("SELECT pkgKey, name FROM files ORDER BY pkgKey")?;
+
+    let mut requires = Cursor::new(requires_stmt.query([])?);
+    let mut provides = Cursor::new(provides_stmt.query([])?);
+    let mut conflicts = Cursor::new(conflicts_stmt.query([])?);
+    let mut obsoletes = Cursor::new(obsoletes_stmt.query([])?);
+    let mut recommends = Cursor::new(recommends_stmt.query([])?);
+    let mut suggests = Cursor::new(suggests_stmt.query([])?);
+    let mut supplements = Cursor::new(supplements_stmt.query([])?);
+    let mut enhances = Cursor::new(enhances_stmt.query([])?);
+    let mut files = Cursor::new(files_stmt.query([])?);
+
+    let mut rows = packages_stmt.query([])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let pkg_key: i64 = row.get(0)?;
+
+        let mut pkg = primary::empty_package(interner);
+        pkg.name.name = text(row, 1)?.unwrap_or_default();
+        pkg.name.arch = interner.intern(&text(row, 2)?.unwrap_or_default());
+        pkg.version.epoch = text(row, 3)?.and_then(|epoch| epoch.parse().ok()).unwrap_or(0);
+        pkg.version.version = text(row, 4)?.unwrap_or_default();
+        pkg.version.release = text(row, 5)?.unwrap_or_default();
+        pkg.summary = text(row, 6)?.unwrap_or_default();
+        pkg.description = text(row, 7)?.unwrap_or_default();
+        pkg.url = text(row, 8)?.unwrap_or_default();
+        pkg.license = interner.intern(&text(row, 9)?.unwrap_or_default());
+        pkg.size = text(row, 10)?.and_then(|size| size.parse().ok()).unwrap_or(0);
+        pkg.location = text(row, 11)?.unwrap_or_default();
+        pkg.checksum = text(row, 12)?.zip(text(row, 13)?);
+
+        pkg.dependencies = requires.take_for(pkg_key, interner, dependency_row)?;
+        pkg.provides = provides.take_for(pkg_key, interner, dependency_row)?;
+        pkg.conflicts = conflicts.take_for(pkg_key, interner, dependency_row)?;
+        pkg.obsoletes = obsoletes.take_for(pkg_key, interner, dependency_row)?;
+        pkg.recommends = recommends.take_for(pkg_key, interner, dependency_row)?;
+        pkg.suggests = suggests.take_for(pkg_key, interner, dependency_row)?;
+        pkg.supplements = supplements.take_for(pkg_key, interner, dependency_row)?;
+        pkg.enhances = enhances.take_for(pkg_key, interner, dependency_row)?;
+        pkg.files = files.take_for(pkg_key, interner, |row, _| Ok(text(row, 1)?.unwrap_or_default()))?;
+
+        count += 1;
+        emit(pkg);
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="2">
+<package type="rpm">
+  <name>nano</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="7.2" rel="4.fc39"/>
+  <checksum type="sha256" pkgid="YES">0123abcd</checksum>
+  <summary>A small text editor</summary>
+  <description>GNU nano is a small text editor.</description>
+  <url>https://www.nano-editor.org</url>
+  <size package="631234"/>
+  <location href="Packages/n/nano-7.2-4.fc39.x86_64.rpm"/>
+  <format>
+    <rpm:license>GPL-3.0-or-later</rpm:license>
+    <rpm:provides>
+      <rpm:entry name="nano" flags="EQ" epoch="0" ver="7.2" rel="4.fc39"/>
+    </rpm:provides>
+    <rpm:requires>
+      <rpm:entry name="libc.so.6()(64bit)"/>
+      <rpm:entry name="ncurses-libs" flags="GE" ver="6.4"/>
+    </rpm:requires>
+    <rpm:recommends>
+      <rpm:entry name="nano-default-editor"/>
+    </rpm:recommends>
+    <file>/usr/bin/nano</file>
+    <file>/usr/bin/rnano</file>
+  </format>
+</package>
+<package type="rpm">
+  <name>vim-common</name>
+  <arch>x86_64</arch>
+  <version epoch="2" ver="9.0.2120" rel="1.fc39"/>
+  <checksum type="sha256" pkgid="YES">4567ef01</checksum>
+  <summary>The common files of vim</summary>
+  <description></description>
+  <url></url>
+  <size package="7001234"/>
+  <location href="Packages/v/vim-common-9.0.2120-1.fc39.x86_64.rpm"/>
+  <format>
+    <rpm:license>Vim AND MIT</rpm:license>
+    <rpm:conflicts>
+      <rpm:entry name="nano" flags="LT" epoch="1" ver="1" rel="1"/>
+    </rpm:conflicts>
+    <rpm:obsoletes>
+      <rpm:entry name="vim-minimal" flags="LT" epoch="2" ver="9.0"/>
+    </rpm:obsoletes>
+  </format>
+</package>
+</metadata>
+"#;
+
+    /// The same packages as `PRIMARY`, in the schema createrepo writes, with
+    /// an integer epoch and a row of a package that no longer exists
+    const SCHEMA: &str = "
+        CREATE TABLE packages (pkgKey INTEGER PRIMARY KEY, pkgId TEXT, name TEXT, arch TEXT, version TEXT,
+            epoch TEXT, release TEXT, summary TEXT, description TEXT, url TEXT, rpm_license TEXT,
+            size_package INTEGER, location_href TEXT, checksum_type TEXT);
+        CREATE TABLE requires (name TEXT, flags TEXT, epoch TEXT, version TEXT, release TEXT, pkgKey INTEGER, pre BOOLEAN);
+        CREATE TABLE provides (name TEXT, flags TEXT, epoch TEXT, version TEXT, release TEXT, pkgKey INTEGER);
+        CREATE TABLE conflicts (name TEXT, flags TEXT, epoch TEXT, version TEXT, release TEXT, pkgKey INTEGER);
+        CREATE TABLE obsoletes (name TEXT, flags TEXT, epoch TEXT, version TEXT, release TEXT, pkgKey INTEGER);
+        CREATE TABLE recommends (name TEXT, flags TEXT, epoch TEXT, version TEXT, release TEXT, pkgKey INTEGER);
+        CREATE TABLE files (name TEXT, type TEXT, pkgKey INTEGER);
+
+        INSERT INTO packages VALUES (1, '0123abcd', 'nano', 'x86_64', '7.2', '0', '4.fc39', 'A small text editor',
+            'GNU nano is a small text editor.', 'https://www.nano-editor.org', 'GPL-3.0-or-later', 631234,
+            'Packages/n/nano-7.2-4.fc39.x86_64.rpm', 'sha256');
+        INSERT INTO packages VALUES (3, '4567ef01', 'vim-common', 'x86_64', '9.0.2120', 2, '1.fc39',
+            'The common files of vim', '', '', 'Vim AND MIT', 7001234,
+            'Packages/v/vim-common-9.0.2120-1.fc39.x86_64.rpm', 'sha256');
+
+        INSERT INTO requires VALUES ('gone', NULL, NULL, NULL, NULL, 0, 0);
+        INSERT INTO requires VALUES ('libc.so.6()(64bit)', NULL, NULL, NULL, NULL, 1, 0);
+        INSERT INTO requires VALUES ('ncurses-libs', 'GE', NULL, '6.4', NULL, 1, 0);
+        INSERT INTO provides VALUES ('nano', 'EQ', '0', '7.2', '4.fc39', 1);
+        INSERT INTO conflicts VALUES ('nano', 'LT', '1', '1', '1', 3);
+        INSERT INTO obsoletes VALUES ('vim-minimal', 'LT', 2, '9.0', NULL, 3);
+        INSERT INTO recommends VALUES ('nano-default-editor', NULL, NULL, NULL, NULL, 1);
+        INSERT INTO files VALUES ('/usr/bin/nano', 'file', 1);
+        INSERT INTO files VALUES ('/gone', 'file', 2);
+        INSERT INTO files VALUES ('/usr/bin/rnano', 'file', 1);
+    ";
+
+    #[test]
+    fn sqlite_and_xml_give_the_same_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("primary.sqlite");
+        Connection::open(&path).unwrap().execute_batch(SCHEMA).unwrap();
+
+        let mut from_sqlite = Vec::new();
+        let count = parse_primary_sqlite(&path, &mut Interner::default(), |pkg| from_sqlite.push(pkg)).unwrap();
+        let mut from_xml = Vec::new();
+        primary::parse_primary(PRIMARY.as_bytes(), &mut Interner::default(), |pkg| from_xml.push(pkg)).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(format!("{:#?}", from_sqlite), format!("{:#?}", from_xml));
+    }
+}
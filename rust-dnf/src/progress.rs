@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often the terminal bars are redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a status line is printed when not on a terminal
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+const BAR_WIDTH: usize = 25;
+
+/// Totals of one `Downloader::fetch_all` call
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DownloadSummary {
+    pub files: usize,
+    pub failed: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// Receives progress events from the downloader, so that embedding tools can
+/// render their own UI. Each download has an id that is unique within the
+/// downloader. Events arrive from download tasks, possibly from several
+/// threads and batches at once.
+pub trait DownloadProgress: Send + Sync {
+    /// A `fetch_all` call is starting `files` downloads
+    fn batch_started(&self, _files: usize) {}
+
+    /// A transfer has begun. `size` is the full size of the file if known;
+    /// `resumed_at` is how much of it is already on disk. Called again for
+    /// every retry or mirror switch.
+    fn file_started(&self, _id: usize, _name: &str, _size: Option<u64>, _resumed_at: u64) {}
+
+    /// `downloaded` bytes of the file are now on disk, counting resumed ones
+    fn file_progress(&self, _id: usize, _downloaded: u64) {}
+
+    /// The download is over, with the error that ended it if it failed
+    fn file_finished(&self, _id: usize, _name: &str, _error: Option<&str>) {}
+
+    fn batch_finished(&self, _summary: &DownloadSummary) {}
+}
+
+/// Progress reporting that does nothing
+pub struct Silent;
+
+impl DownloadProgress for Silent {}
+
+/// Bars on a terminal, periodic lines otherwise
+pub fn default_progress() -> Arc<dyn DownloadProgress> {
+    if std::io::stderr().is_terminal() {
+        Arc::new(TerminalProgress::default())
+    } else {
+        Arc::new(PlainProgress::default())
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+#[derive(Debug)]
+struct FileState {
+    name: String,
+    size: Option<u64>,
+    resumed_at: u64,
+    downloaded: u64,
+    started: Instant,
+}
+
+impl FileState {
+    fn speed(&self) -> u64 {
+        rate(self.downloaded - self.resumed_at, self.started.elapsed())
+    }
+}
+
+/// What the built-in renderers share: the files in flight and totals since
+/// the first of the currently overlapping batches began. A summary is
+/// printed once the last of those batches is done.
+#[derive(Debug, Default)]
+struct Session {
+    batches: usize,
+    started: Option<Instant>,
+    files: BTreeMap<usize, FileState>,
+    done: usize,
+    failed: usize,
+    expected: usize,
+    finished_bytes: u64,
+}
+
+impl Session {
+    fn batch_started(&mut self, files: usize) {
+        if self.batches == 0 {
+            *self = Session {
+                started: Some(Instant::now()),
+                ..Session::default()
+            };
+        }
+        self.batches += 1;
+        self.expected += files;
+    }
+
+    fn file_started(&mut self, id: usize, name: &str, size: Option<u64>, resumed_at: u64) {
+        self.files.insert(
+            id,
+            FileState {
+                name: name.to_string(),
+                size,
+                resumed_at,
+                downloaded: resumed_at,
+                started: Instant::now(),
+            },
+        );
+    }
+
+    fn file_progress(&mut self, id: usize, downloaded: u64) {
+        if let Some(file) = self.files.get_mut(&id) {
+            file.downloaded = downloaded;
+        }
+    }
+
+    fn file_finished(&mut self, id: usize, error: Option<&str>) -> Option<FileState> {
+        let file = self.files.remove(&id);
+        if error.is_some() {
+            self.failed += 1;
+        } else {
+            self.done += 1;
+            if let Some(file) = &file {
+                self.finished_bytes += file.downloaded - file.resumed_at;
+            }
+        }
+        file
+    }
+
+    /// Returns the summary line once no batch is left running
+    fn batch_finished(&mut self) -> Option<String> {
+        self.batches = self.batches.saturating_sub(1);
+        if self.batches > 0 || self.expected == 0 {
+            return None;
+        }
+        let elapsed = self.started.map(|s| s.elapsed()).unwrap_or_default();
+        let mut line = format!(
+            "Downloaded {} files, {} in {} ({}/s)",
+            self.done,
+            format_size(self.finished_bytes),
+            format_duration(elapsed),
+            format_size(rate(self.finished_bytes, elapsed))
+        );
+        if self.failed > 0 {
+            line.push_str(&format!(", {} failed", self.failed));
+        }
+        Some(line)
+    }
+
+    fn in_flight_bytes(&self) -> u64 {
+        self.files.values().map(|f| f.downloaded - f.resumed_at).sum()
+    }
+
+    fn total_line(&self) -> String {
+        let bytes = self.finished_bytes + self.in_flight_bytes();
+        let elapsed = self.started.map(|s| s.elapsed()).unwrap_or_default();
+        format!(
+            "Total {}/{} files  {}  {}/s  {}",
+            self.done + self.failed,
+            self.expected,
+            format_size(bytes),
+            format_size(rate(bytes, elapsed)),
+            format_duration(elapsed)
+        )
+    }
+}
+
+fn done_line(name: &str, file: Option<&FileState>, error: Option<&str>) -> String {
+    match (file, error) {
+        (_, Some(error)) => format!("{}: failed: {}", name, error),
+        (None, None) => name.to_string(),
+        (Some(file), None) => format!(
+            "{:<40} {:>10}/s | {:>10}  {}",
+            file.name,
+            format_size(file.speed()),
+            format_size(file.downloaded),
+            format_duration(file.started.elapsed())
+        ),
+    }
+}
+
+#[derive(Debug, Default)]
+struct TerminalState {
+    session: Session,
+    drawn_lines: usize,
+    last_draw: Option<Instant>,
+}
+
+/// Per-file bars with size, speed and ETA, plus a total line, redrawn in
+/// place on stderr
+#[derive(Debug, Default)]
+pub struct TerminalProgress {
+    state: Mutex<TerminalState>,
+}
+
+impl TerminalProgress {
+    fn bar(file: &FileState) -> String {
+        let (filled, size, eta) = match file.size {
+            Some(size) if size > 0 => {
+                let filled = (file.downloaded.min(size) * BAR_WIDTH as u64 / size) as usize;
+                let speed = file.speed();
+                let eta = match speed {
+                    0 => "--:--".to_string(),
+                    _ => format_duration(Duration::from_secs(size.saturating_sub(file.downloaded) / speed)),
+                };
+                (filled, format_size(size), eta)
+            }
+            _ => (0, "?".to_string(), "--:--".to_string()),
+        };
+        format!(
+            "{:<30} [{}{}] {:>10}/{:<10} {:>10}/s  ETA {}",
+            file.name,
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            format_size(file.downloaded),
+            size,
+            format_size(file.speed()),
+            eta
+        )
+    }
+
+    /// Replace the bars drawn last time with `above` (printed for good)
+    /// followed by fresh bars
+    fn redraw(state: &mut TerminalState, above: &[String], force: bool) {
+        let due = state
+            .last_draw
+            .map(|last| last.elapsed() >= REDRAW_INTERVAL)
+            .unwrap_or(true);
+        if !force && above.is_empty() && !due {
+            return;
+        }
+
+        let mut out = std::io::stderr().lock();
+        for _ in 0..state.drawn_lines {
+            let _ = write!(out, "\x1b[1A\x1b[2K");
+        }
+        for line in above {
+            let _ = writeln!(out, "{}", line);
+        }
+        state.drawn_lines = 0;
+        if state.session.batches > 0 {
+            for file in state.session.files.values() {
+                let _ = writeln!(out, "{}", Self::bar(file));
+                state.drawn_lines += 1;
+            }
+            let _ = writeln!(out, "{}", state.session.total_line());
+            state.drawn_lines += 1;
+        }
+        let _ = out.flush();
+        state.last_draw = Some(Instant::now());
+    }
+}
+
+impl DownloadProgress for TerminalProgress {
+    fn batch_started(&self, files: usize) {
+        self.state.lock().unwrap().session.batch_started(files);
+    }
+
+    fn file_started(&self, id: usize, name: &str, size: Option<u64>, resumed_at: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.session.file_started(id, name, size, resumed_at);
+        Self::redraw(&mut state, &[], true);
+    }
+
+    fn file_progress(&self, id: usize, downloaded: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.session.file_progress(id, downloaded);
+        Self::redraw(&mut state, &[], false);
+    }
+
+    fn file_finished(&self, id: usize, name: &str, error: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let file = state.session.file_finished(id, error);
+        let line = done_line(name, file.as_ref(), error);
+        Self::redraw(&mut state, &[line], true);
+    }
+
+    fn batch_finished(&self, _summary: &DownloadSummary) {
+        let mut state = self.state.lock().unwrap();
+        let summary = state.session.batch_finished();
+        Self::redraw(&mut state, summary.as_slice(), true);
+    }
+}
+
+#[derive(Debug, Default)]
+struct PlainState {
+    session: Session,
+    last_line: Option<Instant>,
+}
+
+/// A line per finished file and a status line every few seconds, for logs
+/// and pipes
+#[derive(Debug, Default)]
+pub struct PlainProgress {
+    state: Mutex<PlainState>,
+}
+
+impl DownloadProgress for PlainProgress {
+    fn batch_started(&self, files: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.session.batch_started(files);
+        if state.last_line.is_none() {
+            state.last_line = Some(Instant::now());
+        }
+    }
+
+    fn file_started(&self, id: usize, name: &str, size: Option<u64>, resumed_at: u64) {
+        self.state.lock().unwrap().session.file_started(id, name, size, resumed_at);
+    }
+
+    fn file_progress(&self, id: usize, downloaded: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.session.file_progress(id, downloaded);
+        let due = state
+            .last_line
+            .map(|last| last.elapsed() >= PLAIN_INTERVAL)
+            .unwrap_or(true);
+        if due {
+            eprintln!("{}", state.session.total_line());
+            state.last_line = Some(Instant::now());
+        }
+    }
+
+    fn file_finished(&self, id: usize, name: &str, error: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let file = state.session.file_finished(id, error);
+        eprintln!("{}", done_line(name, file.as_ref(), error));
+    }
+
+    fn batch_finished(&self, _summary: &DownloadSummary) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(summary) = state.session.batch_finished() {
+            eprintln!("{}", summary);
+            state.last_line = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_and_rates_are_formatted() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 kB");
+        assert_eq!(format_size(1536), "1.5 kB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(format_size(3 << 30), "3.0 GB");
+        assert_eq!(format_size(2048 << 40), "2048.0 TB");
+
+        assert_eq!(format_duration(Duration::from_millis(59_900)), "00:59");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "62:05");
+
+        assert_eq!(rate(1000, Duration::from_millis(500)), 2000);
+        assert_eq!(rate(1000, Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn sessions_count_the_bytes_of_overlapping_batches() {
+        let mut session = Session::default();
+        session.batch_started(2);
+        session.batch_started(1);
+
+        // Resumed bytes were transferred earlier and are not counted
+        session.file_started(0, "a", Some(1000), 400);
+        session.file_progress(0, 700);
+        session.file_started(1, "b", None, 0);
+        session.file_progress(1, 50);
+        assert_eq!(session.in_flight_bytes(), 350);
+        assert!(session.total_line().starts_with("Total 0/3 files  350 B  "));
+
+        session.file_progress(0, 1000);
+        let file = session.file_finished(0, None).unwrap();
+        assert_eq!((file.name.as_str(), file.downloaded), ("a", 1000));
+        session.file_finished(1, Some("timed out"));
+        assert_eq!(session.in_flight_bytes(), 0);
+        assert_eq!(session.batch_finished(), None);
+
+        session.file_started(2, "c", Some(2048), 0);
+        session.file_progress(2, 2048);
+        session.file_finished(2, None);
+        assert!(session.total_line().starts_with("Total 3/3 files  2.6 kB  "));
+        let summary = session.batch_finished().unwrap();
+        assert!(summary.starts_with("Downloaded 2 files, 2.6 kB in 00:00 ("), "{}", summary);
+        assert!(summary.ends_with(", 1 failed"), "{}", summary);
+
+        // The next batch starts a new session
+        session.batch_started(1);
+        assert_eq!((session.done, session.failed, session.expected), (0, 0, 1));
+        session.file_started(3, "d", Some(10), 0);
+        session.file_progress(3, 10);
+        session.file_finished(3, None);
+        let summary = session.batch_finished().unwrap();
+        assert!(summary.starts_with("Downloaded 1 files, 10 B in "), "{}", summary);
+        assert!(!summary.contains("failed"));
+    }
+
+    #[test]
+    fn empty_batches_have_no_summary() {
+        let mut session = Session::default();
+        session.batch_started(0);
+        assert_eq!(session.batch_finished(), None);
+    }
+
+    #[test]
+    fn bars_fill_with_the_download() {
+        let file = FileState {
+            name: "a.rpm".to_string(),
+            size: Some(1000),
+            resumed_at: 0,
+            downloaded: 500,
+            started: Instant::now(),
+        };
+        let bar = TerminalProgress::bar(&file);
+        assert!(bar.starts_with("a.rpm"), "{}", bar);
+        assert!(bar.contains(&format!("[{}{}]", "=".repeat(12), " ".repeat(13))), "{}", bar);
+        assert!(bar.contains("500 B/1000 B"), "{}", bar);
+
+        let unknown = FileState { size: None, ..file };
+        assert!(TerminalProgress::bar(&unknown).contains(&format!("[{}]", " ".repeat(BAR_WIDTH))));
+        assert!(TerminalProgress::bar(&unknown).ends_with("ETA --:--"));
+    }
+}
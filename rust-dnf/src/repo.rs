@@ -1,20 +1,57 @@
+use crate::advisory::{self, Advisory};
+use crate::checksum;
+use crate::comps::{self, Comps};
+use crate::compression;
+use crate::deltarpm::{self, Delta, Deltas};
+use crate::mirrors::{self, Metalink, Mirror};
+use crate::modulemd::{self, ModuleMetadata};
 use crate::package::Package;
-use crate::config::Repository as RepoConfig;
+use crate::primary::{self, Interner};
+use crate::primary_sqlite;
+use crate::sack_cache::{self, Sack, SackRef};
+use crate::zchunk;
+use crate::config::{NetworkOptions, Repository as RepoConfig};
+use crate::download::{Download, Downloader};
 use anyhow::Result;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-use reqwest::blocking::Client;
-use flate2::read::GzDecoder;
-use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::io::BufReader;
 use quick_xml::events::Event;
 use quick_xml::Reader;
-use log;
+use std::time::Duration;
+
+/// How long a downloaded mirrorlist or metalink is used before it is
+/// fetched again
+const MIRRORS_MAX_AGE: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// One `<data>` entry of repomd.xml
+#[derive(Debug, Clone, Default)]
+pub struct RepoMdRecord {
+    pub location: String,
+    pub checksum_type: String,
+    pub checksum: String,
+    pub header_checksum: Option<(String, String)>, // (type, hex digest) of a zchunk file's header
+    pub header_size: Option<u64>,
+}
 
 #[derive(Debug)]
 pub struct Repository {
     pub config: RepoConfig,
-    pub packages: HashMap<String, Package>,
+    /// Every build of each package, by `name.arch`, in metadata order
+    pub packages: HashMap<String, Vec<Package>>,
+    pub advisories: Vec<Advisory>,
+    pub comps: Comps,
+    pub modules: ModuleMetadata,
+    /// Packages that are artifacts of a module stream. They are kept apart
+    /// from `packages` and only visible while their stream is active.
+    pub modular_packages: Vec<Package>,
+    active_modular: Vec<usize>,
+    /// Deltarpms from prestodelta metadata, by the nevra they produce
+    pub deltas: Deltas,
+    /// Mirrors from the mirrorlist or metalink, best first
+    pub mirrors: Vec<Mirror>,
+    metalink: Option<Metalink>,
 }
 
 impl Repository {
@@ -22,54 +59,205 @@ impl Repository {
         Self {
             config,
             packages: HashMap::new(),
+            advisories: Vec::new(),
+            comps: Comps::default(),
+            modules: ModuleMetadata::default(),
+            modular_packages: Vec::new(),
+            active_modular: Vec::new(),
+            deltas: Deltas::new(),
+            mirrors: Vec::new(),
+            metalink: None,
         }
     }
     
-    pub fn load_metadata(&mut self, cache_dir: &PathBuf) -> Result<()> {
+    pub fn load_metadata(&mut self, cache_dir: &Path, downloader: &Downloader) -> Result<()> {
         log::info!("Loading metadata for repository: {}", self.config.name);
         
         // Create repository cache directory
         let repo_cache_dir = cache_dir.join(&self.config.name);
         fs::create_dir_all(&repo_cache_dir)?;
         
+        if self.config.has_mirrors() && self.config.local_path().is_none() {
+            if let Err(e) = self.load_mirrors(&repo_cache_dir, downloader) {
+                log::warn!("Failed to load mirrors for {}: {}", self.config.name, e);
+            }
+        }
+        
         // Try to download real metadata
-        if let Err(e) = self.try_download_metadata(&repo_cache_dir) {
+        if let Err(e) = self.try_download_metadata(&repo_cache_dir, downloader) {
             log::warn!("Failed to download real metadata for {}: {}", self.config.name, e);
             log::info!("Falling back to mock data");
             self.load_mock_data()?;
         }
         
         log::debug!("Repository {} metadata loaded with {} packages", 
-                   self.config.name, self.packages.len());
+                   self.config.name, self.packages.values().map(Vec::len).sum::<usize>());
         Ok(())
     }
 
-    fn try_download_metadata(&mut self, repo_cache_dir: &PathBuf) -> Result<()> {
+    fn try_download_metadata(&mut self, repo_cache_dir: &Path, downloader: &Downloader) -> Result<()> {
         // Try different metadata locations (Fedora uses repomd.xml)
         let metadata_paths = vec![
             "repodata/repomd.xml",
             "repodata/primary.xml.gz",
             "repodata/primary.sqlite.gz",
+            "repodata/primary.sqlite.bz2",
         ];
         
         for metadata_path in metadata_paths {
-            let metadata_url = format!("{}/{}", self.config.url, metadata_path);
-            let local_path = repo_cache_dir.join(metadata_path);
+            let fetched = match self.config.local_path() {
+                Some(base) => local_file(&base, metadata_path),
+                None => {
+                    let dest = repo_cache_dir.join(metadata_path);
+                    // Files under these fixed names are replaced in place,
+                    // so a partial copy is never continued
+                    let mut download = self.download(metadata_path, dest.clone()).without_resume();
+                    if let (true, Some(metalink)) = (metadata_path.ends_with("repomd.xml"), self.metalink.clone()) {
+                        download = download.verify(move |path| metalink.verify_repomd(path));
+                    }
+                    downloader.fetch(download).map(|()| dest)
+                }
+            };
             
-            if self.download_file(&metadata_url, &local_path).is_ok() {
-                log::info!("Successfully downloaded metadata from: {}", metadata_url);
+            if let Ok(local_path) = fetched {
+                log::info!("Using metadata from: {:?}", local_path);
                 
                 // Parse based on file type
-                if metadata_path.ends_with("primary.xml.gz") {
-                    return self.parse_primary_xml(&local_path);
+                if metadata_path.contains("/primary.") {
+                    return self.parse_primary(&local_path, repo_cache_dir);
                 } else if metadata_path.ends_with("repomd.xml") {
-                    if let Ok(primary_location) = self.parse_repomd(&local_path) {
-                        let primary_url = format!("{}/{}", self.config.url, primary_location);
-                        let primary_path = repo_cache_dir.join("primary.xml.gz");
+                    let records = match self.parse_repomd(&local_path) {
+                        Ok(records) => records,
+                        Err(_) => continue,
+                    };
+                    
+                    // Unchanged repomd.xml means unchanged metadata, so the
+                    // parsed sack from the last run can be reused as is
+                    let repomd_checksum = checksum::file_digest(&local_path, "sha256")?;
+                    let cache_path = repo_cache_dir.join("sack.cache");
+                    match sack_cache::load(&cache_path, &repomd_checksum) {
+                        Ok(Some(sack)) => {
+                            log::info!("Loaded {} from metadata cache", self.config.name);
+                            self.apply_sack(sack);
+                            return Ok(());
+                        }
+                        Ok(None) => {}
+                        Err(e) => log::warn!("Ignoring unreadable metadata cache {:?}: {}", cache_path, e),
+                    }
+                    
+                    // Repos built with `createrepo --database` carry primary
+                    // twice; the XML copy is preferred as it streams
+                    let primary_record = records.get("primary").or_else(|| records.get("primary_db"));
+                    let group = records.get("group_gz").or_else(|| records.get("group"));
+                    // Deltas only save anything when packages are downloaded
+                    let prestodelta = records.get("prestodelta").filter(|_| self.config.local_path().is_none());
+                    // Of zchunk primary and filelists only the chunks that
+                    // changed since the last download are fetched, next to
+                    // the other files
+                    let remote = self.config.local_path().is_none();
+                    let primary_zck = records.get("primary_zck").filter(|_| remote);
+                    let filelists_zck = records.get("filelists_zck").filter(|_| remote);
+                    let filelists_record = records.get("filelists");
+                    let this = &*self;
+                    let (zck_primary, zck_filelists, [modules, primary, filelists, updateinfo, group, prestodelta]) =
+                        std::thread::scope(|scope| {
+                            let primary_zck = primary_zck.map(|record| {
+                                let dest = repo_cache_dir.join("primary.xml.zck");
+                                scope.spawn(move || this.fetch_zck(downloader, record, &dest))
+                            });
+                            let filelists_zck = filelists_zck.map(|record| {
+                                let dest = repo_cache_dir.join("filelists.xml.zck");
+                                scope.spawn(move || this.fetch_zck(downloader, record, &dest))
+                            });
+                            let fetched = this.fetch_records(
+                                downloader,
+                                repo_cache_dir,
+                                [
+                                    records.get("modules"),
+                                    primary_record.filter(|_| primary_zck.is_none()),
+                                    filelists_record.filter(|_| filelists_zck.is_none()),
+                                    records.get("updateinfo"),
+                                    group,
+                                    prestodelta,
+                                ],
+                            );
+                            let join = |handle: std::thread::ScopedJoinHandle<'_, Result<PathBuf>>| {
+                                handle.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Fetching zchunk metadata panicked")))
+                            };
+                            (primary_zck.map(join), filelists_zck.map(join), fetched)
+                        });
+                    let primary = match zck_primary {
+                        Some(Ok(path)) => Some(Ok(path)),
+                        Some(Err(e)) => {
+                            log::warn!("Failed to fetch zchunk primary for {}: {}", self.config.name, e);
+                            let [primary] = self.fetch_records(downloader, repo_cache_dir, [primary_record]);
+                            primary
+                        }
+                        None => primary,
+                    };
+                    let filelists = match zck_filelists {
+                        Some(Ok(path)) => Some(Ok(path)),
+                        Some(Err(e)) => {
+                            log::warn!("Failed to fetch zchunk filelists for {}: {}", self.config.name, e);
+                            let [filelists] = self.fetch_records(downloader, repo_cache_dir, [filelists_record]);
+                            filelists
+                        }
+                        None => filelists,
+                    };
+                    
+                    // Only metadata that loaded completely is cached, otherwise
+                    // what failed would stay missing until repomd.xml changes
+                    let mut complete = true;
+                    
+                    // Module metadata decides which primary packages are modular,
+                    // so it has to be known before primary is parsed
+                    if let Some(modules) = modules {
+                        if let Err(e) = modules.and_then(|path| self.load_modules(&path)) {
+                            log::warn!("Failed to load modules for {}: {}", self.config.name, e);
+                            complete = false;
+                        }
+                    }
+                    
+                    if let Some(Ok(primary_path)) = primary {
+                        self.parse_primary(&primary_path, repo_cache_dir)?;
+                        
+                        // Primary lists only commonly required files, filelists
+                        // completes them for file dependencies
+                        if let Some(filelists) = filelists {
+                            if let Err(e) = filelists.and_then(|path| self.load_filelists(&path)) {
+                                log::warn!("Failed to load filelists for {}: {}", self.config.name, e);
+                                complete = false;
+                            }
+                        }
+                        
+                        // Advisories are optional; a repo without them is still usable
+                        if let Some(updateinfo) = updateinfo {
+                            if let Err(e) = updateinfo.and_then(|path| self.load_updateinfo(&path)) {
+                                log::warn!("Failed to load updateinfo for {}: {}", self.config.name, e);
+                                complete = false;
+                            }
+                        }
+                        
+                        if let Some(group) = group {
+                            if let Err(e) = group.and_then(|path| self.load_comps(&path)) {
+                                log::warn!("Failed to load comps for {}: {}", self.config.name, e);
+                                complete = false;
+                            }
+                        }
+                        
+                        if let Some(prestodelta) = prestodelta {
+                            if let Err(e) = prestodelta.and_then(|path| self.load_prestodelta(&path)) {
+                                log::warn!("Failed to load deltarpm metadata for {}: {}", self.config.name, e);
+                                complete = false;
+                            }
+                        }
                         
-                        if self.download_file(&primary_url, &primary_path).is_ok() {
-                            return self.parse_primary_xml(&primary_path);
+                        if !complete {
+                            log::info!("Not caching metadata of {}, some of it failed to load", self.config.name);
+                        } else if let Err(e) = sack_cache::store(&cache_path, &repomd_checksum, &self.sack_ref()) {
+                            log::warn!("Failed to write metadata cache for {}: {}", self.config.name, e);
                         }
+                        return Ok(());
                     }
                 }
             }
@@ -78,42 +266,96 @@ impl Repository {
         anyhow::bail!("Could not download or parse any metadata files")
     }
     
-    fn parse_repomd(&self, path: &PathBuf) -> Result<String> {
+    fn apply_sack(&mut self, sack: Sack) {
+        self.packages.clear();
+        for pkg in sack.packages {
+            self.add_package(pkg);
+        }
+        self.modular_packages = sack.modular_packages;
+        self.advisories = sack.advisories;
+        self.comps = sack.comps;
+        self.modules = sack.modules;
+        self.deltas = sack.deltas;
+    }
+    
+    fn sack_ref(&self) -> SackRef<'_> {
+        SackRef {
+            packages: self.packages.values().flatten().collect(),
+            modular_packages: &self.modular_packages,
+            advisories: &self.advisories,
+            comps: &self.comps,
+            modules: &self.modules,
+            deltas: &self.deltas,
+        }
+    }
+    
+    fn parse_repomd(&self, path: &Path) -> Result<HashMap<String, RepoMdRecord>> {
         let content = fs::read_to_string(path)?;
         let mut reader = Reader::from_str(&content);
         reader.trim_text(true);
         
         let mut buf = Vec::new();
-        let mut in_data = false;
-        let mut data_type = String::new();
-        let mut location = String::new();
+        let mut records = HashMap::new();
+        let mut current: Option<(String, RepoMdRecord)> = None;
+        let mut field: Option<Vec<u8>> = None; // Element whose text is wanted
         
         loop {
             match reader.read_event_into(&mut buf) {
-                Ok(Event::Start(e)) => {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
                     match e.name().as_ref() {
                         b"data" => {
-                            in_data = true;
-                            if let Some(typ) = e.try_get_attribute("type")? {
-                                data_type = String::from_utf8_lossy(&typ.value).to_string();
+                            let data_type = match e.try_get_attribute("type")? {
+                                Some(typ) => String::from_utf8_lossy(&typ.value).to_string(),
+                                None => String::new(),
+                            };
+                            current = Some((data_type, RepoMdRecord::default()));
+                        }
+                        b"location" => {
+                            if let (Some((_, record)), Some(href)) = (current.as_mut(), e.try_get_attribute("href")?) {
+                                record.location = String::from_utf8_lossy(&href.value).to_string();
+                            }
+                        }
+                        b"checksum" => {
+                            if let (Some((_, record)), Some(typ)) = (current.as_mut(), e.try_get_attribute("type")?) {
+                                record.checksum_type = String::from_utf8_lossy(&typ.value).to_string();
+                                field = Some(b"checksum".to_vec());
                             }
                         }
-                        b"location" if in_data && data_type == "primary" => {
-                            if let Some(href) = e.try_get_attribute("href")? {
-                                location = String::from_utf8_lossy(&href.value).to_string();
+                        b"header-checksum" => {
+                            if let (Some((_, record)), Some(typ)) = (current.as_mut(), e.try_get_attribute("type")?) {
+                                record.header_checksum = Some((String::from_utf8_lossy(&typ.value).to_string(), String::new()));
+                                field = Some(b"header-checksum".to_vec());
                             }
                         }
+                        b"header-size" => field = Some(b"header-size".to_vec()),
                         _ => {}
                     }
                 }
-                Ok(Event::End(e)) => {
-                    if e.name().as_ref() == b"data" {
-                        in_data = false;
-                        if !location.is_empty() {
-                            return Ok(location);
+                Ok(Event::Text(e)) => {
+                    if let (Some((_, record)), Some(field)) = (current.as_mut(), field.as_deref()) {
+                        let text = e.unescape()?.to_string();
+                        match field {
+                            b"checksum" => record.checksum = text,
+                            b"header-size" => record.header_size = text.trim().parse().ok(),
+                            _ => {
+                                if let Some((_, digest)) = record.header_checksum.as_mut() {
+                                    *digest = text;
+                                }
+                            }
                         }
                     }
                 }
+                Ok(Event::End(e)) => match e.name().as_ref() {
+                    b"checksum" | b"header-checksum" | b"header-size" => field = None,
+                    b"data" => {
+                        if let Some((data_type, record)) = current.take() {
+                            if !record.location.is_empty() {
+                                records.insert(data_type, record);
+                            }
+                        }
+                    }
+                    _ => {}
+                },
                 Ok(Event::Eof) => break,
                 Err(e) => {
                     log::warn!("Error parsing repomd.xml: {}", e);
@@ -124,118 +366,354 @@ impl Repository {
             buf.clear();
         }
         
-        anyhow::bail!("Could not find primary metadata location in repomd.xml")
+        if !records.contains_key("primary") && !records.contains_key("primary_db") {
+            anyhow::bail!("Could not find primary metadata location in repomd.xml");
+        }
+        Ok(records)
     }
     
-    fn parse_primary_xml(&mut self, path: &PathBuf) -> Result<()> {
-        log::info!("Parsing primary metadata from: {:?}", path);
+    /// Get the files the given repomd records point at, downloading them
+    /// into the repo cache together unless the repository is local.
+    fn fetch_records<const N: usize>(
+        &self,
+        downloader: &Downloader,
+        repo_cache_dir: &Path,
+        records: [Option<&RepoMdRecord>; N],
+    ) -> [Option<Result<PathBuf>>; N] {
+        let files = records
+            .iter()
+            .flatten()
+            .filter_map(|record| {
+                let file_name = Path::new(&record.location).file_name()?;
+                Some((record.location.as_str(), repo_cache_dir.join(file_name)))
+            })
+            .collect();
+        let mut fetched = self.fetch_all(downloader, files).into_iter();
         
-        // Decompress if needed
-        let mut file = fs::File::open(path)?;
-        let mut content = String::new();
+        records.map(|record| {
+            let record = record?;
+            if Path::new(&record.location).file_name().is_none() {
+                return Some(Err(anyhow::anyhow!("Invalid metadata location: {}", record.location)));
+            }
+            fetched.next()
+        })
+    }
+    
+    /// Bring the zchunk file of `record` at `dest` up to date. Chunks that
+    /// the copy already there has are kept and only the others are fetched,
+    /// by byte ranges; the whole file is fetched if that does not work.
+    fn fetch_zck(&self, downloader: &Downloader, record: &RepoMdRecord, dest: &Path) -> Result<PathBuf> {
+        if dest.is_file() && checksum::verify_file(dest, &record.checksum_type, &record.checksum).is_ok() {
+            log::debug!("{:?} is up to date", dest);
+            return Ok(dest.to_path_buf());
+        }
         
-        if path.extension().map(|ext| ext == "gz").unwrap_or(false) {
-            let mut decoder = GzDecoder::new(file);
-            decoder.read_to_string(&mut content)?;
-        } else {
-            file.read_to_string(&mut content)?;
+        if let Err(e) = self.fetch_zck_chunks(downloader, record, dest) {
+            log::warn!("Fetching changed chunks of {} failed, downloading all of it: {}", record.location, e);
+            let (typ, expected) = (record.checksum_type.clone(), record.checksum.clone());
+            let download = self
+                .download(&record.location, dest.to_path_buf())
+                .without_resume()
+                .verify(move |path| checksum::verify_file(path, &typ, &expected));
+            downloader.fetch(download)?;
         }
+        Ok(dest.to_path_buf())
+    }
+    
+    fn fetch_zck_chunks(&self, downloader: &Downloader, record: &RepoMdRecord, dest: &Path) -> Result<()> {
+        let header_size = match record.header_size {
+            Some(size) if size > 0 => size,
+            _ => anyhow::bail!("repomd.xml gives no zchunk header size"),
+        };
+        let header_path = dest.with_extension("header");
+        let tmp_path = dest.with_extension("tmp");
+        let mut range_paths = Vec::new();
         
-        let mut reader = Reader::from_str(&content);
-        reader.trim_text(true);
+        let result = (|| {
+            downloader.fetch(self.download(&record.location, header_path.clone()).range(0, header_size - 1))?;
+            let header = zchunk::Header::read(&mut BufReader::new(fs::File::open(&header_path)?))?;
+            if let Some((_, expected)) = &record.header_checksum {
+                if !hex::encode(&header.digest).eq_ignore_ascii_case(expected) {
+                    anyhow::bail!("zchunk header does not match repomd.xml");
+                }
+            }
+            
+            let old = fs::File::open(dest)
+                .ok()
+                .and_then(|file| zchunk::Header::read(&mut BufReader::new(file)).ok());
+            let plan = zchunk::Plan::new(&header, old.as_ref());
+            let downloads = plan
+                .ranges
+                .iter()
+                .enumerate()
+                .map(|(i, &(first, last))| {
+                    let path = dest.with_extension(format!("range{}", i));
+                    range_paths.push(path.clone());
+                    self.download(&record.location, path).range(first, last)
+                })
+                .collect();
+            for result in downloader.fetch_all(downloads) {
+                result?;
+            }
+            
+            zchunk::assemble(&header, &header_path, dest, &plan, &range_paths, &tmp_path)?;
+            checksum::verify_file(&tmp_path, &record.checksum_type, &record.checksum)?;
+            fs::rename(&tmp_path, dest)?;
+            log::info!(
+                "Updated {:?}: reused {} of {} chunks, fetched {} bytes",
+                dest,
+                plan.reused(),
+                header.chunks.len(),
+                plan.fetch_size() + header_size
+            );
+            Ok(())
+        })();
         
-        let mut buf = Vec::new();
-        let mut current_package: Option<Package> = None;
-        let mut current_text = String::new();
+        for path in range_paths.iter().chain([&header_path, &tmp_path]) {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+    
+    /// Paths of the files at the given locations within the repository.
+    /// Files of local repositories are used where they are; others are
+    /// downloaded concurrently to their destinations.
+    fn fetch_all(&self, downloader: &Downloader, files: Vec<(&str, PathBuf)>) -> Vec<Result<PathBuf>> {
+        if let Some(base) = self.config.local_path() {
+            return files
+                .into_iter()
+                .map(|(location, _)| local_file(&base, location))
+                .collect();
+        }
         
-        loop {
-            match reader.read_event_into(&mut buf) {
-                Ok(Event::Start(e)) => {
-                    current_text.clear();
-                    
-                    if e.name().as_ref() == b"package" {
-                        current_package = Some(Package::new(
-                            crate::package::PackageName::new("unknown", "x86_64").unwrap(),
-                            crate::package::Version::new(0, "0", "0").unwrap(),
-                            String::new(),
-                        ));
-                    }
-                }
-                
-                Ok(Event::Text(e)) => {
-                    current_text.push_str(&e.unescape()?);
-                }
-                
-                Ok(Event::End(e)) => {
-                    let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                    
-                    if let Some(ref mut pkg) = current_package {
-                        match tag.as_str() {
-                            "name" => {
-                                pkg.name = crate::package::PackageName::new(&current_text, &self.config.name).unwrap();
-                            }
-                            "arch" => {
-                                pkg.name.arch = current_text.clone();
-                            }
-                            "version" => {
-                                // Version parsing will be handled by attributes
-                            }
-                            "summary" => {
-                                pkg.summary = current_text.clone();
-                            }
-                            "description" => {
-                                pkg.description = current_text.clone();
-                            }
-                            "package" => {
-                                // End of package - add to hashmap
-                                if pkg.name.name != "unknown" {
-                                    self.packages.insert(pkg.name.name.clone(), pkg.clone());
-                                }
-                                current_package = None;
-                            }
-                            _ => {}
-                        }
-                    }
-                    
-                    // Handle version with attributes
-                    if tag == "version" {
-                        if let Some(ref mut pkg) = current_package {
-                            // For now, use a simple version - we'll parse attributes later
-                            pkg.version = crate::package::Version::parse(&current_text).unwrap();
-                        }
-                    }
-                }
-                
-                Ok(Event::Eof) => break,
+        let downloads = files
+            .iter()
+            .map(|(location, dest)| self.download(location, dest.clone()))
+            .collect();
+        downloader
+            .fetch_all(downloads)
+            .into_iter()
+            .zip(files)
+            .map(|(result, (_, dest))| result.map(|()| dest))
+            .collect()
+    }
+    
+    /// A download of `location` that tries the configured url, then mirrors
+    fn download(&self, location: &str, dest: PathBuf) -> Download {
+        let urls = self
+            .base_urls()
+            .into_iter()
+            .map(|base| format!("{}/{}", base.trim_end_matches('/'), location))
+            .collect();
+        Download::new(urls, dest).network(self.config.network.clone())
+    }
+    
+    /// Where the rpm of `package` is read from, along with the download that
+    /// has to happen first when the repository is remote and the rpm is not
+    /// cached yet. A cached rpm that does not match the metadata is deleted
+    /// and fetched again; a bad rpm in a local repository is an error.
+    pub fn package_source(&self, package: &Package, cache_dir: &Path) -> Result<(PathBuf, Option<Download>)> {
+        let verify = package_verifier(package);
+        if let Some(base) = self.config.local_path() {
+            let path = local_file(&base, &package.location)?;
+            verify(&path)?;
+            return Ok((path, None));
+        }
+        
+        let dest = self.cache_path(&package.location, cache_dir)?;
+        if dest.is_file() {
+            match verify(&dest) {
+                Ok(()) => return Ok((dest, None)),
                 Err(e) => {
-                    log::warn!("XML parsing error: {}, continuing...", e);
-                    continue;
+                    log::warn!("Downloading {} again: {}", package.nevra(), e);
+                    fs::remove_file(&dest)?;
                 }
-                _ => {}
             }
-            buf.clear();
+        }
+        Ok((dest.clone(), Some(self.download(&package.location, dest).verify(verify))))
+    }
+    
+    /// Where a file at `location` in the repository is kept in the cache
+    fn cache_path(&self, location: &str, cache_dir: &Path) -> Result<PathBuf> {
+        let file_name = Path::new(location)
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid package location: {:?}", location))?;
+        Ok(cache_dir.join(&self.config.name).join("packages").join(file_name))
+    }
+    
+    /// The rpm of a build of this repository's packages that may no longer
+    /// be in the metadata, if it is still in the repository's directory or
+    /// in the package cache
+    pub fn cached_rpm(&self, package: &Package, cache_dir: &Path) -> Option<PathBuf> {
+        if package.location.is_empty() {
+            return None;
+        }
+        match self.config.local_path() {
+            Some(base) => local_file(&base, &package.location).ok(),
+            None => self.cache_path(&package.location, cache_dir).ok().filter(|path| path.is_file()),
+        }
+    }
+    
+    /// Deltas that produce `package`
+    pub fn deltas_for(&self, package: &Package) -> &[Delta] {
+        self.deltas.get(&package.nevra()).map(Vec::as_slice).unwrap_or_default()
+    }
+    
+    /// Where `delta` is downloaded to, and the download, which rejects a
+    /// file that does not match the metadata
+    pub fn delta_download(&self, delta: &Delta, cache_dir: &Path) -> Result<(PathBuf, Download)> {
+        let dest = self.cache_path(&delta.location, cache_dir)?;
+        let mut download = self.download(&delta.location, dest.clone());
+        if let Some((typ, digest)) = delta.checksum.clone() {
+            download = download.verify(move |path| checksum::verify_file(path, &typ, &digest));
+        }
+        Ok((dest, download))
+    }
+    
+    /// Whether `package` is one of this repository's own entries
+    pub fn contains(&self, package: &Package) -> bool {
+        self.builds(&package.name.name, &package.name.arch)
+            .iter()
+            .any(|pkg| std::ptr::eq(pkg, package))
+            || self.modular_packages.iter().any(|pkg| std::ptr::eq(pkg, package))
+    }
+    
+    /// URLs to download from in order: the configured url, then mirrors
+    fn base_urls(&self) -> Vec<&str> {
+        let configured = Some(self.config.url.as_str()).filter(|url| !url.is_empty());
+        configured
+            .into_iter()
+            .chain(self.mirrors.iter().map(|mirror| mirror.url.as_str()))
+            .collect()
+    }
+    
+    fn load_mirrors(&mut self, repo_cache_dir: &Path, downloader: &Downloader) -> Result<()> {
+        if let Some(url) = self.config.metalink.clone() {
+            let path = repo_cache_dir.join("metalink.xml");
+            refresh_mirror_file(downloader, &url, &path, &self.config.network)?;
+            let metalink = mirrors::parse_metalink(BufReader::new(fs::File::open(path)?))?;
+            self.mirrors = metalink.mirrors.clone();
+            self.metalink = Some(metalink);
+        } else if let Some(url) = self.config.mirrorlist.clone() {
+            let path = repo_cache_dir.join("mirrorlist.txt");
+            refresh_mirror_file(downloader, &url, &path, &self.config.network)?;
+            let content = fs::read_to_string(path)?;
+            // Mirror managers answer mirrorlist URLs with a metalink as well
+            if content.contains("<metalink") {
+                let metalink = mirrors::parse_metalink(content.as_bytes())?;
+                self.mirrors = metalink.mirrors.clone();
+                self.metalink = Some(metalink);
+            } else {
+                self.mirrors = mirrors::parse_mirrorlist(&content);
+            }
         }
         
-        log::info!("Parsed {} packages from primary metadata", self.packages.len());
+        if self.mirrors.is_empty() {
+            anyhow::bail!("No usable mirrors listed");
+        }
+        log::debug!("Repository {} has {} mirrors", self.config.name, self.mirrors.len());
+        Ok(())
+    }
+    
+    fn load_updateinfo(&mut self, path: &Path) -> Result<()> {
+        self.advisories = advisory::parse_updateinfo(BufReader::new(compression::open(path)?))?;
+        Ok(())
+    }
+    
+    fn load_modules(&mut self, path: &Path) -> Result<()> {
+        self.modules = modulemd::parse_modules(compression::open(path)?)?;
+        Ok(())
+    }
+    
+    fn load_prestodelta(&mut self, path: &Path) -> Result<()> {
+        self.deltas = deltarpm::parse_prestodelta(BufReader::new(compression::open(path)?))?;
+        log::debug!("Repository {} has deltas for {} packages", self.config.name, self.deltas.len());
         Ok(())
     }
     
-    fn download_file(&self, url: &str, path: &PathBuf) -> Result<()> {
-        log::debug!("Downloading {} to {:?}", url, path);
+    /// Replace the file lists of the packages primary listed with the
+    /// complete ones of filelists.xml
+    fn load_filelists(&mut self, path: &Path) -> Result<()> {
+        let modular: HashMap<String, usize> = self
+            .modular_packages
+            .iter()
+            .enumerate()
+            .map(|(i, pkg)| (pkg.nevra(), i))
+            .collect();
+        let packages = &mut self.packages;
+        let modular_packages = &mut self.modular_packages;
         
-        let client = Client::new();
-        let response = client.get(url).send()?;
+        let mut interner = Interner::default();
+        let mut matched = 0;
+        let count = primary::parse_filelists(BufReader::new(compression::open(path)?), &mut interner, |listed| {
+            let key = format!("{}.{}", listed.name.name, listed.name.arch);
+            let pkg = match packages.get_mut(&key) {
+                Some(builds) => builds.iter_mut().find(|pkg| pkg.version == listed.version),
+                None => None,
+            };
+            let pkg = match pkg {
+                Some(pkg) => Some(pkg),
+                None => modular.get(&listed.nevra()).map(|&i| &mut modular_packages[i]),
+            };
+            if let Some(pkg) = pkg {
+                pkg.files = listed.files;
+                matched += 1;
+            }
+        })?;
         
-        if response.status().is_success() {
-            let content = response.bytes()?;
-            fs::write(path, content)?;
-            log::debug!("Successfully downloaded {}", url);
-            Ok(())
+        log::debug!("Read file lists of {} packages, {} of them known from primary", count, matched);
+        Ok(())
+    }
+    
+    fn load_comps(&mut self, path: &Path) -> Result<()> {
+        self.comps = comps::parse_comps(BufReader::new(compression::open(path)?))?;
+        Ok(())
+    }
+    
+    /// Parse primary metadata in either of its formats, primary.xml or
+    /// primary.sqlite, telling them apart by file name.
+    fn parse_primary(&mut self, path: &Path, repo_cache_dir: &Path) -> Result<()> {
+        log::info!("Parsing primary metadata from: {:?}", path);
+        
+        let artifacts: HashSet<&str> = self.modules
+            .streams
+            .iter()
+            .flat_map(|stream| stream.artifacts.iter().map(|a| a.as_str()))
+            .collect();
+        let packages = &mut self.packages;
+        let modular_packages = &mut self.modular_packages;
+        
+        let mut interner = Interner::default();
+        let mut add = |pkg: Package| {
+            if !artifacts.is_empty() && artifacts.contains(pkg.nevra().as_str()) {
+                modular_packages.push(pkg);
+                return;
+            }
+            // Older builds stay, advisory upgrades may target one of them
+            packages.entry(format!("{}.{}", pkg.name.name, pkg.name.arch)).or_default().push(pkg);
+        };
+        
+        let is_sqlite = path
+            .file_name()
+            .map(|name| name.to_string_lossy().contains(".sqlite"))
+            .unwrap_or(false);
+        let count = if is_sqlite {
+            // Local repositories may be read-only, so the database is
+            // always decompressed into the cache
+            let file_name = path.file_name().unwrap_or_default();
+            let dest = repo_cache_dir.join(file_name).with_extension("db");
+            let db_path = compression::decompress_to(path, &dest)?;
+            primary_sqlite::parse_primary_sqlite(&db_path, &mut interner, &mut add)?
         } else {
-            anyhow::bail!("Failed to download {}: {}", url, response.status());
-        }
+            let source = BufReader::new(compression::open(path)?);
+            primary::parse_primary(source, &mut interner, &mut add)?
+        };
+        
+        log::info!("Parsed {} packages ({} modular, {} distinct strings) from primary metadata",
+                   count, self.modular_packages.len(), interner.len());
+        Ok(())
     }
-
+    
     fn load_mock_data(&mut self) -> Result<()> {
         log::warn!("Using mock data for repository: {}", self.config.name);
         
@@ -278,20 +756,86 @@ impl Repository {
         ];
         
         for pkg in mock_packages {
-            self.packages.insert(pkg.name.name.clone(), pkg);
+            self.add_package(pkg);
         }
         
         Ok(())
     }
     
+    /// Make only the artifacts of the given `(module, stream)` pairs visible
+    pub fn set_active_streams(&mut self, active: &HashSet<(String, String)>) {
+        let nevras: HashSet<&str> = self.modules
+            .streams
+            .iter()
+            .filter(|s| active.contains(&(s.name.clone(), s.stream.clone())))
+            .flat_map(|s| s.artifacts.iter().map(|a| a.as_str()))
+            .collect();
+        
+        self.active_modular = self.modular_packages
+            .iter()
+            .enumerate()
+            .filter(|(_, pkg)| nevras.contains(pkg.nevra().as_str()))
+            .map(|(i, _)| i)
+            .collect();
+    }
+    
+    fn active_modular_packages(&self) -> impl Iterator<Item = &Package> {
+        self.active_modular.iter().map(|&i| &self.modular_packages[i])
+    }
+    
+    pub fn is_active_modular(&self, package: &Package) -> bool {
+        self.active_modular_packages().any(|pkg| std::ptr::eq(pkg, package))
+    }
+    
+    pub fn find_modular_package(&self, name: &str) -> Option<&Package> {
+        self.active_modular_packages()
+            .filter(|pkg| pkg.name.name == name)
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+    
+    /// The build to install for `name` or `name.arch`: the newest build of
+    /// the best architecture, which is the machine's own, then noarch
     pub fn find_package(&self, name: &str) -> Option<&Package> {
-        self.packages.get(name)
+        if let Some(pkg) = self.find_modular_package(name) {
+            return Some(pkg);
+        }
+        if let Some(builds) = self.packages.get(name) {
+            return newest(builds);
+        }
+        self.packages
+            .values()
+            .filter_map(|builds| newest(builds))
+            .filter(|pkg| pkg.name.name == name)
+            .min_by(|a, b| arch_rank(&a.name.arch).cmp(&arch_rank(&b.name.arch)).then(b.version.cmp(&a.version)))
+    }
+    
+    /// All non-modular builds of `name` for `arch`
+    pub fn builds(&self, name: &str, arch: &str) -> &[Package] {
+        self.packages
+            .get(&format!("{}.{}", name, arch))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+    
+    /// Builds of `name` for `arch` that may be installed: the artifacts of
+    /// active streams if the name is modular, otherwise all builds
+    pub fn candidates(&self, name: &str, arch: &str) -> Vec<&Package> {
+        let modular: Vec<&Package> = self.active_modular_packages().filter(|pkg| pkg.name.name == name).collect();
+        if modular.is_empty() {
+            self.builds(name, arch).iter().collect()
+        } else {
+            modular.into_iter().filter(|pkg| pkg.name.arch.as_ref() == arch).collect()
+        }
+    }
+    
+    pub fn add_package(&mut self, pkg: Package) {
+        self.packages.entry(format!("{}.{}", pkg.name.name, pkg.name.arch)).or_default().push(pkg);
     }
     
     pub fn search(&self, query: &str) -> Vec<&Package> {
         let query_lower = query.to_lowercase();
-        self.packages
-            .values()
+        self.list_packages()
+            .into_iter()
             .filter(|pkg| {
                 pkg.name.name.to_lowercase().contains(&query_lower) || 
                 pkg.description.to_lowercase().contains(&query_lower) ||
@@ -300,7 +844,353 @@ impl Repository {
             .collect()
     }
     
+    /// Visible packages: non-modular ones plus the artifacts of active streams
     pub fn list_packages(&self) -> Vec<&Package> {
-        self.packages.values().collect()
+        let modular_names: HashSet<&str> = self.active_modular_packages()
+            .map(|pkg| pkg.name.name.as_str())
+            .collect();
+        self.packages
+            .values()
+            .filter_map(|builds| newest(builds))
+            .filter(|pkg| !modular_names.contains(pkg.name.name.as_str()))
+            .chain(self.active_modular_packages())
+            .collect()
     }
-}
\ No newline at end of file
+}
+
+fn newest(builds: &[Package]) -> Option<&Package> {
+    builds.iter().max_by(|a, b| a.version.cmp(&b.version))
+}
+
+/// How well a package architecture suits this machine, lower is better
+fn arch_rank(arch: &str) -> u8 {
+    if arch == std::env::consts::ARCH {
+        0
+    } else if arch == "noarch" {
+        1
+    } else {
+        2
+    }
+}
+
+/// A check that an rpm file has the size and checksum primary gives for
+/// `package`. A checksum of a type we cannot compute fails the check, as
+/// the file could not be told apart from a tampered one.
+fn package_verifier(package: &Package) -> impl Fn(&Path) -> Result<()> + Send + Sync + 'static {
+    let size = package.size;
+    let checksum = package.checksum.clone();
+    move |path| {
+        let actual = fs::metadata(path)?.len();
+        if size != 0 && actual != size {
+            anyhow::bail!("{:?} is {} bytes, expected {}", path, actual, size);
+        }
+        match &checksum {
+            Some((typ, expected)) if checksum::supported(typ) => checksum::verify_file(path, typ, expected),
+            Some((typ, _)) => anyhow::bail!("Cannot verify {:?}, {} checksums are not supported", path, typ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Path of `location` within a local repository at `base`
+fn local_file(base: &Path, location: &str) -> Result<PathBuf> {
+    let path = base.join(location);
+    if !path.is_file() {
+        anyhow::bail!("{:?} does not exist", path);
+    }
+    Ok(path)
+}
+
+/// Download a mirror list to `path` unless a recent copy is there. A
+/// stale copy is still better than nothing if the download fails.
+fn refresh_mirror_file(downloader: &Downloader, url: &str, path: &Path, network: &NetworkOptions) -> Result<()> {
+    let fresh = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age < MIRRORS_MAX_AGE)
+        .unwrap_or(false);
+    if fresh {
+        return Ok(());
+    }
+    
+    let download = Download::new(vec![url.to_string()], path.to_path_buf())
+        .without_resume()
+        .network(network.clone());
+    match downloader.fetch(download) {
+        Ok(()) => Ok(()),
+        Err(e) if path.is_file() => {
+            log::warn!("Failed to refresh mirrors from {}, using cached copy: {}", url, e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIMARY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" packages="1">
+<package type="rpm">
+  <name>nano</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="7.2" rel="1"/>
+  <location href="Packages/nano-7.2-1.x86_64.rpm"/>
+</package>
+</metadata>
+"#;
+
+    const UPDATEINFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<updates>
+<update type="security">
+  <id>TEST-2024-1</id>
+  <title>nano fix</title>
+  <pkglist><collection>
+    <package name="nano" arch="x86_64" epoch="0" version="7.2" release="1">
+      <filename>nano-7.2-1.x86_64.rpm</filename>
+    </package>
+  </collection></pkglist>
+</update>
+</updates>
+"#;
+
+    type Files = std::sync::Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>;
+    
+    /// A local HTTP stand-in serving the files put in the returned map by
+    /// path; anything else is 404
+    fn serve() -> (String, Files) {
+        use std::io::{BufRead, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let files = Files::default();
+        let served = files.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                let mut line = String::new();
+                if reader.read_line(&mut request_line).is_err() {
+                    continue;
+                }
+                while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+                    line.clear();
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+                let body = served.lock().unwrap().get(path).cloned();
+                let _ = match body {
+                    Some(body) => {
+                        write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len())
+                            .and_then(|()| stream.write_all(&body))
+                    }
+                    None => stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+                };
+            }
+        });
+        (base, files)
+    }
+    
+    fn write_repo(dir: &Path, updateinfo: &str) {
+        let repodata = dir.join("repodata");
+        fs::create_dir_all(&repodata).unwrap();
+        fs::write(
+            repodata.join("repomd.xml"),
+            r#"<repomd>
+<data type="primary"><location href="repodata/primary.xml"/></data>
+<data type="updateinfo"><location href="repodata/updateinfo.xml"/></data>
+</repomd>"#,
+        )
+        .unwrap();
+        fs::write(repodata.join("primary.xml"), PRIMARY).unwrap();
+        fs::write(repodata.join("updateinfo.xml"), updateinfo).unwrap();
+    }
+
+    fn repo_config(url: &str) -> RepoConfig {
+        RepoConfig {
+            name: "local".to_string(),
+            url: url.to_string(),
+            enabled: true,
+            gpg_check: false,
+            gpg_key: None,
+            metadata_sig: false,
+            mirrorlist: None,
+            metalink: None,
+            network: NetworkOptions::default(),
+        }
+    }
+    
+    fn load_with(repo_config: RepoConfig, cache_dir: &Path) -> Repository {
+        let config = crate::config::Config {
+            cache_dir: cache_dir.to_path_buf(),
+            retries: 0,
+            ..Default::default()
+        };
+        let mut repo = Repository::new(repo_config);
+        repo.load_metadata(cache_dir, &Downloader::new(&config).unwrap()).unwrap();
+        repo
+    }
+    
+    fn load(repo_dir: &Path, cache_dir: &Path) -> Repository {
+        load_with(repo_config(&repo_dir.to_string_lossy()), cache_dir)
+    }
+
+    #[test]
+    fn incomplete_metadata_is_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo_dir, cache_dir) = (dir.path().join("repo"), dir.path().join("cache"));
+        let cache_path = cache_dir.join("local").join("sack.cache");
+
+        write_repo(&repo_dir, "<updates><update></id></updates>");
+        let repo = load(&repo_dir, &cache_dir);
+        assert_eq!(repo.find_package("nano").unwrap().location, "Packages/nano-7.2-1.x86_64.rpm");
+        assert!(repo.advisories.is_empty());
+        assert!(!cache_path.exists());
+
+        // The same repomd.xml loads again, now with its advisories
+        write_repo(&repo_dir, UPDATEINFO);
+        let repo = load(&repo_dir, &cache_dir);
+        assert_eq!(repo.advisories.len(), 1);
+        assert!(cache_path.exists());
+
+        let cached = load(&repo_dir, &cache_dir);
+        assert_eq!(cached.advisories.len(), 1);
+        assert_eq!(cached.find_package("nano").unwrap().location, "Packages/nano-7.2-1.x86_64.rpm");
+    }
+
+    #[test]
+    fn all_builds_are_kept() {
+        let mut repo = Repository::new(repo_config(""));
+        for (arch, version) in [("x86_64", "1.0"), ("x86_64", "1.2"), ("x86_64", "1.1"), ("i686", "1.3")] {
+            repo.add_package(Package::new(
+                crate::package::PackageName::new("zlib", arch).unwrap(),
+                crate::package::Version::new(0, version, "1").unwrap(),
+                String::new(),
+            ));
+        }
+
+        assert_eq!(repo.builds("zlib", "x86_64").len(), 3);
+        assert_eq!(repo.find_package("zlib.i686").unwrap().version.version, "1.3");
+        let best = repo.find_package("zlib").unwrap();
+        if std::env::consts::ARCH == "x86_64" {
+            assert_eq!(best.nevra(), "zlib-0:1.2-1.x86_64");
+        }
+        assert!(repo.contains(best));
+        assert_eq!(repo.list_packages().len(), 2);
+    }
+    
+    #[test]
+    fn filelists_complete_file_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo_dir, cache_dir) = (dir.path().join("repo"), dir.path().join("cache"));
+        let repodata = repo_dir.join("repodata");
+        fs::create_dir_all(&repodata).unwrap();
+        fs::write(
+            repodata.join("repomd.xml"),
+            r#"<repomd>
+<data type="primary"><location href="repodata/primary.xml"/></data>
+<data type="filelists"><location href="repodata/filelists.xml"/></data>
+</repomd>"#,
+        )
+        .unwrap();
+        fs::write(repodata.join("primary.xml"), PRIMARY).unwrap();
+        fs::write(
+            repodata.join("filelists.xml"),
+            r#"<filelists packages="2">
+<package pkgid="1" name="nano" arch="x86_64"><version epoch="0" ver="7.2" rel="1"/>
+  <file>/usr/bin/nano</file><file type="dir">/usr/share/nano</file>
+</package>
+<package pkgid="2" name="nano" arch="x86_64"><version epoch="0" ver="7.1" rel="1"/>
+  <file>/usr/bin/rnano</file>
+</package>
+</filelists>"#,
+        )
+        .unwrap();
+
+        let repo = load(&repo_dir, &cache_dir);
+        assert_eq!(repo.find_package("nano").unwrap().files, ["/usr/bin/nano", "/usr/share/nano"]);
+        let cached = load(&repo_dir, &cache_dir);
+        assert_eq!(cached.find_package("nano").unwrap().files, ["/usr/bin/nano", "/usr/share/nano"]);
+    }
+    
+    #[test]
+    fn packages_are_verified_against_primary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.rpm");
+        fs::write(&path, b"hello").unwrap();
+        let mut package = Package::new(
+            crate::package::PackageName::new("hello", "x86_64").unwrap(),
+            crate::package::Version::new(0, "1.0", "1").unwrap(),
+            String::new(),
+        );
+        package.size = 5;
+        let mut verify = |checksum: (&str, &str)| {
+            package.checksum = Some((checksum.0.to_string(), checksum.1.to_string()));
+            package_verifier(&package)(&path)
+        };
+
+        assert!(verify(("sha", "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")).is_ok());
+        assert!(verify(("sha256", "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824")).is_ok());
+        assert!(verify(("sha256", &"0".repeat(64))).unwrap_err().to_string().contains("mismatch"));
+        assert!(verify(("whirlpool", "00")).unwrap_err().to_string().contains("not supported"));
+
+        package.checksum = None;
+        package.size = 4;
+        assert!(package_verifier(&package)(&path).unwrap_err().to_string().contains("is 5 bytes"));
+    }
+    
+    const REPOMD: &str = r#"<repomd><data type="primary"><location href="repodata/primary.xml"/></data></repomd>"#;
+    
+    /// Put a mirror of the test repository at `prefix`
+    fn add_mirror(files: &Files, prefix: &str, repomd: &str) {
+        let mut files = files.lock().unwrap();
+        files.insert(format!("{}/repodata/repomd.xml", prefix), repomd.as_bytes().to_vec());
+        files.insert(format!("{}/repodata/primary.xml", prefix), PRIMARY.as_bytes().to_vec());
+    }
+    
+    #[test]
+    fn metalink_mirrors_fail_over() {
+        use sha2::{Digest, Sha256};
+        let dir = tempfile::tempdir().unwrap();
+        let (base, files) = serve();
+        // The best mirror is down and the next one is out of date
+        add_mirror(&files, "/stale", &REPOMD.replace("<repomd>", "<repomd><revision>1</revision>"));
+        add_mirror(&files, "/good", REPOMD);
+        let metalink = format!(
+            r#"<metalink><files><file name="repomd.xml">
+<size>{}</size><verification><hash type="sha256">{}</hash></verification>
+<resources>
+<url protocol="http" preference="80">{base}/good/repodata/repomd.xml</url>
+<url protocol="http" preference="100">{base}/down/repodata/repomd.xml</url>
+<url protocol="http" preference="90">{base}/stale/repodata/repomd.xml</url>
+</resources></file></files></metalink>"#,
+            REPOMD.len(),
+            hex::encode(Sha256::digest(REPOMD)),
+        );
+        files.lock().unwrap().insert("/metalink".to_string(), metalink.into_bytes());
+
+        let mut config = repo_config("");
+        config.metalink = Some(format!("{}/metalink", base));
+        let repo = load_with(config, dir.path());
+        let mirrors: Vec<String> = repo.mirrors.iter().map(|m| m.url.clone()).collect();
+        assert_eq!(mirrors, [format!("{}/down", base), format!("{}/stale", base), format!("{}/good", base)]);
+        assert_eq!(repo.find_package("nano").unwrap().location, "Packages/nano-7.2-1.x86_64.rpm");
+        assert_eq!(fs::read_to_string(dir.path().join("local/repodata/repomd.xml")).unwrap(), REPOMD);
+    }
+    
+    #[test]
+    fn mirrorlist_mirrors_fail_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, files) = serve();
+        add_mirror(&files, "/second", REPOMD);
+        let mirrorlist = format!("# mirrors\n{base}/first/\n{base}/second\n");
+        files.lock().unwrap().insert("/mirrorlist".to_string(), mirrorlist.into_bytes());
+
+        let mut config = repo_config("");
+        config.mirrorlist = Some(format!("{}/mirrorlist", base));
+        let repo = load_with(config, dir.path());
+        assert_eq!(repo.mirrors.len(), 2);
+        assert_eq!(repo.find_package("nano").unwrap().location, "Packages/nano-7.2-1.x86_64.rpm");
+    }
+}
@@ -1,44 +1,228 @@
-use crate::config::Config;
+use crate::advisory::{Advisory, AdvisoryFilter};
+use crate::comps::{Category, Environment, Group};
+use crate::config::{Config, NetworkOptions, Repository as RepoConfig};
+use crate::db::PackageDatabase;
+use crate::deltarpm::{self, Delta};
+use crate::download::{Download, Downloader};
+use crate::keyring::{Keyring, MissingKey};
+use crate::progress::{format_size, DownloadProgress};
+use crate::module_db::ModuleDatabase;
+use crate::modulemd::ModuleStream;
+use crate::package::Package;
 use crate::repo::Repository as Repo;
+use crate::rpm;
 use anyhow::Result;
-use std::collections::HashMap;
-use log;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the transient repository holding rpms given on the command line
+pub const COMMANDLINE_REPO: &str = "@commandline";
+
+/// Whether a command line argument names an rpm file or URL rather than a
+/// package
+pub fn is_rpm_argument(arg: &str) -> bool {
+    arg.ends_with(".rpm") && (arg.contains("://") || arg.contains('/') || Path::new(arg).is_file())
+}
+
+/// Ask `question` on the terminal. Anything but yes, including no answer
+/// because input is not a terminal, means no.
+fn confirm(question: &str) -> bool {
+    use std::io::{BufRead, Write};
+    print!("{} [y/N]: ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// An upgrade to rebuild from a delta, and the full download to fall back to
+struct DeltaRebuild<'a> {
+    package: &'a Package,
+    delta: &'a Delta,
+    delta_path: PathBuf,
+    base_rpm: Option<PathBuf>,
+    dest: PathBuf,
+    fallback: Download,
+}
 
 #[derive(Debug)]
 pub struct RepositoryManager {
     pub repositories: HashMap<String, Repo>,
     pub config: Config,
+    /// `(module, stream)` pairs whose artifacts take part in resolution
+    active_streams: HashSet<(String, String)>,
+    downloader: Downloader,
 }
 
 impl RepositoryManager {
-    pub fn new(config: Config) -> Self {
-        Self {
+    pub fn new(config: Config) -> Result<Self> {
+        let downloader = Downloader::new(&config)?;
+        Ok(Self {
             repositories: HashMap::new(),
             config,
-        }
+            active_streams: HashSet::new(),
+            downloader,
+        })
+    }
+    
+    /// Report download progress to `progress` instead of the terminal
+    pub fn set_progress(&mut self, progress: Arc<dyn DownloadProgress>) {
+        self.downloader.set_progress(progress);
     }
     
+    /// Load all enabled repositories at once. Each loads on its own thread;
+    /// their downloads share the downloader's connection limits.
     pub fn load_repositories(&mut self) -> Result<()> {
         log::info!("Loading repositories");
         
-        for (name, repo_config) in &self.config.repositories {
-            if !repo_config.enabled {
-                log::debug!("Skipping disabled repository: {}", name);
-                continue;
-            }
-            
-            log::info!("Loading repository: {}", name);
-            let mut repo = Repo::new(repo_config.clone());
-            repo.load_metadata(&self.config.cache_dir)?;
+        let cache_dir = &self.config.cache_dir;
+        let downloader = &self.downloader;
+        let loaded: Vec<(String, Result<Repo>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .config
+                .repositories
+                .iter()
+                .filter(|(name, repo_config)| {
+                    if !repo_config.enabled {
+                        log::debug!("Skipping disabled repository: {}", name);
+                    }
+                    repo_config.enabled
+                })
+                .map(|(name, repo_config)| {
+                    log::info!("Loading repository: {}", name);
+                    let handle = scope.spawn(move || {
+                        let mut repo = Repo::new(repo_config.clone());
+                        repo.load_metadata(cache_dir, downloader)?;
+                        Ok(repo)
+                    });
+                    (name.clone(), handle)
+                })
+                .collect();
             
-            self.repositories.insert(name.clone(), repo);
+            handles
+                .into_iter()
+                .map(|(name, handle)| {
+                    let result = handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("Loading repository {} panicked", name)));
+                    (name, result)
+                })
+                .collect()
+        });
+        
+        for (name, result) in loaded {
+            let mut repo = result?;
+            repo.set_active_streams(&self.active_streams);
+            self.repositories.insert(name, repo);
         }
         
         log::info!("Loaded {} repositories", self.repositories.len());
         Ok(())
     }
     
+    /// Decide which module streams are active: the enabled stream of each
+    /// module, or its default stream unless the module is disabled.
+    pub fn select_module_streams(&mut self, module_db: &ModuleDatabase) {
+        let mut modules: Vec<&str> = self
+            .repositories
+            .values()
+            .flat_map(|repo| repo.modules.streams.iter().map(|s| s.name.as_str()))
+            .collect();
+        modules.sort_unstable();
+        modules.dedup();
+        
+        let mut active = HashSet::new();
+        for module in modules {
+            if module_db.is_disabled(module) {
+                continue;
+            }
+            let stream = module_db.enabled_stream(module).or_else(|| self.default_stream(module));
+            if let Some(stream) = stream {
+                active.insert((module.to_string(), stream.to_string()));
+            }
+        }
+        
+        for repo in self.repositories.values_mut() {
+            repo.set_active_streams(&active);
+        }
+        self.active_streams = active;
+    }
+    
+    /// Read rpms at local paths or URLs into the `@commandline` repository,
+    /// downloading remote ones to the cache first. Returns the names to
+    /// look the packages up by.
+    pub fn add_commandline_rpms(&mut self, args: &[String]) -> Result<Vec<String>> {
+        let download_dir = self.config.cache_dir.join(COMMANDLINE_REPO).join("packages");
+        let mut paths = Vec::with_capacity(args.len());
+        let mut downloads = Vec::new();
+        for arg in args {
+            if let Some(path) = arg.strip_prefix("file://") {
+                paths.push(PathBuf::from(path));
+            } else if arg.contains("://") {
+                let file_name = arg.rsplit('/').next().unwrap_or_default();
+                let dest = download_dir.join(file_name);
+                downloads.push(Download::new(vec![arg.clone()], dest.clone()));
+                paths.push(dest);
+            } else {
+                paths.push(PathBuf::from(arg));
+            }
+        }
+        if !downloads.is_empty() {
+            fs::create_dir_all(&download_dir)?;
+        }
+        for result in self.downloader.fetch_all(downloads) {
+            result?;
+        }
+        
+        let repo = self.repositories.entry(COMMANDLINE_REPO.to_string()).or_insert_with(|| {
+            Repo::new(RepoConfig {
+                name: COMMANDLINE_REPO.to_string(),
+                url: "/".to_string(), // Locations are absolute paths
+                enabled: true,
+                gpg_check: self.config.localpkg_gpgcheck,
+                gpg_key: None,
+                metadata_sig: false,
+                mirrorlist: None,
+                metalink: None,
+                network: NetworkOptions::default(),
+            })
+        });
+        let mut names = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.canonicalize().map_err(|e| anyhow::anyhow!("Cannot open {:?}: {}", path, e))?;
+            let mut package = rpm::read(&path)?.to_package()?;
+            if package.name.arch.as_ref() == "src" {
+                anyhow::bail!("{:?} is a source rpm and cannot be installed", path);
+            }
+            package.location = path.to_string_lossy().into_owned();
+            log::info!("Added {} from {:?}", package.nevra(), path);
+            names.push(package.name.name.clone());
+            repo.add_package(package);
+        }
+        Ok(names)
+    }
+    
+    /// The package of that name read from an rpm given on the command line
+    pub fn commandline_package(&self, name: &str) -> Option<&Package> {
+        self.repositories.get(COMMANDLINE_REPO)?.find_package(name)
+    }
+    
     pub fn find_package(&self, package_name: &str) -> Option<&crate::package::Package> {
+        // Rpms given on the command line are what the user asked for
+        if let Some(pkg) = self.commandline_package(package_name) {
+            return Some(pkg);
+        }
+        // Artifacts of active module streams take precedence over
+        // non-modular packages of the same name from any repository
+        for repo in self.repositories.values() {
+            if let Some(pkg) = repo.find_modular_package(package_name) {
+                return Some(pkg);
+            }
+        }
         for repo in self.repositories.values() {
             if let Some(pkg) = repo.find_package(package_name) {
                 return Some(pkg);
@@ -54,13 +238,457 @@ impl RepositoryManager {
             results.extend(repo.search(query));
         }
         
+        // Hide non-modular packages shadowed by a module in another repository
+        results.retain(|pkg| {
+            self.repositories.values().all(|repo| repo.find_modular_package(&pkg.name.name).is_none())
+                || self.repositories.values().any(|repo| repo.is_active_modular(pkg))
+        });
+        
         results.sort_by(|a, b| a.name.name.cmp(&b.name.name));
         results
     }
     
+    /// All module stream contexts, sorted by module name and stream
+    pub fn module_streams(&self) -> Vec<&ModuleStream> {
+        let mut streams: Vec<&ModuleStream> = self.repositories
+            .values()
+            .flat_map(|repo| repo.modules.streams.iter())
+            .collect();
+        streams.sort_by(|a, b| {
+            (&a.name, &a.stream, a.version, &a.context).cmp(&(&b.name, &b.stream, b.version, &b.context))
+        });
+        streams
+    }
+    
+    pub fn default_stream(&self, module: &str) -> Option<&str> {
+        self.repositories.values().find_map(|repo| repo.modules.default_stream(module))
+    }
+    
+    pub fn default_profiles(&self, module: &str, stream: &str) -> Vec<&str> {
+        self.repositories
+            .values()
+            .flat_map(|repo| repo.modules.default_profiles(module, stream))
+            .map(|profile| profile.as_str())
+            .collect()
+    }
+    
+    /// All comps groups, with duplicates from several repositories merged
+    pub fn groups(&self) -> Vec<&Group> {
+        let mut groups: Vec<&Group> = Vec::new();
+        for repo in self.repositories.values() {
+            for group in &repo.comps.groups {
+                if !groups.iter().any(|g| g.id == group.id) {
+                    groups.push(group);
+                }
+            }
+        }
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+    
+    /// Find a group by id or by its (case-insensitive) name
+    pub fn find_group(&self, id: &str) -> Option<&Group> {
+        self.repositories
+            .values()
+            .flat_map(|repo| repo.comps.groups.iter())
+            .find(|group| group.id == id || group.name.eq_ignore_ascii_case(id))
+    }
+    
+    pub fn find_environment(&self, id: &str) -> Option<&Environment> {
+        self.repositories
+            .values()
+            .flat_map(|repo| repo.comps.environments.iter())
+            .find(|env| env.id == id || env.name.eq_ignore_ascii_case(id))
+    }
+    
+    pub fn find_category(&self, id: &str) -> Option<&Category> {
+        self.repositories
+            .values()
+            .flat_map(|repo| repo.comps.categories.iter())
+            .find(|category| category.id == id || category.name.eq_ignore_ascii_case(id))
+    }
+    
+    pub fn environments(&self) -> Vec<&Environment> {
+        let mut environments: Vec<&Environment> = Vec::new();
+        for repo in self.repositories.values() {
+            for env in &repo.comps.environments {
+                if !environments.iter().any(|e| e.id == env.id) {
+                    environments.push(env);
+                }
+            }
+        }
+        environments.sort_by_key(|env| (env.display_order.unwrap_or(u32::MAX), env.name.clone()));
+        environments
+    }
+    
+    /// Find the package `installed` should be upgraded to, together with the
+    /// selected advisories it fixes. With an advisory filter the target is the
+    /// lowest available build that contains every selected fix.
+    pub fn find_upgrade(
+        &self,
+        installed: &Package,
+        filter: &AdvisoryFilter,
+    ) -> Option<(&Package, Vec<&Advisory>)> {
+        let candidates: Vec<&Package> = self
+            .repositories
+            .values()
+            .flat_map(|repo| repo.candidates(&installed.name.name, &installed.name.arch))
+            .filter(|pkg| pkg.version > installed.version)
+            .collect();
+        
+        if filter.is_empty() {
+            let newest = candidates.into_iter().max_by(|a, b| a.version.cmp(&b.version))?;
+            return Some((newest, Vec::new()));
+        }
+        
+        let advisories: Vec<&Advisory> = self
+            .repositories
+            .values()
+            .flat_map(|repo| repo.advisories.iter())
+            .filter(|advisory| filter.matches(advisory) && advisory.fixes(installed).is_some())
+            .collect();
+        let target = advisories
+            .iter()
+            .filter_map(|advisory| advisory.fixes(installed))
+            .map(|fix| &fix.version)
+            .max()?;
+        
+        let minimal = candidates
+            .into_iter()
+            .filter(|pkg| pkg.version >= *target)
+            .min_by(|a, b| a.version.cmp(&b.version))?;
+        Some((minimal, advisories))
+    }
+    
+    /// The contents of a key file at a path or URL, fetching remote ones
+    /// into the cache of `repo_name`
+    pub fn read_key(&self, source: &str, repo_name: &str) -> Result<Vec<u8>> {
+        if let Some(path) = source.strip_prefix("file://") {
+            return Ok(fs::read(path)?);
+        }
+        if !source.contains("://") {
+            return Ok(fs::read(source)?);
+        }
+        let file_name = source.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("key");
+        let dir = self.config.cache_dir.join(repo_name).join("keys");
+        fs::create_dir_all(&dir)?;
+        let dest = dir.join(file_name);
+        // Keys can be replaced upstream, so never resume an old copy
+        let _ = fs::remove_file(&dest);
+        self.downloader.fetch(Download::new(vec![source.to_string()], dest.clone()).without_resume())?;
+        Ok(fs::read(dest)?)
+    }
+    
+    /// Check the signatures of downloaded rpms from repositories with
+    /// gpg_check on. The keys a repository lists in gpg_key are imported
+    /// the first time one of its packages is signed with a key that is not
+    /// imported yet; any other failure refuses the package.
+    pub fn verify_signatures(&self, packages: &[&Package], paths: &[Option<PathBuf>], keyring: &mut Keyring) -> Result<()> {
+        let mut imported_from = HashSet::new();
+        for (package, path) in packages.iter().zip(paths) {
+            let Some(path) = path else { continue };
+            let Some(repo) = self.repositories.values().find(|repo| repo.contains(package)) else { continue };
+            if !repo.config.gpg_check {
+                log::debug!("Not checking the signature of {}, gpg_check is off for {}", package.nevra(), repo.config.name);
+                continue;
+            }
+            
+            let missing = match keyring.verify_rpm(path) {
+                Ok(()) => continue,
+                Err(e) => match e.downcast::<MissingKey>() {
+                    Ok(missing) => missing,
+                    Err(e) => anyhow::bail!("Refusing {}: {}", package.nevra(), e),
+                },
+            };
+            let sources = repo.config.gpg_key.as_deref().unwrap_or("");
+            if sources.trim().is_empty() || !imported_from.insert(repo.config.name.clone()) {
+                anyhow::bail!("Refusing {}: {}", package.nevra(), missing);
+            }
+            for source in sources.split_whitespace() {
+                let data = self.read_key(source, &repo.config.name)?;
+                for certificate in keyring.new_certificates(&data)? {
+                    let key_id = certificate.primary.key_id() as u32;
+                    println!("Importing GPG key 0x{:08X}:", key_id);
+                    println!(" Userid     : \"{}\"", certificate.user_id());
+                    println!(" Fingerprint: {}", certificate.primary.fingerprint_hex());
+                    println!(" From       : {}", source);
+                    if !self.config.assumeyes && !confirm("Is this ok") {
+                        anyhow::bail!("Refusing {}: key 0x{:08X} was not imported", package.nevra(), key_id);
+                    }
+                    keyring.add(certificate)?;
+                }
+            }
+            keyring.verify_rpm(path).map_err(|e| match e.downcast::<MissingKey>() {
+                Ok(missing) => anyhow::anyhow!(
+                    "Refusing {}: the keys listed for repository {} do not include its signing key 0x{:08X}",
+                    package.nevra(),
+                    repo.config.name,
+                    missing.key_id
+                ),
+                Err(e) => anyhow::anyhow!("Refusing {}: {}", package.nevra(), e),
+            })?;
+        }
+        Ok(())
+    }
+    
+    /// Local paths of the rpm files of `packages`, which must have been
+    /// looked up through this manager. Rpms that are not local or cached
+    /// are downloaded concurrently, whichever repositories they come from.
+    /// Upgrades of `installed` packages are rebuilt from deltas where that
+    /// saves downloading, falling back to the full rpm if that fails.
+    /// Packages without a known location, such as the mock packages used
+    /// when metadata is missing, get `None`.
+    pub fn download_packages(&self, packages: &[&Package], installed: &PackageDatabase) -> Result<Vec<Option<PathBuf>>> {
+        let use_deltas = self.config.deltarpm && deltarpm::available();
+        let mut paths = Vec::with_capacity(packages.len());
+        let mut downloads = Vec::new();
+        let mut rebuilds = Vec::new();
+        let mut delta_downloads = Vec::new();
+        for package in packages {
+            if package.location.is_empty() {
+                paths.push(None);
+                continue;
+            }
+            let repo = self
+                .repositories
+                .values()
+                .find(|repo| repo.contains(package))
+                .ok_or_else(|| anyhow::anyhow!("Package {} does not belong to any repository", package.nevra()))?;
+            let (path, download) = repo.package_source(package, &self.config.cache_dir)?;
+            let delta = match (&download, use_deltas) {
+                (Some(_), true) => self.choose_delta(repo, package, installed),
+                _ => None,
+            };
+            match (download, delta) {
+                (Some(download), Some((delta, base_rpm))) => {
+                    let (delta_path, delta_download) = repo.delta_download(delta, &self.config.cache_dir)?;
+                    delta_downloads.push(delta_download);
+                    rebuilds.push(DeltaRebuild {
+                        package,
+                        delta,
+                        delta_path,
+                        base_rpm,
+                        dest: path.clone(),
+                        fallback: download,
+                    });
+                }
+                (Some(download), None) => downloads.push(download),
+                (None, _) => {}
+            }
+            paths.push(Some(path));
+        }
+        
+        if !downloads.is_empty() || !rebuilds.is_empty() {
+            log::info!("Downloading {} packages and {} deltas", downloads.len(), rebuilds.len());
+        }
+        let full_count = downloads.len();
+        downloads.extend(delta_downloads);
+        let mut results = self.downloader.fetch_all(downloads).into_iter();
+        for result in results.by_ref().take(full_count) {
+            result?;
+        }
+        
+        let (mut full_size, mut delta_size) = (0, 0);
+        let mut fallbacks = Vec::new();
+        for (rebuild, result) in rebuilds.into_iter().zip(results) {
+            let rebuilt = result.and_then(|()| {
+                deltarpm::rebuild(
+                    rebuild.package,
+                    rebuild.delta,
+                    &rebuild.delta_path,
+                    rebuild.base_rpm.as_deref(),
+                    &rebuild.dest,
+                )
+            });
+            let _ = std::fs::remove_file(&rebuild.delta_path);
+            match rebuilt {
+                Ok(()) => {
+                    log::debug!("Rebuilt {} from a delta", rebuild.package.nevra());
+                    full_size += rebuild.package.size;
+                    delta_size += rebuild.delta.size;
+                }
+                Err(e) => {
+                    log::warn!("Cannot use delta for {}, downloading the full rpm: {}", rebuild.package.nevra(), e);
+                    fallbacks.push(rebuild.fallback);
+                }
+            }
+        }
+        if full_size > 0 {
+            log::info!(
+                "Deltas reduced {} of upgrades to {} ({}% saved)",
+                format_size(full_size),
+                format_size(delta_size),
+                100 - delta_size * 100 / full_size
+            );
+        }
+        for result in self.downloader.fetch_all(fallbacks) {
+            result?;
+        }
+        Ok(paths)
+    }
+    
+    /// The delta to rebuild `package` from, if one applies to the installed
+    /// build and is smaller than the rpm, along with the base build's rpm
+    /// if it is still cached. Without that rpm the base files are read from
+    /// the system, which only works when installing into `/`.
+    fn choose_delta<'a>(
+        &self,
+        repo: &'a Repo,
+        package: &Package,
+        installed: &PackageDatabase,
+    ) -> Option<(&'a Delta, Option<PathBuf>)> {
+        let key = format!("{}.{}", package.name.name, package.name.arch);
+        let base = &installed.get_installed(&key)?.package;
+        let delta = deltarpm::choose(repo.deltas_for(package), &base.version, package.size)?;
+        
+        let base_rpm = self
+            .repositories
+            .values()
+            .find_map(|repo| repo.cached_rpm(base, &self.config.cache_dir));
+        if base_rpm.is_none() && self.config.install_root != Path::new("/") {
+            return None;
+        }
+        Some((delta, base_rpm))
+    }
+    
     pub fn update(&mut self) -> Result<()> {
         log::info!("Updating repository metadata");
         self.repositories.clear();
         self.load_repositories()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advisory::{AdvisoryKind, AdvisoryPackage};
+    use crate::package::{PackageName, Version};
+
+    fn package(name: &str, arch: &str, version: &str, release: &str) -> Package {
+        Package::new(
+            PackageName::new(name, arch).unwrap(),
+            Version::new(0, version, release).unwrap(),
+            String::new(),
+        )
+    }
+
+    fn security_advisory(id: &str, fixed: &Package) -> Advisory {
+        Advisory {
+            id: id.to_string(),
+            kind: AdvisoryKind::Security,
+            severity: Some("Important".to_string()),
+            title: String::new(),
+            issued: String::new(),
+            references: Vec::new(),
+            packages: vec![AdvisoryPackage {
+                name: fixed.name.name.clone(),
+                arch: fixed.name.arch.to_string(),
+                version: fixed.version.clone(),
+                filename: String::new(),
+            }],
+        }
+    }
+
+    fn manager(packages: Vec<Package>, advisories: Vec<Advisory>) -> RepositoryManager {
+        let mut manager = RepositoryManager::new(Config::default()).unwrap();
+        let mut repo = Repo::new(RepoConfig {
+            name: "test".to_string(),
+            url: String::new(),
+            enabled: true,
+            gpg_check: false,
+            gpg_key: None,
+            metadata_sig: false,
+            mirrorlist: None,
+            metalink: None,
+            network: NetworkOptions::default(),
+        });
+        for pkg in packages {
+            repo.add_package(pkg);
+        }
+        repo.advisories = advisories;
+        manager.repositories.insert("test".to_string(), repo);
+        manager
+    }
+
+    #[test]
+    fn advisory_upgrade_picks_lowest_fixing_build() {
+        let fixed = package("openssl", "x86_64", "3.0.9", "2");
+        let manager = manager(
+            vec![
+                package("openssl", "x86_64", "3.0.9", "1"),
+                fixed.clone(),
+                package("openssl", "x86_64", "3.1.1", "1"),
+                package("openssl", "i686", "3.1.2", "1"),
+            ],
+            vec![security_advisory("FEDORA-2023-1", &fixed)],
+        );
+        let installed = package("openssl", "x86_64", "3.0.8", "1");
+
+        let filter = AdvisoryFilter { security: true, ..Default::default() };
+        let (target, fixes) = manager.find_upgrade(&installed, &filter).unwrap();
+        assert_eq!(target.nevra(), "openssl-0:3.0.9-2.x86_64");
+        assert_eq!(fixes.len(), 1);
+
+        let (target, fixes) = manager.find_upgrade(&installed, &AdvisoryFilter::default()).unwrap();
+        assert_eq!(target.nevra(), "openssl-0:3.1.1-1.x86_64");
+        assert!(fixes.is_empty());
+    }
+
+    #[test]
+    fn advisory_upgrade_needs_a_fixing_build() {
+        let fixed = package("openssl", "x86_64", "3.0.9", "2");
+        let manager = manager(
+            vec![package("openssl", "x86_64", "3.0.9", "1")],
+            vec![security_advisory("FEDORA-2023-1", &fixed)],
+        );
+        let installed = package("openssl", "x86_64", "3.0.8", "1");
+
+        let filter = AdvisoryFilter { advisories: vec!["fedora-2023-1".to_string()], ..Default::default() };
+        assert!(manager.find_upgrade(&installed, &filter).is_none());
+        let filter = AdvisoryFilter { bugfix: true, ..Default::default() };
+        assert!(manager.find_upgrade(&installed, &filter).is_none());
+    }
+
+    #[test]
+    fn only_active_module_streams_are_visible() {
+        use crate::modulemd::ModuleDefaults;
+
+        let stream = |name: &str, stream: &str, artifact: &Package| ModuleStream {
+            name: name.to_string(),
+            stream: stream.to_string(),
+            artifacts: vec![artifact.nevra()],
+            ..Default::default()
+        };
+        let (node18, node20) = (package("nodejs", "x86_64", "18.17.1", "1"), package("nodejs", "x86_64", "20.8.0", "1"));
+        let postgres = package("postgresql", "x86_64", "15.4", "1");
+        let mut manager = manager(vec![package("nodejs", "x86_64", "16.0.0", "1"), package("postgresql", "x86_64", "16.0", "1")], Vec::new());
+        let repo = manager.repositories.get_mut("test").unwrap();
+        repo.modular_packages = vec![node18.clone(), node20.clone(), postgres.clone()];
+        repo.modules.streams = vec![stream("nodejs", "18", &node18), stream("nodejs", "20", &node20), stream("postgresql", "15", &postgres)];
+        repo.modules.defaults.insert(
+            "nodejs".to_string(),
+            ModuleDefaults { module: "nodejs".to_string(), stream: Some("18".to_string()), ..Default::default() },
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut module_db = ModuleDatabase::new(dir.path().join("modules.json"));
+        let visible = |manager: &mut RepositoryManager, module_db: &ModuleDatabase, name: &str| {
+            manager.select_module_streams(module_db);
+            manager.find_package(name).unwrap().nevra()
+        };
+
+        // Default streams are active, modules without one are not
+        assert_eq!(visible(&mut manager, &module_db, "nodejs"), node18.nevra());
+        assert_eq!(visible(&mut manager, &module_db, "postgresql"), "postgresql-0:16.0-1.x86_64");
+
+        module_db.switch_stream("nodejs", "20").unwrap();
+        module_db.enable("postgresql", "15").unwrap();
+        assert_eq!(visible(&mut manager, &module_db, "nodejs"), node20.nevra());
+        assert_eq!(visible(&mut manager, &module_db, "postgresql"), postgres.nevra());
+
+        module_db.disable("nodejs").unwrap();
+        assert_eq!(visible(&mut manager, &module_db, "nodejs"), "nodejs-0:16.0.0-1.x86_64");
+        module_db.reset("nodejs").unwrap();
+        assert_eq!(visible(&mut manager, &module_db, "nodejs"), node18.nevra());
+    }
+}
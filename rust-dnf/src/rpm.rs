@@ -0,0 +1,801 @@
+use crate::package::{
+    ChangelogEntry, Dependency, FileEntry, FileTrigger, Package, PackageName, Script, Scriptlet, ScriptletKind,
+    Trigger, TriggerKind, Version,
+};
+use crate::primary::Interner;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const LEAD_SIZE: usize = 96;
+const HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+
+/// Limits rpm itself enforces, so a corrupt file cannot ask for gigabytes
+const MAX_INDEX_ENTRIES: u32 = 0x10000;
+const MAX_DATA_SIZE: u32 = 256 * 1024 * 1024;
+
+/// Tag numbers, as in rpmtag.h
+pub mod tag {
+    pub const HEADER_I18NTABLE: u32 = 100;
+
+    pub const SIG_SIZE: u32 = 1000;
+    pub const SIG_LONGSIZE: u32 = 270;
+    pub const SIG_DSA: u32 = 267; // Of the main header
+    pub const SIG_RSA: u32 = 268;
+    pub const SIG_PGP: u32 = 1002; // Of the main header and payload
+    pub const SIG_GPG: u32 = 1005;
+
+    pub const NAME: u32 = 1000;
+    pub const VERSION: u32 = 1001;
+    pub const RELEASE: u32 = 1002;
+    pub const EPOCH: u32 = 1003;
+    pub const SUMMARY: u32 = 1004;
+    pub const DESCRIPTION: u32 = 1005;
+    pub const LICENSE: u32 = 1014;
+    pub const URL: u32 = 1020;
+    pub const ARCH: u32 = 1022;
+    pub const PREIN: u32 = 1023;
+    pub const POSTIN: u32 = 1024;
+    pub const PREUN: u32 = 1025;
+    pub const POSTUN: u32 = 1026;
+    pub const OLDFILENAMES: u32 = 1027;
+    pub const FILESIZES: u32 = 1028;
+    pub const FILEMODES: u32 = 1030;
+    pub const FILERDEVS: u32 = 1033;
+    pub const FILEMTIMES: u32 = 1034;
+    pub const FILEDIGESTS: u32 = 1035;
+    pub const FILELINKTOS: u32 = 1036;
+    pub const FILEFLAGS: u32 = 1037;
+    pub const FILEUSERNAME: u32 = 1039;
+    pub const FILEGROUPNAME: u32 = 1040;
+    pub const PROVIDENAME: u32 = 1047;
+    pub const REQUIREFLAGS: u32 = 1048;
+    pub const REQUIRENAME: u32 = 1049;
+    pub const REQUIREVERSION: u32 = 1050;
+    pub const CONFLICTFLAGS: u32 = 1053;
+    pub const CONFLICTNAME: u32 = 1054;
+    pub const CONFLICTVERSION: u32 = 1055;
+    pub const TRIGGERSCRIPTS: u32 = 1065;
+    pub const TRIGGERNAME: u32 = 1066;
+    pub const TRIGGERVERSION: u32 = 1067;
+    pub const TRIGGERFLAGS: u32 = 1068;
+    pub const TRIGGERINDEX: u32 = 1069;
+    pub const CHANGELOGTIME: u32 = 1080;
+    pub const CHANGELOGNAME: u32 = 1081;
+    pub const CHANGELOGTEXT: u32 = 1082;
+    pub const PREINPROG: u32 = 1085;
+    pub const POSTINPROG: u32 = 1086;
+    pub const PREUNPROG: u32 = 1087;
+    pub const POSTUNPROG: u32 = 1088;
+    pub const OBSOLETENAME: u32 = 1090;
+    pub const TRIGGERSCRIPTPROG: u32 = 1092;
+    pub const FILEDEVICES: u32 = 1095;
+    pub const FILEINODES: u32 = 1096;
+    pub const FILELANGS: u32 = 1097;
+    pub const PROVIDEFLAGS: u32 = 1112;
+    pub const PROVIDEVERSION: u32 = 1113;
+    pub const OBSOLETEFLAGS: u32 = 1114;
+    pub const OBSOLETEVERSION: u32 = 1115;
+    pub const DIRINDEXES: u32 = 1116;
+    pub const BASENAMES: u32 = 1117;
+    pub const DIRNAMES: u32 = 1118;
+    pub const FILECOLORS: u32 = 1140;
+    pub const PRETRANS: u32 = 1151;
+    pub const POSTTRANS: u32 = 1152;
+    pub const PRETRANSPROG: u32 = 1153;
+    pub const POSTTRANSPROG: u32 = 1154;
+    pub const LONGFILESIZES: u32 = 5008;
+    pub const FILECAPS: u32 = 5010;
+    pub const FILEDIGESTALGO: u32 = 5011;
+    pub const PREINFLAGS: u32 = 5020;
+    pub const POSTINFLAGS: u32 = 5021;
+    pub const PREUNFLAGS: u32 = 5022;
+    pub const POSTUNFLAGS: u32 = 5023;
+    pub const PRETRANSFLAGS: u32 = 5024;
+    pub const POSTTRANSFLAGS: u32 = 5025;
+    pub const TRIGGERSCRIPTFLAGS: u32 = 5027;
+    pub const RECOMMENDNAME: u32 = 5046;
+    pub const RECOMMENDVERSION: u32 = 5047;
+    pub const RECOMMENDFLAGS: u32 = 5048;
+    pub const SUGGESTNAME: u32 = 5049;
+    pub const SUGGESTVERSION: u32 = 5050;
+    pub const SUGGESTFLAGS: u32 = 5051;
+    pub const SUPPLEMENTNAME: u32 = 5052;
+    pub const SUPPLEMENTVERSION: u32 = 5053;
+    pub const SUPPLEMENTFLAGS: u32 = 5054;
+    pub const ENHANCENAME: u32 = 5055;
+    pub const ENHANCEVERSION: u32 = 5056;
+    pub const ENHANCEFLAGS: u32 = 5057;
+    pub const FILETRIGGERSCRIPTS: u32 = 5066;
+    pub const FILETRIGGERSCRIPTPROG: u32 = 5067;
+    pub const FILETRIGGERSCRIPTFLAGS: u32 = 5068;
+    pub const FILETRIGGERNAME: u32 = 5069;
+    pub const FILETRIGGERINDEX: u32 = 5070;
+    pub const FILETRIGGERFLAGS: u32 = 5072;
+    pub const TRANSFILETRIGGERSCRIPTS: u32 = 5076;
+    pub const TRANSFILETRIGGERSCRIPTPROG: u32 = 5077;
+    pub const TRANSFILETRIGGERSCRIPTFLAGS: u32 = 5078;
+    pub const TRANSFILETRIGGERNAME: u32 = 5079;
+    pub const TRANSFILETRIGGERINDEX: u32 = 5080;
+    pub const TRANSFILETRIGGERFLAGS: u32 = 5082;
+    pub const FILETRIGGERPRIORITIES: u32 = 5084;
+    pub const TRANSFILETRIGGERPRIORITIES: u32 = 5085;
+    pub const PAYLOADDIGEST: u32 = 5092;
+    pub const PAYLOADDIGESTALGO: u32 = 5093;
+}
+
+// Dependency flags (rpmsenseFlags)
+const SENSE_LESS: u64 = 1 << 1;
+const SENSE_GREATER: u64 = 1 << 2;
+const SENSE_EQUAL: u64 = 1 << 3;
+const SENSE_TRIGGERIN: u64 = 1 << 16;
+const SENSE_TRIGGERUN: u64 = 1 << 17;
+const SENSE_TRIGGERPOSTUN: u64 = 1 << 18;
+const SENSE_RPMLIB: u64 = 1 << 24;
+const SENSE_TRIGGERPREIN: u64 = 1 << 25;
+
+/// Priority of file triggers that do not set one
+const DEFAULT_FILE_TRIGGER_PRIORITY: u32 = 1_000_000;
+
+/// The data of one header tag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Char(Vec<u8>),
+    Int8(Vec<u8>),
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    Int64(Vec<u64>),
+    String(String),
+    Bin(Vec<u8>),
+    StringArray(Vec<String>),
+    I18nString(Vec<String>), // One translation per locale of the I18NTABLE tag
+}
+
+/// A header structure: the signature header or the main header of an rpm
+#[derive(Debug, Clone, Default)]
+pub struct Header {
+    pub tags: HashMap<u32, Value>,
+    pub size: u64, // Bytes the header takes in the file, from its magic on
+}
+
+impl Header {
+    /// Read a header structure from its magic to the end of its data store
+    pub fn read<R: Read>(reader: &mut R) -> Result<Header> {
+        let mut intro = [0; 16];
+        reader.read_exact(&mut intro)?;
+        if intro[..4] != HEADER_MAGIC {
+            anyhow::bail!("Bad rpm header magic");
+        }
+        let index_count = u32::from_be_bytes(intro[8..12].try_into()?);
+        let data_size = u32::from_be_bytes(intro[12..16].try_into()?);
+        if index_count > MAX_INDEX_ENTRIES || data_size > MAX_DATA_SIZE {
+            anyhow::bail!("rpm header too large ({} tags, {} bytes)", index_count, data_size);
+        }
+
+        let mut index = vec![0; index_count as usize * 16];
+        reader.read_exact(&mut index)?;
+        let mut data = vec![0; data_size as usize];
+        reader.read_exact(&mut data)?;
+
+        let mut tags = HashMap::new();
+        for entry in index.chunks_exact(16) {
+            let field = |i: usize| u32::from_be_bytes(entry[i * 4..i * 4 + 4].try_into().expect("4 bytes"));
+            let (tag, typ, offset, count) = (field(0), field(1), field(2) as usize, field(3) as usize);
+            let value = parse_value(&data, typ, offset, count)
+                .map_err(|e| anyhow::anyhow!("Bad rpm header tag {}: {}", tag, e))?;
+            tags.insert(tag, value);
+        }
+        let size = 16 + index.len() as u64 + u64::from(data_size);
+        Ok(Header { tags, size })
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&Value> {
+        self.tags.get(&tag)
+    }
+
+    /// A string tag, or the first entry of an array or translated one
+    pub fn string(&self, tag: u32) -> Option<&str> {
+        match self.get(tag)? {
+            Value::String(s) => Some(s),
+            Value::StringArray(strings) | Value::I18nString(strings) => strings.first().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// A translated string in `locale` (like "de_DE"), falling back to the
+    /// same language in another territory, then to the untranslated text
+    pub fn i18n_string(&self, tag: u32, locale: &str) -> Option<&str> {
+        let translations = match self.get(tag)? {
+            Value::I18nString(strings) => strings,
+            _ => return self.string(tag),
+        };
+        let locales = self.strings(tag::HEADER_I18NTABLE);
+        let language = locale.split('_').next().unwrap_or(locale);
+        let position = locales
+            .iter()
+            .position(|l| l == locale)
+            .or_else(|| locales.iter().position(|l| l == language))
+            .or_else(|| locales.iter().position(|l| l.split('_').next() == Some(language)));
+        position
+            .and_then(|i| translations.get(i))
+            .or_else(|| translations.first())
+            .map(String::as_str)
+    }
+
+    /// All strings of a tag, or none if it is missing
+    pub fn strings(&self, tag: u32) -> Vec<String> {
+        match self.get(tag) {
+            Some(Value::String(s)) => vec![s.clone()],
+            Some(Value::StringArray(strings)) | Some(Value::I18nString(strings)) => strings.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// All integers of a tag, whatever their width, or none if it is missing
+    pub fn ints(&self, tag: u32) -> Vec<u64> {
+        match self.get(tag) {
+            Some(Value::Char(v)) | Some(Value::Int8(v)) => v.iter().map(|&i| u64::from(i)).collect(),
+            Some(Value::Int16(v)) => v.iter().map(|&i| u64::from(i)).collect(),
+            Some(Value::Int32(v)) => v.iter().map(|&i| u64::from(i)).collect(),
+            Some(Value::Int64(v)) => v.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn int(&self, tag: u32) -> Option<u64> {
+        self.ints(tag).first().copied()
+    }
+}
+
+fn parse_value(data: &[u8], typ: u32, offset: usize, count: usize) -> Result<Value> {
+    let bytes = |size: usize| -> Result<&[u8]> {
+        let end = size
+            .checked_mul(count)
+            .and_then(|len| offset.checked_add(len))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| anyhow::anyhow!("data out of bounds"))?;
+        Ok(&data[offset..end])
+    };
+    let strings = || -> Result<Vec<String>> {
+        let mut rest = data.get(offset..).ok_or_else(|| anyhow::anyhow!("data out of bounds"))?;
+        // Each string takes at least its terminating NUL, which bounds the
+        // count before anything is allocated for it
+        if count > rest.len() {
+            anyhow::bail!("data out of bounds");
+        }
+        let mut strings = Vec::with_capacity(count);
+        for _ in 0..count {
+            let end = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| anyhow::anyhow!("unterminated string"))?;
+            strings.push(String::from_utf8_lossy(&rest[..end]).into_owned());
+            rest = &rest[end + 1..];
+        }
+        Ok(strings)
+    };
+
+    Ok(match typ {
+        0 => Value::Null,
+        1 => Value::Char(bytes(1)?.to_vec()),
+        2 => Value::Int8(bytes(1)?.to_vec()),
+        3 => Value::Int16(bytes(2)?.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect()),
+        4 => Value::Int32(bytes(4)?.chunks_exact(4).map(|b| u32::from_be_bytes(b.try_into().expect("4 bytes"))).collect()),
+        5 => Value::Int64(bytes(8)?.chunks_exact(8).map(|b| u64::from_be_bytes(b.try_into().expect("8 bytes"))).collect()),
+        6 => Value::String(strings()?.pop().unwrap_or_default()),
+        7 => Value::Bin(bytes(1)?.to_vec()),
+        8 => Value::StringArray(strings()?),
+        9 => Value::I18nString(strings()?),
+        other => anyhow::bail!("unknown type {}", other),
+    })
+}
+
+/// The headers of an rpm file, read up to its payload
+#[derive(Debug)]
+pub struct RpmFile {
+    pub source: bool, // A source rpm, per its lead
+    pub signature: Header,
+    pub header: Header,
+    pub header_offset: u64,  // Where the main header starts
+    pub payload_offset: u64, // Where the compressed cpio archive starts
+    pub size: u64,           // Of the whole file
+}
+
+/// Read the lead, signature header and main header of the rpm at `path`
+pub fn read(path: &Path) -> Result<RpmFile> {
+    let size = fs::metadata(path)?.len();
+    let mut reader = BufReader::new(fs::File::open(path)?);
+
+    let mut lead = [0; LEAD_SIZE];
+    reader
+        .read_exact(&mut lead)
+        .map_err(|_| anyhow::anyhow!("{:?} is not an rpm file", path))?;
+    if lead[..4] != LEAD_MAGIC {
+        anyhow::bail!("{:?} is not an rpm file", path);
+    }
+    if lead[4] < 3 {
+        anyhow::bail!("{:?} is in rpm format version {}, which is not supported", path, lead[4]);
+    }
+    let source = u16::from_be_bytes([lead[6], lead[7]]) == 1;
+
+    let signature = Header::read(&mut reader).map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))?;
+    // The signature header is padded to a multiple of 8 bytes
+    let padding = (8 - signature.size % 8) % 8;
+    std::io::copy(&mut (&mut reader).take(padding), &mut std::io::sink())?;
+    let header_offset = LEAD_SIZE as u64 + signature.size + padding;
+
+    // The signature records the size of the main header plus payload,
+    // which catches truncated downloads before anything else is read
+    let expected = signature.int(tag::SIG_LONGSIZE).or_else(|| signature.int(tag::SIG_SIZE));
+    if let Some(expected) = expected {
+        if header_offset + expected != size {
+            anyhow::bail!("{:?} is {} bytes, its signature header says {}", path, size, header_offset + expected);
+        }
+    }
+
+    let header = Header::read(&mut reader).map_err(|e| anyhow::anyhow!("{:?}: {}", path, e))?;
+    let payload_offset = header_offset + header.size;
+    Ok(RpmFile { source, signature, header, header_offset, payload_offset, size })
+}
+
+/// The locale to pick translated summaries and descriptions in, as
+/// setlocale(3) would choose it for messages
+fn message_locale() -> String {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    // "de_DE.UTF-8@euro" -> "de_DE"
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    match locale {
+        "" | "C" | "POSIX" => "C".to_string(),
+        locale => locale.to_string(),
+    }
+}
+
+/// Name of a file or payload digest algorithm by its number in headers
+pub fn digest_algo(id: u64) -> &'static str {
+    match id {
+        1 => "md5",
+        2 => "sha1",
+        8 => "sha256",
+        9 => "sha384",
+        10 => "sha512",
+        11 => "sha224",
+        _ => "unknown",
+    }
+}
+
+fn comparator(flags: u64) -> Option<&'static str> {
+    match flags & (SENSE_LESS | SENSE_GREATER | SENSE_EQUAL) {
+        f if f == SENSE_EQUAL => Some("="),
+        f if f == SENSE_LESS => Some("<"),
+        f if f == SENSE_LESS | SENSE_EQUAL => Some("<="),
+        f if f == SENSE_GREATER => Some(">"),
+        f if f == SENSE_GREATER | SENSE_EQUAL => Some(">="),
+        _ => None,
+    }
+}
+
+impl RpmFile {
+    /// Dependencies from parallel name, flags and version tags. rpmlib()
+    /// requirements describe rpm's own features and are left out.
+    fn dependencies(&self, names: u32, flags: u32, versions: u32, interner: &mut Interner) -> Vec<Dependency> {
+        let header = &self.header;
+        let all_flags = header.ints(flags);
+        let all_versions = header.strings(versions);
+        header
+            .strings(names)
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| {
+                let flags = all_flags.get(i).copied().unwrap_or(0);
+                if flags & SENSE_RPMLIB != 0 {
+                    return None;
+                }
+                let comparator = comparator(flags);
+                let version = all_versions.get(i).filter(|v| !v.is_empty() && comparator.is_some());
+                Some(Dependency {
+                    name: interner.intern(name),
+                    version: version.map(|v| v.strip_prefix("0:").unwrap_or(v).to_string()),
+                    comparator: version.and(comparator).map(String::from),
+                })
+            })
+            .collect()
+    }
+
+    /// Full paths of the files, from the compressed dirname/basename form
+    /// or the old flat list
+    fn file_paths(&self) -> Vec<String> {
+        let header = &self.header;
+        let basenames = header.strings(tag::BASENAMES);
+        if basenames.is_empty() {
+            return header.strings(tag::OLDFILENAMES);
+        }
+        let dirnames = header.strings(tag::DIRNAMES);
+        let dirindexes = header.ints(tag::DIRINDEXES);
+        basenames
+            .iter()
+            .enumerate()
+            .map(|(i, base)| {
+                let dir = dirindexes.get(i).and_then(|&d| dirnames.get(d as usize));
+                format!("{}{}", dir.map(String::as_str).unwrap_or(""), base)
+            })
+            .collect()
+    }
+
+    fn file_entries(&self) -> Vec<FileEntry> {
+        let header = &self.header;
+        let strings = |tag| header.strings(tag);
+        let ints = |tag| header.ints(tag);
+        let (modes, rdevs, mtimes, flags) = (ints(tag::FILEMODES), ints(tag::FILERDEVS), ints(tag::FILEMTIMES), ints(tag::FILEFLAGS));
+        let (inodes, devices, colors) = (ints(tag::FILEINODES), ints(tag::FILEDEVICES), ints(tag::FILECOLORS));
+        let sizes = match header.get(tag::LONGFILESIZES) {
+            Some(_) => ints(tag::LONGFILESIZES),
+            None => ints(tag::FILESIZES),
+        };
+        let (users, groups, digests) = (strings(tag::FILEUSERNAME), strings(tag::FILEGROUPNAME), strings(tag::FILEDIGESTS));
+        let (links, caps, langs) = (strings(tag::FILELINKTOS), strings(tag::FILECAPS), strings(tag::FILELANGS));
+
+        let int = |v: &[u64], i: usize| v.get(i).copied().unwrap_or(0);
+        let string = |v: &[String], i: usize| v.get(i).cloned().unwrap_or_default();
+        self.file_paths()
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| FileEntry {
+                path,
+                mode: int(&modes, i) as u16,
+                user: string(&users, i),
+                group: string(&groups, i),
+                size: int(&sizes, i),
+                mtime: int(&mtimes, i) as u32,
+                digest: string(&digests, i),
+                link_to: string(&links, i),
+                flags: int(&flags, i) as u32,
+                inode: int(&inodes, i) as u32,
+                device: int(&devices, i) as u32,
+                rdev: int(&rdevs, i) as u16,
+                caps: string(&caps, i),
+                lang: string(&langs, i),
+                color: int(&colors, i) as u32,
+            })
+            .collect()
+    }
+
+    /// A script with its interpreter tag. Scripts without an interpreter
+    /// are run by /bin/sh, as rpmbuild assumes.
+    fn script(&self, body: Option<&str>, prog: Vec<String>, flags: u64) -> Script {
+        let body = body.filter(|body| !body.is_empty()).map(String::from);
+        let interpreter = match prog.is_empty() {
+            true => vec!["/bin/sh".to_string()],
+            false => prog,
+        };
+        Script { interpreter, body, flags: flags as u32 }
+    }
+
+    fn scriptlets(&self) -> Vec<Scriptlet> {
+        let header = &self.header;
+        [
+            (ScriptletKind::PreTrans, tag::PRETRANS, tag::PRETRANSPROG, tag::PRETRANSFLAGS),
+            (ScriptletKind::Pre, tag::PREIN, tag::PREINPROG, tag::PREINFLAGS),
+            (ScriptletKind::Post, tag::POSTIN, tag::POSTINPROG, tag::POSTINFLAGS),
+            (ScriptletKind::PreUn, tag::PREUN, tag::PREUNPROG, tag::PREUNFLAGS),
+            (ScriptletKind::PostUn, tag::POSTUN, tag::POSTUNPROG, tag::POSTUNFLAGS),
+            (ScriptletKind::PostTrans, tag::POSTTRANS, tag::POSTTRANSPROG, tag::POSTTRANSFLAGS),
+        ]
+        .into_iter()
+        .filter(|&(_, body, prog, _)| header.get(body).is_some() || header.get(prog).is_some())
+        .map(|(kind, body, prog, flags)| Scriptlet {
+            kind,
+            script: self.script(header.string(body), header.strings(prog), header.int(flags).unwrap_or(0)),
+        })
+        .collect()
+    }
+
+    fn triggers(&self, interner: &mut Interner) -> Vec<Trigger> {
+        let header = &self.header;
+        let scripts = header.strings(tag::TRIGGERSCRIPTS);
+        let progs = header.strings(tag::TRIGGERSCRIPTPROG);
+        let script_flags = header.ints(tag::TRIGGERSCRIPTFLAGS);
+        let conditions = self.dependencies(tag::TRIGGERNAME, tag::TRIGGERFLAGS, tag::TRIGGERVERSION, interner);
+        let flags = header.ints(tag::TRIGGERFLAGS);
+        let indexes = header.ints(tag::TRIGGERINDEX);
+
+        // Every condition names the script it sets off, and its kind
+        let mut triggers: Vec<Option<Trigger>> = vec![None; scripts.len()];
+        for (i, condition) in conditions.into_iter().enumerate() {
+            let index = indexes.get(i).copied().unwrap_or(0) as usize;
+            let Some(body) = scripts.get(index) else { continue };
+            let Some(kind) = trigger_kind(flags.get(i).copied().unwrap_or(0)) else { continue };
+            triggers[index]
+                .get_or_insert_with(|| Trigger {
+                    kind,
+                    conditions: Vec::new(),
+                    script: self.script(
+                        Some(body),
+                        progs.get(index).cloned().into_iter().collect(),
+                        script_flags.get(index).copied().unwrap_or(0),
+                    ),
+                })
+                .conditions
+                .push(condition);
+        }
+        triggers.into_iter().flatten().collect()
+    }
+
+    fn file_triggers(&self, transaction: bool) -> Vec<FileTrigger> {
+        let header = &self.header;
+        let (scripts, progs, script_flags, names, indexes, flags, priorities) = match transaction {
+            false => (
+                tag::FILETRIGGERSCRIPTS,
+                tag::FILETRIGGERSCRIPTPROG,
+                tag::FILETRIGGERSCRIPTFLAGS,
+                tag::FILETRIGGERNAME,
+                tag::FILETRIGGERINDEX,
+                tag::FILETRIGGERFLAGS,
+                tag::FILETRIGGERPRIORITIES,
+            ),
+            true => (
+                tag::TRANSFILETRIGGERSCRIPTS,
+                tag::TRANSFILETRIGGERSCRIPTPROG,
+                tag::TRANSFILETRIGGERSCRIPTFLAGS,
+                tag::TRANSFILETRIGGERNAME,
+                tag::TRANSFILETRIGGERINDEX,
+                tag::TRANSFILETRIGGERFLAGS,
+                tag::TRANSFILETRIGGERPRIORITIES,
+            ),
+        };
+        let scripts = header.strings(scripts);
+        let progs = header.strings(progs);
+        let script_flags = header.ints(script_flags);
+        let flags = header.ints(flags);
+        let indexes = header.ints(indexes);
+        let priorities = header.ints(priorities);
+
+        let mut triggers: Vec<Option<FileTrigger>> = vec![None; scripts.len()];
+        for (i, prefix) in header.strings(names).into_iter().enumerate() {
+            let index = indexes.get(i).copied().unwrap_or(0) as usize;
+            let Some(body) = scripts.get(index) else { continue };
+            let Some(kind) = trigger_kind(flags.get(i).copied().unwrap_or(0)) else { continue };
+            triggers[index]
+                .get_or_insert_with(|| FileTrigger {
+                    kind,
+                    transaction,
+                    prefixes: Vec::new(),
+                    priority: priorities
+                        .get(index)
+                        .map(|&p| p as u32)
+                        .unwrap_or(DEFAULT_FILE_TRIGGER_PRIORITY),
+                    script: self.script(
+                        Some(body),
+                        progs.get(index).cloned().into_iter().collect(),
+                        script_flags.get(index).copied().unwrap_or(0),
+                    ),
+                })
+                .prefixes
+                .push(prefix);
+        }
+        triggers.into_iter().flatten().collect()
+    }
+
+    fn changelog(&self) -> Vec<ChangelogEntry> {
+        let header = &self.header;
+        let times = header.ints(tag::CHANGELOGTIME);
+        let texts = header.strings(tag::CHANGELOGTEXT);
+        header
+            .strings(tag::CHANGELOGNAME)
+            .into_iter()
+            .enumerate()
+            .map(|(i, author)| ChangelogEntry {
+                time: times.get(i).copied().unwrap_or(0) as u32,
+                author,
+                text: texts.get(i).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// The package this rpm holds, with everything its header says about it
+    pub fn to_package(&self) -> Result<Package> {
+        let header = &self.header;
+        let mut interner = Interner::default();
+        let field = |tag: u32, what: &str| {
+            header
+                .string(tag)
+                .ok_or_else(|| anyhow::anyhow!("rpm header has no {}", what))
+        };
+        let locale = message_locale();
+
+        // Source rpms carry the architecture they were built on
+        let arch = match self.source {
+            true => "src",
+            false => field(tag::ARCH, "architecture")?,
+        };
+        let name = PackageName::new(field(tag::NAME, "name")?, arch)?;
+        let version = Version::new(
+            header.int(tag::EPOCH).unwrap_or(0) as u32,
+            field(tag::VERSION, "version")?,
+            field(tag::RELEASE, "release")?,
+        )?;
+        let description = header.i18n_string(tag::DESCRIPTION, &locale).unwrap_or_default();
+
+        let mut pkg = Package::new(name, version, description.to_string());
+        pkg.summary = header.i18n_string(tag::SUMMARY, &locale).unwrap_or_default().to_string();
+        pkg.license = Arc::from(header.string(tag::LICENSE).unwrap_or_default());
+        pkg.url = header.string(tag::URL).unwrap_or_default().to_string();
+        pkg.size = self.size;
+
+        pkg.dependencies = self.dependencies(tag::REQUIRENAME, tag::REQUIREFLAGS, tag::REQUIREVERSION, &mut interner);
+        pkg.provides = self.dependencies(tag::PROVIDENAME, tag::PROVIDEFLAGS, tag::PROVIDEVERSION, &mut interner);
+        pkg.conflicts = self.dependencies(tag::CONFLICTNAME, tag::CONFLICTFLAGS, tag::CONFLICTVERSION, &mut interner);
+        pkg.obsoletes = self.dependencies(tag::OBSOLETENAME, tag::OBSOLETEFLAGS, tag::OBSOLETEVERSION, &mut interner);
+        pkg.recommends = self.dependencies(tag::RECOMMENDNAME, tag::RECOMMENDFLAGS, tag::RECOMMENDVERSION, &mut interner);
+        pkg.suggests = self.dependencies(tag::SUGGESTNAME, tag::SUGGESTFLAGS, tag::SUGGESTVERSION, &mut interner);
+        pkg.supplements = self.dependencies(tag::SUPPLEMENTNAME, tag::SUPPLEMENTFLAGS, tag::SUPPLEMENTVERSION, &mut interner);
+        pkg.enhances = self.dependencies(tag::ENHANCENAME, tag::ENHANCEFLAGS, tag::ENHANCEVERSION, &mut interner);
+
+        pkg.file_entries = self.file_entries();
+        pkg.files = pkg.file_entries.iter().map(|file| file.path.clone()).collect();
+        // Packages from before FILEDIGESTALGO existed use md5
+        pkg.file_digest_algo = digest_algo(header.int(tag::FILEDIGESTALGO).unwrap_or(1)).to_string();
+
+        pkg.scriptlets = self.scriptlets();
+        pkg.triggers = self.triggers(&mut interner);
+        pkg.file_triggers = self.file_triggers(false);
+        pkg.file_triggers.extend(self.file_triggers(true));
+        pkg.changelog = self.changelog();
+        Ok(pkg)
+    }
+}
+
+fn trigger_kind(flags: u64) -> Option<TriggerKind> {
+    if flags & SENSE_TRIGGERPREIN != 0 {
+        Some(TriggerKind::PreIn)
+    } else if flags & SENSE_TRIGGERIN != 0 {
+        Some(TriggerKind::In)
+    } else if flags & SENSE_TRIGGERUN != 0 {
+        Some(TriggerKind::Un)
+    } else if flags & SENSE_TRIGGERPOSTUN != 0 {
+        Some(TriggerKind::PostUn)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header structure with `entries` of (tag, type, offset, count)
+    /// over `data`
+    fn header_bytes(entries: &[(u32, u32, u32, u32)], data: &[u8]) -> Vec<u8> {
+        let mut bytes = HEADER_MAGIC.to_vec();
+        bytes.extend([0; 4]);
+        bytes.extend((entries.len() as u32).to_be_bytes());
+        bytes.extend((data.len() as u32).to_be_bytes());
+        for &(tag, typ, offset, count) in entries {
+            for field in [tag, typ, offset, count] {
+                bytes.extend(field.to_be_bytes());
+            }
+        }
+        bytes.extend(data);
+        bytes
+    }
+
+    fn parse(entries: &[(u32, u32, u32, u32)], data: &[u8]) -> Result<Header> {
+        Header::read(&mut &header_bytes(entries, data)[..])
+    }
+
+    #[test]
+    fn header_tags_are_read() {
+        let data = b"hello\0C\0de\0Hallo\0Welt\0\0\0\0\0\0\x2a\0\0\0\x07";
+        let header = parse(
+            &[
+                (tag::NAME, 6, 0, 1),
+                (tag::HEADER_I18NTABLE, 8, 6, 2),
+                (tag::SUMMARY, 9, 11, 2),
+                (tag::FILEMODES, 4, 24, 2),
+            ],
+            data,
+        )
+        .unwrap();
+
+        assert_eq!(header.size, 16 + 4 * 16 + data.len() as u64);
+        assert_eq!(header.string(tag::NAME), Some("hello"));
+        assert_eq!(header.strings(tag::HEADER_I18NTABLE), ["C", "de"]);
+        assert_eq!(header.i18n_string(tag::SUMMARY, "de_AT"), Some("Welt"));
+        assert_eq!(header.i18n_string(tag::SUMMARY, "fr_FR"), Some("Hallo"));
+        assert_eq!(header.ints(tag::FILEMODES), [42, 7]);
+        assert_eq!(header.int(tag::EPOCH), None);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let error = |entries: &[(u32, u32, u32, u32)], data: &[u8]| parse(entries, data).unwrap_err().to_string();
+
+        let mut bad_magic = header_bytes(&[], b"");
+        bad_magic[0] = 0;
+        assert!(Header::read(&mut &bad_magic[..]).is_err());
+
+        // Counts are checked before anything is allocated for them
+        let mut huge = HEADER_MAGIC.to_vec();
+        huge.extend([0; 4]);
+        huge.extend(u32::MAX.to_be_bytes());
+        huge.extend(0u32.to_be_bytes());
+        assert!(Header::read(&mut &huge[..]).unwrap_err().to_string().contains("too large"));
+        assert!(error(&[(tag::NAME, 8, 0, u32::MAX)], b"a\0").contains("out of bounds"));
+        assert!(error(&[(tag::FILEMODES, 4, 0, u32::MAX)], b"\0\0\0\x01").contains("out of bounds"));
+        assert!(error(&[(tag::FILEMODES, 4, u32::MAX, 1)], b"\0\0\0\x01").contains("out of bounds"));
+
+        assert!(error(&[(tag::NAME, 6, 0, 1)], b"abc").contains("unterminated"));
+        assert!(error(&[(tag::NAME, 6, 8, 1)], b"abc\0").contains("out of bounds"));
+        assert!(error(&[(tag::NAME, 42, 0, 1)], b"abc\0").contains("unknown type"));
+
+        // Truncated index or data
+        let bytes = header_bytes(&[(tag::NAME, 6, 0, 1)], b"hello\0");
+        assert!(Header::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(Header::read(&mut &bytes[..20]).is_err());
+    }
+
+    /// An rpm of package "hello" whose signature header records `size`
+    /// as the size of header and payload
+    fn rpm_bytes(size: Option<u32>) -> Vec<u8> {
+        let mut lead = vec![0; LEAD_SIZE];
+        lead[..4].copy_from_slice(&LEAD_MAGIC);
+        lead[4] = 3;
+        let main = header_bytes(
+            &[(tag::NAME, 6, 0, 1), (tag::VERSION, 6, 6, 1), (tag::RELEASE, 6, 10, 1), (tag::ARCH, 6, 12, 1)],
+            b"hello\x001.0\x001\0x86_64\0",
+        );
+        let payload = b"payload";
+        let size = size.unwrap_or((main.len() + payload.len()) as u32);
+        let signature = header_bytes(&[(tag::SIG_SIZE, 4, 0, 1)], &size.to_be_bytes());
+
+        let mut bytes = lead;
+        bytes.extend(&signature);
+        bytes.extend(vec![0; (8 - signature.len() % 8) % 8]);
+        bytes.extend(main);
+        bytes.extend(payload);
+        bytes
+    }
+
+    #[test]
+    fn rpm_files_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.rpm");
+        let bytes = rpm_bytes(None);
+        fs::write(&path, &bytes).unwrap();
+
+        let rpm = read(&path).unwrap();
+        assert!(!rpm.source);
+        assert_eq!(rpm.header_offset % 8, 0);
+        assert_eq!(rpm.payload_offset, bytes.len() as u64 - 7);
+        let package = rpm.to_package().unwrap();
+        assert_eq!(package.nevra(), "hello-0:1.0-1.x86_64");
+        assert_eq!(package.size, bytes.len() as u64);
+    }
+
+    #[test]
+    fn malformed_rpm_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad.rpm");
+
+        // Truncated, as an interrupted download would be
+        let bytes = rpm_bytes(Some(1000));
+        fs::write(&path, &bytes).unwrap();
+        assert!(read(&path).unwrap_err().to_string().contains("its signature header says"));
+
+        fs::write(&path, &bytes[..50]).unwrap();
+        assert!(read(&path).unwrap_err().to_string().contains("not an rpm file"));
+
+        let mut old_format = rpm_bytes(None);
+        old_format[4] = 2;
+        fs::write(&path, &old_format).unwrap();
+        assert!(read(&path).unwrap_err().to_string().contains("format version 2"));
+    }
+}
@@ -0,0 +1,390 @@
+use crate::advisory::Advisory;
+use crate::comps::Comps;
+use crate::deltarpm::Deltas;
+use crate::modulemd::ModuleMetadata;
+use crate::package::{Dependency, Package, PackageName, Version};
+use anyhow::Result;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: [u8; 8] = *b"RDNFSACK";
+
+/// Bumped whenever the layout of the cached structures changes, so that
+/// caches written by older versions are ignored instead of misread.
+const FORMAT_VERSION: u32 = 5;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    repomd_checksum: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDependency {
+    name: u32, // Index into the string pool
+    version: Option<String>,
+    comparator: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedPackage {
+    name: String,
+    arch: u32,
+    epoch: u32,
+    version: String,
+    release: String,
+    summary: String,
+    description: String,
+    requires: Vec<CachedDependency>,
+    provides: Vec<CachedDependency>,
+    conflicts: Vec<CachedDependency>,
+    obsoletes: Vec<CachedDependency>,
+    recommends: Vec<CachedDependency>,
+    suggests: Vec<CachedDependency>,
+    supplements: Vec<CachedDependency>,
+    enhances: Vec<CachedDependency>,
+    files: Vec<String>,
+    size: u64,
+    license: u32,
+    url: String,
+    location: String,
+    checksum: Option<(String, String)>,
+}
+
+/// Strings that are interned in memory are written once to a pool and
+/// referenced by index, like libsolv's string store.
+#[derive(Deserialize)]
+struct Body {
+    strings: Vec<String>,
+    packages: Vec<CachedPackage>,
+    modular_packages: Vec<CachedPackage>,
+    advisories: Vec<Advisory>,
+    comps: Comps,
+    modules: ModuleMetadata,
+    deltas: Deltas,
+}
+
+/// Serializes exactly like `Body`, without copying the metadata first
+#[derive(Serialize)]
+struct BodyRef<'a> {
+    strings: &'a [String],
+    packages: &'a [CachedPackage],
+    modular_packages: &'a [CachedPackage],
+    advisories: &'a [Advisory],
+    comps: &'a Comps,
+    modules: &'a ModuleMetadata,
+    deltas: &'a Deltas,
+}
+
+/// Parsed metadata of one repository
+pub struct Sack {
+    pub packages: Vec<Package>,
+    pub modular_packages: Vec<Package>,
+    pub advisories: Vec<Advisory>,
+    pub comps: Comps,
+    pub modules: ModuleMetadata,
+    pub deltas: Deltas,
+}
+
+/// Borrowed view of a repository's metadata, for writing the cache
+pub struct SackRef<'a> {
+    pub packages: Vec<&'a Package>,
+    pub modular_packages: &'a [Package],
+    pub advisories: &'a [Advisory],
+    pub comps: &'a Comps,
+    pub modules: &'a ModuleMetadata,
+    pub deltas: &'a Deltas,
+}
+
+#[derive(Default)]
+struct StringPool {
+    strings: Vec<String>,
+    index: HashMap<Arc<str>, u32>,
+}
+
+impl StringPool {
+    fn add(&mut self, s: &Arc<str>) -> u32 {
+        if let Some(&i) = self.index.get(s) {
+            return i;
+        }
+        let i = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.index.insert(Arc::clone(s), i);
+        i
+    }
+}
+
+fn cache_dependencies(deps: &[Dependency], pool: &mut StringPool) -> Vec<CachedDependency> {
+    deps.iter()
+        .map(|dep| CachedDependency {
+            name: pool.add(&dep.name),
+            version: dep.version.clone(),
+            comparator: dep.comparator.clone(),
+        })
+        .collect()
+}
+
+fn cache_package(pkg: &Package, pool: &mut StringPool) -> CachedPackage {
+    CachedPackage {
+        name: pkg.name.name.clone(),
+        arch: pool.add(&pkg.name.arch),
+        epoch: pkg.version.epoch,
+        version: pkg.version.version.clone(),
+        release: pkg.version.release.clone(),
+        summary: pkg.summary.clone(),
+        description: pkg.description.clone(),
+        requires: cache_dependencies(&pkg.dependencies, pool),
+        provides: cache_dependencies(&pkg.provides, pool),
+        conflicts: cache_dependencies(&pkg.conflicts, pool),
+        obsoletes: cache_dependencies(&pkg.obsoletes, pool),
+        recommends: cache_dependencies(&pkg.recommends, pool),
+        suggests: cache_dependencies(&pkg.suggests, pool),
+        supplements: cache_dependencies(&pkg.supplements, pool),
+        enhances: cache_dependencies(&pkg.enhances, pool),
+        files: pkg.files.clone(),
+        size: pkg.size,
+        license: pool.add(&pkg.license),
+        url: pkg.url.clone(),
+        location: pkg.location.clone(),
+        checksum: pkg.checksum.clone(),
+    }
+}
+
+fn restore_dependencies(deps: Vec<CachedDependency>, strings: &[Arc<str>]) -> Result<Vec<Dependency>> {
+    deps.into_iter()
+        .map(|dep| {
+            Ok(Dependency {
+                name: pooled(strings, dep.name)?,
+                version: dep.version,
+                comparator: dep.comparator,
+            })
+        })
+        .collect()
+}
+
+fn pooled(strings: &[Arc<str>], index: u32) -> Result<Arc<str>> {
+    match strings.get(index as usize) {
+        Some(s) => Ok(Arc::clone(s)),
+        None => anyhow::bail!("String index {} out of range in metadata cache", index),
+    }
+}
+
+fn restore_package(cached: CachedPackage, strings: &[Arc<str>]) -> Result<Package> {
+    let mut pkg = Package::new(
+        PackageName {
+            name: cached.name,
+            arch: pooled(strings, cached.arch)?,
+        },
+        Version {
+            epoch: cached.epoch,
+            version: cached.version,
+            release: cached.release,
+        },
+        cached.description,
+    );
+    pkg.summary = cached.summary;
+    pkg.dependencies = restore_dependencies(cached.requires, strings)?;
+    pkg.provides = restore_dependencies(cached.provides, strings)?;
+    pkg.conflicts = restore_dependencies(cached.conflicts, strings)?;
+    pkg.obsoletes = restore_dependencies(cached.obsoletes, strings)?;
+    pkg.recommends = restore_dependencies(cached.recommends, strings)?;
+    pkg.suggests = restore_dependencies(cached.suggests, strings)?;
+    pkg.supplements = restore_dependencies(cached.supplements, strings)?;
+    pkg.enhances = restore_dependencies(cached.enhances, strings)?;
+    pkg.files = cached.files;
+    pkg.size = cached.size;
+    pkg.license = pooled(strings, cached.license)?;
+    pkg.url = cached.url;
+    pkg.location = cached.location;
+    pkg.checksum = cached.checksum;
+    Ok(pkg)
+}
+
+/// Load the cached sack at `path` if it was written for the repomd.xml with
+/// checksum `repomd_checksum` by this version of the cache format. The file
+/// is mapped so that a stale header is rejected without reading the rest,
+/// but a usable body is still deserialized into owned packages.
+pub fn load(path: &Path, repomd_checksum: &str) -> Result<Option<Sack>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // Safety: the cache is only ever replaced by rename, never modified in place
+    let data = unsafe { Mmap::map(&file)? };
+
+    let header: Header = match bincode::deserialize(&data) {
+        Ok(header) => header,
+        Err(_) => return Ok(None),
+    };
+    if header.magic != MAGIC || header.version != FORMAT_VERSION {
+        log::debug!("Ignoring metadata cache {:?} with an unknown format", path);
+        return Ok(None);
+    }
+    if header.repomd_checksum != repomd_checksum {
+        log::debug!("Metadata cache {:?} is stale", path);
+        return Ok(None);
+    }
+
+    let offset = bincode::serialized_size(&header)? as usize;
+    let body: Body = bincode::deserialize(&data[offset..])?;
+
+    let strings: Vec<Arc<str>> = body.strings.iter().map(|s| Arc::from(s.as_str())).collect();
+
+    let packages = body
+        .packages
+        .into_iter()
+        .map(|pkg| restore_package(pkg, &strings))
+        .collect::<Result<Vec<_>>>()?;
+    let modular_packages = body
+        .modular_packages
+        .into_iter()
+        .map(|pkg| restore_package(pkg, &strings))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Some(Sack {
+        packages,
+        modular_packages,
+        advisories: body.advisories,
+        comps: body.comps,
+        modules: body.modules,
+        deltas: body.deltas,
+    }))
+}
+
+/// Write the sack to `path`, replacing any previous cache atomically
+pub fn store(path: &Path, repomd_checksum: &str, sack: &SackRef) -> Result<()> {
+    let header = Header {
+        magic: MAGIC,
+        version: FORMAT_VERSION,
+        repomd_checksum: repomd_checksum.to_string(),
+    };
+
+    let mut pool = StringPool::default();
+    let packages: Vec<_> = sack.packages.iter().map(|pkg| cache_package(pkg, &mut pool)).collect();
+    let modular_packages: Vec<_> = sack
+        .modular_packages
+        .iter()
+        .map(|pkg| cache_package(pkg, &mut pool))
+        .collect();
+    let body = BodyRef {
+        strings: &pool.strings,
+        packages: &packages,
+        modular_packages: &modular_packages,
+        advisories: sack.advisories,
+        comps: sack.comps,
+        modules: sack.modules,
+        deltas: sack.deltas,
+    };
+
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut out = BufWriter::new(fs::File::create(&tmp_path)?);
+        bincode::serialize_into(&mut out, &header)?;
+        bincode::serialize_into(&mut out, &body)?;
+        out.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    log::debug!("Wrote metadata cache {:?}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package() -> Package {
+        let mut pkg = Package::new(
+            PackageName::new("nano", "x86_64").unwrap(),
+            Version::new(0, "7.2", "4.fc39").unwrap(),
+            "A small text editor".to_string(),
+        );
+        pkg.dependencies = vec![Dependency {
+            name: Arc::from("ncurses-libs"),
+            version: Some("6.4".to_string()),
+            comparator: Some(">=".to_string()),
+        }];
+        pkg.provides = vec![Dependency { name: Arc::from("nano"), version: None, comparator: None }];
+        pkg.files = vec!["/usr/bin/nano".to_string()];
+        pkg.license = Arc::from("GPL-3.0-or-later");
+        pkg.location = "Packages/n/nano-7.2-4.fc39.x86_64.rpm".to_string();
+        pkg.checksum = Some(("sha256".to_string(), "0123abcd".to_string()));
+        pkg
+    }
+
+    fn store_one(path: &Path, repomd_checksum: &str) {
+        let pkg = package();
+        let sack = SackRef {
+            packages: vec![&pkg],
+            modular_packages: &[],
+            advisories: &[],
+            comps: &Comps::default(),
+            modules: &ModuleMetadata::default(),
+            deltas: &Deltas::default(),
+        };
+        store(path, repomd_checksum, &sack).unwrap();
+    }
+
+    #[test]
+    fn sacks_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sack.cache");
+        store_one(&path, "abc");
+
+        let sack = load(&path, "abc").unwrap().unwrap();
+        assert_eq!(sack.packages.len(), 1);
+        assert!(sack.modular_packages.is_empty());
+        let (pkg, expected) = (&sack.packages[0], package());
+        assert_eq!(pkg.nevra(), expected.nevra());
+        assert_eq!(pkg.description, expected.description);
+        assert_eq!(pkg.dependencies[0].version.as_deref(), Some("6.4"));
+        assert_eq!(pkg.dependencies[0].comparator.as_deref(), Some(">="));
+        assert_eq!((&*pkg.dependencies[0].name, &*pkg.provides[0].name), ("ncurses-libs", "nano"));
+        assert_eq!((pkg.files.as_slice(), &*pkg.license), (expected.files.as_slice(), "GPL-3.0-or-later"));
+        assert_eq!((&pkg.location, &pkg.checksum), (&expected.location, &expected.checksum));
+    }
+
+    #[test]
+    fn stale_and_foreign_caches_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sack.cache");
+        assert!(load(&path, "abc").unwrap().is_none());
+
+        store_one(&path, "abc");
+        assert!(load(&path, "def").unwrap().is_none());
+
+        let body = fs::read(&path).unwrap();
+        let old_header = Header { magic: MAGIC, version: FORMAT_VERSION - 1, repomd_checksum: "abc".to_string() };
+        let offset = bincode::serialized_size(&old_header).unwrap() as usize;
+        let mut data = bincode::serialize(&old_header).unwrap();
+        data.extend(&body[offset..]);
+        fs::write(&path, &data).unwrap();
+        assert!(load(&path, "abc").unwrap().is_none());
+
+        data[..MAGIC.len()].copy_from_slice(b"SOMETHIN");
+        data[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        assert!(load(&path, "abc").unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_caches_are_not_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sack.cache");
+        fs::write(&path, b"garbage").unwrap();
+        assert!(load(&path, "abc").unwrap().is_none());
+
+        // A body cut short fails to load, which the caller logs and ignores
+        store_one(&path, "abc");
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        assert!(load(&path, "abc").is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::package::Script;
+use crate::transaction::TransactionLog;
+use anyhow::Result;
+use std::ffi::CString;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Interpreter of scripts rpm runs with its embedded Lua
+const LUA: &str = "<lua>";
+
+/// PATH scripts run with, as rpm sets it
+const SCRIPT_PATH: &str = "/sbin:/bin:/usr/sbin:/usr/bin";
+
+/// Run `script` inside `root`, chrooted unless `root` is `/`, with the
+/// instance counts `args` as its $1 (and $2) and `input` on its stdin.
+/// What it prints goes to `log` under `label`, e.g.
+/// "%post(bash-0:5.2-1.x86_64)". Fails if the script cannot be started or
+/// exits unsuccessfully.
+pub fn run(
+    root: &Path,
+    script: &Script,
+    label: &str,
+    args: &[u32],
+    input: Option<&[u8]>,
+    log: &mut TransactionLog,
+) -> Result<()> {
+    let (program, interpreter_args) = match script.interpreter.split_first() {
+        Some((program, interpreter_args)) => (program, interpreter_args),
+        None => anyhow::bail!("{} has no interpreter", label),
+    };
+    if program == LUA {
+        log::warn!("Skipping {}, Lua scriptlets are not supported", label);
+        log.write(&format!("{}: skipped, Lua scriptlets are not supported", label));
+        return Ok(());
+    }
+
+    let chroot = root != Path::new("/");
+    let mut command = Command::new(program);
+    command
+        .args(interpreter_args)
+        .env_clear()
+        .env("PATH", SCRIPT_PATH)
+        .current_dir(root)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // The body is handed to the interpreter as a file, as rpm does
+    let script_file = match &script.body {
+        Some(body) => {
+            let (mut file, path, inner_path) = script_file(root)?;
+            let written = file.write_all(body.as_bytes());
+            drop(file);
+            if let Err(e) = written {
+                let _ = fs::remove_file(&path);
+                return Err(e.into());
+            }
+            command.arg(if chroot { &inner_path } else { &path });
+            Some(path)
+        }
+        None => None,
+    };
+    command.args(args.iter().map(u32::to_string));
+
+    if chroot {
+        let root_c = CString::new(root.as_os_str().as_bytes())?;
+        // SAFETY: only async-signal-safe calls happen between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::chroot(root_c.as_ptr()) != 0 || libc::chdir(c"/".as_ptr()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    log::debug!("Running {}", label);
+    let output = command.spawn().and_then(|mut child| {
+        // Written from another thread, so a script that prints a lot before
+        // reading all of its input cannot block on a full pipe
+        let writer = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => {
+                let input = input.to_vec();
+                Some(std::thread::spawn(move || {
+                    let _ = stdin.write_all(&input);
+                }))
+            }
+            _ => None,
+        };
+        let output = child.wait_with_output();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        output
+    });
+    if let Some(path) = script_file {
+        let _ = fs::remove_file(path);
+    }
+    let output = output.map_err(|e| anyhow::anyhow!("Cannot run {} with {}: {}", label, program, e))?;
+
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        log.write(&format!("{}: {}", label, line));
+    }
+    if !output.status.success() {
+        log.write(&format!("{}: {}", label, output.status));
+        anyhow::bail!("{} failed ({})", label, output.status);
+    }
+    Ok(())
+}
+
+/// A new file for a script body, with its path from outside and from inside
+/// the installation root. The name is random and the file is created
+/// exclusively, so that nothing already at that path, like a symlink
+/// planted in the world-writable /var/tmp, is written through.
+fn script_file(root: &Path) -> Result<(fs::File, PathBuf, PathBuf)> {
+    let mut random = [0u8; 8];
+    fs::File::open("/dev/urandom")?.read_exact(&mut random)?;
+    let name: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let inner_path = Path::new("/var/tmp").join(format!("rpm-tmp.{}", name));
+
+    let path = crate::payload::root_path(root, &inner_path.to_string_lossy())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!("Cannot create script file {:?}: {}", path, e))?;
+    Ok((file, path, inner_path))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// An installation root holding /bin/sh and the libraries it needs, for
+    /// running scripts chrooted. None unless running as root, which chroot
+    /// requires.
+    pub(crate) fn shell_root() -> Option<tempfile::TempDir> {
+        // SAFETY: geteuid has no preconditions
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("Not running as root, skipping a test that needs chroot");
+            return None;
+        }
+        let root = tempfile::tempdir().unwrap();
+        let ldd = Command::new("ldd").arg("/bin/sh").output().unwrap();
+        let ldd = String::from_utf8(ldd.stdout).unwrap();
+        let libraries = ldd.split_whitespace().filter(|word| word.starts_with('/'));
+        for path in libraries.chain(["/bin/sh"]) {
+            let target = root.path().join(&path[1..]);
+            fs::create_dir_all(target.parent().unwrap()).unwrap();
+            fs::copy(path, &target).unwrap();
+        }
+        Some(root)
+    }
+
+    pub(crate) fn shell(body: &str) -> Script {
+        Script {
+            interpreter: vec!["/bin/sh".to_string()],
+            body: Some(body.to_string()),
+            flags: 0,
+        }
+    }
+
+    /// The messages in the transaction log at `path`, without timestamps
+    pub(crate) fn log_lines(path: &Path) -> Vec<String> {
+        let log = fs::read_to_string(path).unwrap_or_default();
+        log.lines().map(|line| line.split_once(' ').unwrap().1.to_string()).collect()
+    }
+
+    #[test]
+    fn script_files_are_new_and_private() {
+        let root = tempfile::tempdir().unwrap();
+        let (_, path, inner_path) = script_file(root.path()).unwrap();
+        let (_, other, _) = script_file(root.path()).unwrap();
+
+        assert_ne!(path, other);
+        assert_eq!(path, root.path().join(inner_path.strip_prefix("/").unwrap()));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn symlinked_script_directories_are_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("var")).unwrap();
+        std::os::unix::fs::symlink(elsewhere.path(), root.path().join("var/tmp")).unwrap();
+
+        assert!(script_file(root.path()).is_err());
+        assert_eq!(fs::read_dir(elsewhere.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn scripts_run_chrooted_with_arguments_and_input() {
+        let Some(root) = shell_root() else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = TransactionLog::open(&dir.path().join("log"));
+        let script = shell("echo \"args $# $1 $2\"; read path; echo \"input $path\"; echo oops >&2; export -p >/environment");
+
+        run(root.path(), &script, "%post(a)", &[2, 1], Some(b"/usr/lib/a\n"), &mut log).unwrap();
+        assert_eq!(log_lines(&dir.path().join("log")), ["%post(a): args 2 2 1", "%post(a): input /usr/lib/a", "%post(a): oops"]);
+        let environment = fs::read_to_string(root.path().join("environment")).unwrap();
+        // Only PATH is passed on; the shell adds PWD, which is the new root
+        let path = format!("export PATH='{}'", SCRIPT_PATH);
+        assert_eq!(environment.lines().collect::<Vec<_>>(), [path.as_str(), "export PWD='/'"]);
+        assert_eq!(fs::read_dir(root.path().join("var/tmp")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn failing_scripts_are_errors() {
+        let Some(root) = shell_root() else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = TransactionLog::open(&dir.path().join("log"));
+
+        let error = run(root.path(), &shell("echo partial; exit 3"), "%pre(a)", &[1], None, &mut log).unwrap_err();
+        assert_eq!(error.to_string(), "%pre(a) failed (exit status: 3)");
+        assert_eq!(log_lines(&dir.path().join("log")), ["%pre(a): partial", "%pre(a): exit status: 3"]);
+
+        let missing = Script {
+            interpreter: vec!["/usr/bin/python3".to_string()],
+            ..shell("print()")
+        };
+        let error = run(root.path(), &missing, "%post(a)", &[1], None, &mut log).unwrap_err();
+        assert!(error.to_string().starts_with("Cannot run %post(a) with /usr/bin/python3"), "{}", error);
+    }
+}
@@ -0,0 +1,602 @@
+use crate::config::Config;
+use crate::config_files;
+use crate::conflicts;
+use crate::db::PackageDatabase;
+use crate::package::{Package, ScriptletKind, TriggerKind};
+use crate::payload;
+use crate::scriptlet;
+use crate::trigger::{self, TriggerRun};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Appends what transactions did, and what their scripts printed, to a
+/// log file
+pub struct TransactionLog {
+    file: Option<fs::File>,
+}
+
+impl TransactionLog {
+    pub fn open(path: &Path) -> Self {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| log::warn!("Cannot open transaction log {:?}: {}", path, e))
+            .ok();
+        Self { file }
+    }
+
+    pub fn write(&mut self, message: &str) {
+        if let Some(file) = &mut self.file {
+            let line = format!("{} {}\n", chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true), message);
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                log::warn!("Cannot write to the transaction log: {}", e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// A package to install or upgrade to, with its downloaded rpm. Packages
+/// without an rpm, such as mock packages, are only recorded.
+pub struct Install {
+    pub package: Package,
+    pub rpm: Option<PathBuf>,
+}
+
+/// Installs and erases packages under the installation root, running
+/// their scriptlets and the triggers they set off in the order rpm does:
+/// %pretrans of everything installed and %transfiletriggerun for what
+/// goes away; per package %triggerprein, %pre, unpacking, %filetriggerin,
+/// %post and %triggerin, followed by erasing the build it replaces; then
+/// the erasures, each with %filetriggerun, %triggerun, %preun, removal,
+/// %postun, %triggerpostun and %filetriggerpostun; and last %posttrans,
+/// %transfiletriggerpostun and %transfiletriggerin.
+/// A failing script before %pre or %preun, or %pretrans, stops that
+/// package; any later failure is only a warning.
+pub struct Transaction<'a> {
+    root: PathBuf,
+    pkg_db: &'a mut PackageDatabase,
+    log: TransactionLog,
+    errors: Vec<String>,
+    removed_files: Vec<String>, // Of everything erased or replaced, for %transfiletriggerpostun
+    config_notes: Vec<String>,  // What became of edited %config files
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new(config: &Config, pkg_db: &'a mut PackageDatabase) -> Self {
+        Self {
+            root: config.install_root.clone(),
+            pkg_db,
+            log: TransactionLog::open(&config.database_dir.join("transaction.log")),
+            errors: Vec::new(),
+            removed_files: Vec::new(),
+            config_notes: Vec::new(),
+        }
+    }
+
+    /// Install `installs` and erase the installed packages with the
+    /// database keys `erases`. Nothing happens if the packages would
+    /// overwrite each other's files. Otherwise the other packages are still
+    /// processed when one fails, but the transaction as a whole then fails.
+    pub fn run(mut self, installs: Vec<Install>, erases: &[String]) -> Result<()> {
+        let incoming: Vec<&Package> = installs.iter().map(|install| &install.package).collect();
+        let conflicts = conflicts::find(self.pkg_db, &incoming, erases);
+        if !conflicts.is_empty() {
+            let report: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+            self.log.write(&format!("Transaction check failed: {}", report.join("; ")));
+            anyhow::bail!("Transaction check failed, nothing was changed:\n  {}", report.join("\n  "));
+        }
+
+        self.log.write("Transaction started");
+
+        let mut ready = Vec::with_capacity(installs.len());
+        for install in installs {
+            let count = self.instances_after_install(&install.package);
+            match run_scriptlet(&self.root, &mut self.log, &install.package, ScriptletKind::PreTrans, count) {
+                Ok(()) => ready.push(install),
+                Err(e) => self.fail(format!("Not installing {}: {}", install.package.nevra(), e)),
+            }
+        }
+
+        // Files of the packages that are erased or replaced by a new build
+        let leaving: Vec<String> = ready
+            .iter()
+            .map(|install| format!("{}.{}", install.package.name.name, install.package.name.arch))
+            .chain(erases.iter().cloned())
+            .filter_map(|key| self.pkg_db.get_installed(&key))
+            .flat_map(|installed| installed.installed_files.iter().cloned())
+            .collect();
+        let runs = trigger::file_set_off(self.pkg_db, TriggerKind::Un, true, &leaving, &[]);
+        self.run_triggers(runs);
+
+        let mut installed = Vec::with_capacity(ready.len());
+        for install in ready {
+            let nevra = install.package.nevra();
+            match self.install(install) {
+                Ok(key) => installed.push(key),
+                Err(e) => self.fail(format!("Installing {} failed: {}", nevra, e)),
+            }
+        }
+
+        for key in erases {
+            if let Err(e) = self.erase(key) {
+                self.fail(format!("Removing {} failed: {}", key, e));
+            }
+        }
+
+        for key in &installed {
+            let package = &self.pkg_db.installed_packages[key].package;
+            let count = self.pkg_db.instances(&package.name.name);
+            if let Err(e) = run_scriptlet(&self.root, &mut self.log, package, ScriptletKind::PostTrans, count) {
+                self.warn(e);
+            }
+        }
+
+        let removed_files = std::mem::take(&mut self.removed_files);
+        let runs = trigger::file_set_off(self.pkg_db, TriggerKind::PostUn, true, &removed_files, &[]);
+        self.run_triggers(runs);
+
+        // Packages new in this transaction see all matching files, the
+        // others only those that came with it
+        let new_names: Vec<&str> = installed
+            .iter()
+            .map(|key| self.pkg_db.installed_packages[key].package.name.name.as_str())
+            .collect();
+        let new_files: Vec<String> = installed
+            .iter()
+            .flat_map(|key| self.pkg_db.installed_packages[key].installed_files.iter().cloned())
+            .collect();
+        let mut runs = trigger::file_set_off(self.pkg_db, TriggerKind::In, true, &new_files, &new_names);
+        for key in &installed {
+            let package = &self.pkg_db.installed_packages[key].package;
+            runs.extend(trigger::file_immediate(self.pkg_db, package, TriggerKind::In, true));
+        }
+        self.run_triggers(runs);
+
+        self.log.write("Transaction finished");
+        if !self.config_notes.is_empty() {
+            println!("Configuration files:");
+            for note in &self.config_notes {
+                println!("  {}", note);
+            }
+        }
+        if !self.errors.is_empty() {
+            anyhow::bail!("Transaction finished with errors:\n  {}", self.errors.join("\n  "));
+        }
+        Ok(())
+    }
+
+    /// Install one package and erase the build it replaces, returning its
+    /// database key
+    fn install(&mut self, install: Install) -> Result<String> {
+        let Install { package, rpm } = install;
+        let key = format!("{}.{}", package.name.name, package.name.arch);
+        let count = self.instances_after_install(&package);
+        let mut runs = trigger::set_off(self.pkg_db, &package, TriggerKind::PreIn, count);
+        runs.extend(trigger::immediate(self.pkg_db, &package, TriggerKind::PreIn, count));
+        self.run_triggers_checked(runs)?;
+        run_scriptlet(&self.root, &mut self.log, &package, ScriptletKind::Pre, count)?;
+
+        let files = match &rpm {
+            Some(path) => {
+                let old = self.pkg_db.get_installed(&key).map(|installed| &installed.package);
+                let config = config_files::plan(&self.root, &package, old);
+                let files = payload::extract(path, &package, &self.root, &config)?;
+                let mut notes: Vec<String> = config.iter().filter_map(|(path, action)| action.describe(path)).collect();
+                notes.sort();
+                for note in notes {
+                    self.note_config(note);
+                }
+                files
+            }
+            None => Vec::new(),
+        };
+        let old = self.pkg_db.installed_packages.remove(&key);
+        match &old {
+            Some(old) => self.log.write(&format!("Upgrade: {} -> {}", old.package.nevra(), package.nevra())),
+            None => self.log.write(&format!("Install: {}", package.nevra())),
+        }
+        self.pkg_db.install_package(package, files)?;
+
+        let installed = &self.pkg_db.installed_packages[&key];
+        let name = installed.package.name.name.as_str();
+        let mut runs = trigger::file_set_off(self.pkg_db, TriggerKind::In, false, &installed.installed_files, &[name]);
+        runs.extend(trigger::file_immediate(self.pkg_db, &installed.package, TriggerKind::In, false));
+        self.run_triggers(runs);
+
+        let package = &self.pkg_db.installed_packages[&key].package;
+        if let Err(e) = run_scriptlet(&self.root, &mut self.log, package, ScriptletKind::Post, count) {
+            self.warn(e);
+        }
+        let package = &self.pkg_db.installed_packages[&key].package;
+        let mut runs = trigger::set_off(self.pkg_db, package, TriggerKind::In, count);
+        runs.extend(trigger::immediate(self.pkg_db, package, TriggerKind::In, count));
+        self.run_triggers(runs);
+
+        if let Some(old) = old {
+            // The new build counts among the instances left
+            let count = self.pkg_db.instances(&old.package.name.name);
+            if let Err(e) = self.before_erase(&old.package, &old.installed_files, count) {
+                self.fail(format!("Files of {} are left behind: {}", old.package.nevra(), e));
+                return Ok(key);
+            }
+            self.after_erase(&old.package, &old.installed_files, count);
+        }
+        Ok(key)
+    }
+
+    fn erase(&mut self, key: &str) -> Result<()> {
+        let installed = self
+            .pkg_db
+            .get_installed(key)
+            .ok_or_else(|| anyhow::anyhow!("Package {} is not installed", key))?;
+        let package = installed.package.clone();
+        let files = installed.installed_files.clone();
+        let count = self.pkg_db.instances(&package.name.name) - 1;
+        self.before_erase(&package, &files, count)?;
+
+        self.pkg_db.remove_package(key)?;
+        self.log.write(&format!("Erase: {}", package.nevra()));
+        self.after_erase(&package, &files, count);
+        Ok(())
+    }
+
+    /// What runs before the files of `package` are removed: the file
+    /// triggers and triggers it sets off and %preun. `count` is the number
+    /// of its instances left afterwards.
+    fn before_erase(&mut self, package: &Package, files: &[String], count: u32) -> Result<()> {
+        let name = package.name.name.as_str();
+        let mut runs = trigger::file_immediate(self.pkg_db, package, TriggerKind::Un, false);
+        runs.extend(trigger::file_set_off(self.pkg_db, TriggerKind::Un, false, files, &[name]));
+        runs.extend(trigger::immediate(self.pkg_db, package, TriggerKind::Un, count));
+        runs.extend(trigger::set_off(self.pkg_db, package, TriggerKind::Un, count));
+        self.run_triggers_checked(runs)?;
+        run_scriptlet(&self.root, &mut self.log, package, ScriptletKind::PreUn, count)
+    }
+
+    /// Remove the files of `package`, which has left the database, and run
+    /// what comes after: %postun and the triggers it sets off
+    fn after_erase(&mut self, package: &Package, files: &[String], count: u32) {
+        self.remove_files(package, files);
+        self.removed_files.extend(files.iter().cloned());
+        if let Err(e) = run_scriptlet(&self.root, &mut self.log, package, ScriptletKind::PostUn, count) {
+            self.warn(e);
+        }
+        let name = package.name.name.as_str();
+        let mut runs = trigger::set_off(self.pkg_db, package, TriggerKind::PostUn, count);
+        runs.extend(trigger::file_set_off(self.pkg_db, TriggerKind::PostUn, false, files, &[name]));
+        self.run_triggers(runs);
+    }
+
+    /// Run triggers whose failure stops the operation that set them off
+    fn run_triggers_checked(&mut self, runs: Vec<TriggerRun>) -> Result<()> {
+        for run in runs {
+            scriptlet::run(&self.root, &run.script, &run.label, &run.args, run.input.as_deref(), &mut self.log)?;
+        }
+        Ok(())
+    }
+
+    /// Run triggers whose failure is only a warning
+    fn run_triggers(&mut self, runs: Vec<TriggerRun>) {
+        for run in runs {
+            if let Err(e) = scriptlet::run(&self.root, &run.script, &run.label, &run.args, run.input.as_deref(), &mut self.log) {
+                self.warn(e);
+            }
+        }
+    }
+
+    /// Delete the files of a package that has left the database, except
+    /// those another installed package still owns. Directories only go
+    /// once they are empty, and edited %config files are kept as
+    /// `.rpmsave`.
+    fn remove_files(&mut self, package: &Package, files: &[String]) {
+        let owned: HashSet<&str> = self
+            .pkg_db
+            .installed_packages
+            .values()
+            .flat_map(|installed| installed.installed_files.iter().map(String::as_str))
+            .collect();
+        let mut files: Vec<&String> = files.iter().filter(|file| !owned.contains(file.as_str())).collect();
+        // Reversed, entries in a directory come before the directory
+        files.sort_by(|a, b| b.cmp(a));
+
+        let mut notes = Vec::new();
+        for file in files {
+            let path = match payload::root_path(&self.root, file) {
+                Ok(path) => path,
+                Err(e) => {
+                    log::warn!("Not removing {}: {}", file, e);
+                    continue;
+                }
+            };
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    let _ = fs::remove_dir(&path);
+                }
+                Ok(metadata) if metadata.is_file() && config_files::is_modified(&self.root, package, file) => {
+                    let mut saved = path.clone().into_os_string();
+                    saved.push(".rpmsave");
+                    match fs::rename(&path, saved) {
+                        Ok(()) => notes.push(format!("{} saved as {}.rpmsave", file, file)),
+                        Err(e) => log::warn!("Cannot save {:?}: {}", path, e),
+                    }
+                }
+                Ok(_) => {
+                    if let Err(e) = fs::remove_file(&path) {
+                        log::warn!("Cannot remove {:?}: {}", path, e);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+        for note in notes {
+            self.note_config(note);
+        }
+    }
+
+    /// $1 of the scriptlets of a package being installed, which counts the
+    /// build it replaces as well, so that an upgrade sees 2
+    fn instances_after_install(&self, package: &Package) -> u32 {
+        self.pkg_db.instances(&package.name.name) + 1
+    }
+
+    fn note_config(&mut self, note: String) {
+        log::debug!("{}", note);
+        self.log.write(&note);
+        self.config_notes.push(note);
+    }
+
+    fn warn(&mut self, e: anyhow::Error) {
+        log::warn!("{}", e);
+        self.log.write(&format!("Warning: {}", e));
+    }
+
+    fn fail(&mut self, message: String) {
+        log::error!("{}", message);
+        self.log.write(&format!("Error: {}", message));
+        self.errors.push(message);
+    }
+}
+
+/// Run the scriptlet of `kind` that `package` has, if any
+fn run_scriptlet(root: &Path, log: &mut TransactionLog, package: &Package, kind: ScriptletKind, count: u32) -> Result<()> {
+    match package.scriptlet(kind) {
+        Some(script) => {
+            let label = format!("{}({})", kind.name(), package.nevra());
+            scriptlet::run(root, script, &label, &[count], None, log)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::{Dependency, FileTrigger, PackageName, Scriptlet, Trigger, Version};
+    use crate::scriptlet::tests::{log_lines, shell, shell_root};
+
+    /// A transaction root with a shell, and a package database and log
+    /// beside it
+    struct Fixture {
+        root: tempfile::TempDir,
+        dir: tempfile::TempDir,
+        pkg_db: PackageDatabase,
+    }
+
+    impl Fixture {
+        fn new() -> Option<Self> {
+            let root = shell_root()?;
+            let dir = tempfile::tempdir().unwrap();
+            let pkg_db = PackageDatabase::new(dir.path().join("packages.json"));
+            Some(Self { root, dir, pkg_db })
+        }
+
+        fn run(&mut self, installs: Vec<Package>, erases: &[&str]) -> Result<()> {
+            let config = Config {
+                install_root: self.root.path().to_path_buf(),
+                database_dir: self.dir.path().to_path_buf(),
+                ..Config::default()
+            };
+            let installs = installs.into_iter().map(|package| Install { package, rpm: None }).collect();
+            let erases: Vec<String> = erases.iter().map(|key| key.to_string()).collect();
+            Transaction::new(&config, &mut self.pkg_db).run(installs, &erases)
+        }
+
+        /// What the scripts appended to /calls since the last call
+        fn calls(&self) -> Vec<String> {
+            let path = self.root.path().join("calls");
+            let calls = fs::read_to_string(&path).unwrap_or_default();
+            let _ = fs::remove_file(&path);
+            calls.lines().map(str::to_string).collect()
+        }
+
+        fn log(&self) -> Vec<String> {
+            log_lines(&self.dir.path().join("transaction.log"))
+        }
+    }
+
+    fn package(name: &str, version: &str) -> Package {
+        Package::new(
+            PackageName::new(name, "noarch").unwrap(),
+            Version::new(0, version, "1").unwrap(),
+            String::new(),
+        )
+    }
+
+    /// A script that records its name and arguments, and exits with `status`
+    fn recorder(name: &str, status: u32) -> crate::package::Script {
+        shell(&format!("echo \"{} $1 $2\" >>/calls; exit {}", name, status))
+    }
+
+    fn with_scriptlet(mut package: Package, kind: ScriptletKind, status: u32) -> Package {
+        let script = recorder(&format!("{} {}", package.name.name, kind.name()), status);
+        package.scriptlets.push(Scriptlet { kind, script });
+        package
+    }
+
+    fn with_trigger(mut package: Package, kind: TriggerKind, on: &str) -> Package {
+        let script = recorder(&format!("{} %trigger{}", package.name.name, kind.suffix()), 0);
+        let conditions = vec![Dependency {
+            name: on.into(),
+            version: None,
+            comparator: None,
+        }];
+        package.triggers.push(Trigger { kind, conditions, script });
+        package
+    }
+
+    /// A file trigger that records its name and argument, then the paths
+    /// it was given
+    fn with_file_trigger(
+        mut package: Package,
+        name: &str,
+        kind: TriggerKind,
+        transaction: bool,
+        prefix: &str,
+        priority: u32,
+    ) -> Package {
+        let script = shell(&format!(
+            "echo \"{} $1\" >>/calls; while read path; do echo \"  $path\" >>/calls; done",
+            name
+        ));
+        package.file_triggers.push(FileTrigger {
+            kind,
+            transaction,
+            prefixes: vec![prefix.to_string()],
+            priority,
+            script,
+        });
+        package
+    }
+
+    #[test]
+    fn failing_scripts_before_a_change_stop_it() {
+        let Some(mut fixture) = Fixture::new() else { return };
+
+        let blocked = with_scriptlet(package("blocked", "1"), ScriptletKind::Pre, 1);
+        let blocked = with_scriptlet(blocked, ScriptletKind::Post, 0);
+        let early = with_scriptlet(package("early", "1"), ScriptletKind::PreTrans, 1);
+        let early = with_scriptlet(early, ScriptletKind::Pre, 0);
+        let warned = with_scriptlet(package("warned", "1"), ScriptletKind::Post, 1);
+        let error = fixture.run(vec![blocked, early, warned], &[]).unwrap_err().to_string();
+
+        assert!(error.contains("Not installing early-0:1-1.noarch: %pretrans(early-0:1-1.noarch) failed"), "{}", error);
+        assert!(error.contains("Installing blocked-0:1-1.noarch failed: %pre(blocked-0:1-1.noarch) failed"), "{}", error);
+        assert!(!error.contains("warned"), "{}", error);
+        let keys: HashSet<&str> = fixture.pkg_db.installed_packages.keys().map(String::as_str).collect();
+        assert_eq!(keys, HashSet::from(["warned.noarch"]));
+        assert_eq!(fixture.calls(), ["early %pretrans 1 ", "blocked %pre 1 ", "warned %post 1 "]);
+        assert!(fixture.log().contains(&"Warning: %post(warned-0:1-1.noarch) failed (exit status: 1)".to_string()));
+
+        let kept = with_scriptlet(package("kept", "1"), ScriptletKind::PreUn, 1);
+        let erased = with_scriptlet(package("erased", "1"), ScriptletKind::PostUn, 1);
+        fixture.pkg_db.install_package(kept, Vec::new()).unwrap();
+        fixture.pkg_db.install_package(erased, Vec::new()).unwrap();
+        let error = fixture.run(Vec::new(), &["kept.noarch", "erased.noarch"]).unwrap_err().to_string();
+
+        assert!(error.contains("Removing kept.noarch failed: %preun(kept-0:1-1.noarch) failed"), "{}", error);
+        assert!(!error.contains("erased"), "{}", error);
+        assert!(fixture.pkg_db.get_installed("kept.noarch").is_some());
+        assert!(fixture.pkg_db.get_installed("erased.noarch").is_none());
+        assert_eq!(fixture.calls(), ["kept %preun 0 ", "erased %postun 0 "]);
+        assert!(fixture.log().contains(&"Warning: %postun(erased-0:1-1.noarch) failed (exit status: 1)".to_string()));
+    }
+
+    #[test]
+    fn triggers_run_around_installs_and_erases_with_instance_counts() {
+        let Some(mut fixture) = Fixture::new() else { return };
+        let mut watcher = package("watcher", "1");
+        for kind in [TriggerKind::PreIn, TriggerKind::In, TriggerKind::Un, TriggerKind::PostUn] {
+            watcher = with_trigger(watcher, kind, "lib");
+        }
+        fixture.pkg_db.install_package(watcher, Vec::new()).unwrap();
+        let lib = |version| {
+            let kinds = [ScriptletKind::Pre, ScriptletKind::Post, ScriptletKind::PreUn, ScriptletKind::PostUn];
+            kinds.into_iter().fold(package("lib", version), |lib, kind| with_scriptlet(lib, kind, 0))
+        };
+
+        fixture.run(vec![lib("1")], &[]).unwrap();
+        assert_eq!(
+            fixture.calls(),
+            ["watcher %triggerprein 1 1", "lib %pre 1 ", "lib %post 1 ", "watcher %triggerin 1 1"]
+        );
+
+        // The old build goes once the new one is in, with one instance left
+        fixture.run(vec![lib("2")], &[]).unwrap();
+        assert_eq!(
+            fixture.calls(),
+            [
+                "watcher %triggerprein 1 2",
+                "lib %pre 2 ",
+                "lib %post 2 ",
+                "watcher %triggerin 1 2",
+                "watcher %triggerun 1 1",
+                "lib %preun 1 ",
+                "lib %postun 1 ",
+                "watcher %triggerpostun 1 1",
+            ]
+        );
+
+        // A trigger of the package being installed gets its own count first
+        let plugin = with_trigger(package("plugin", "1"), TriggerKind::In, "lib");
+        fixture.run(vec![plugin], &[]).unwrap();
+        assert_eq!(fixture.calls(), ["plugin %triggerin 1 1"]);
+
+        fixture.run(Vec::new(), &["lib.noarch"]).unwrap();
+        assert_eq!(
+            fixture.calls(),
+            ["watcher %triggerun 1 0", "lib %preun 0 ", "lib %postun 0 ", "watcher %triggerpostun 1 0"]
+        );
+    }
+
+    #[test]
+    fn file_triggers_run_by_priority_with_the_matching_paths() {
+        let Some(mut fixture) = Fixture::new() else { return };
+        let files = ["/usr/lib/data/a", "/usr/lib/data/b", "/usr/share/doc/data"].map(String::from);
+        fixture.pkg_db.install_package(package("data", "1"), files.to_vec()).unwrap();
+
+        // A package's own file triggers see the files already installed
+        let indexer = with_file_trigger(package("indexer", "1"), "in-low", TriggerKind::In, false, "/usr/lib/", 10);
+        let indexer = with_file_trigger(indexer, "in-high", TriggerKind::In, false, "/usr/lib/data/b", 20);
+        fixture.run(vec![indexer], &[]).unwrap();
+        assert_eq!(
+            fixture.calls(),
+            ["in-high 0", "  /usr/lib/data/b", "in-low 0", "  /usr/lib/data/a", "  /usr/lib/data/b"]
+        );
+
+        let watcher = with_file_trigger(package("watcher", "1"), "un-low", TriggerKind::Un, false, "/usr/lib/", 10);
+        let watcher = with_file_trigger(watcher, "un-high", TriggerKind::Un, false, "/usr/lib/data/a", 100);
+        let watcher = with_file_trigger(watcher, "postun", TriggerKind::PostUn, false, "/usr/", 50);
+        let watcher = with_file_trigger(watcher, "trans-un", TriggerKind::Un, true, "/usr/share/", 50);
+        let watcher = with_file_trigger(watcher, "trans-postun", TriggerKind::PostUn, true, "/usr/lib/", 50);
+        let data = with_scriptlet(package("data", "1"), ScriptletKind::PreUn, 0);
+        fixture.pkg_db.install_package(watcher, Vec::new()).unwrap();
+        fixture.pkg_db.install_package(data, files.to_vec()).unwrap();
+
+        fixture.run(Vec::new(), &["data.noarch"]).unwrap();
+        assert_eq!(
+            fixture.calls(),
+            [
+                "trans-un 0",
+                "  /usr/share/doc/data",
+                "un-high 0",
+                "  /usr/lib/data/a",
+                "un-low 0",
+                "  /usr/lib/data/a",
+                "  /usr/lib/data/b",
+                "data %preun 0 ",
+                "postun 0",
+                "  /usr/lib/data/a",
+                "  /usr/lib/data/b",
+                "  /usr/share/doc/data",
+                "trans-postun 0",
+                "  /usr/lib/data/a",
+                "  /usr/lib/data/b",
+            ]
+        );
+    }
+}
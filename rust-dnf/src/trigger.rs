@@ -0,0 +1,139 @@
+use crate::db::PackageDatabase;
+use crate::package::{FileTrigger, Package, Script, TriggerKind};
+use std::cmp::Reverse;
+
+/// A %trigger or file trigger script that is due, with what it runs with
+pub struct TriggerRun {
+    pub label: String,
+    pub script: Script,
+    pub args: Vec<u32>,
+    pub input: Option<Vec<u8>>, // Matching paths, one per line, for file triggers
+}
+
+/// Installed packages in a stable order, so triggers run the same way on
+/// every run
+fn owners(db: &PackageDatabase) -> Vec<&Package> {
+    let mut keys: Vec<&String> = db.installed_packages.keys().collect();
+    keys.sort();
+    keys.into_iter().map(|key| &db.installed_packages[key].package).collect()
+}
+
+/// %trigger scripts of `kind` in other installed packages that `package`
+/// sets off. They get the instances of their own package as $1 and
+/// `count`, the instances of `package` once its operation is done, as $2.
+pub fn set_off(db: &PackageDatabase, package: &Package, kind: TriggerKind, count: u32) -> Vec<TriggerRun> {
+    let mut runs = Vec::new();
+    for owner in owners(db) {
+        if owner.name.name == package.name.name {
+            continue;
+        }
+        for trigger in owner.triggers.iter().filter(|trigger| trigger.kind == kind) {
+            if trigger.conditions.iter().any(|c| c.matches_package(&package.name.name, &package.version)) {
+                runs.push(TriggerRun {
+                    label: format!("%trigger{}({}) for {}", kind.suffix(), owner.nevra(), package.nevra()),
+                    script: trigger.script.clone(),
+                    args: vec![db.instances(&owner.name.name), count],
+                    input: None,
+                });
+            }
+        }
+    }
+    runs
+}
+
+/// %trigger scripts of `kind` in `package` itself that installed packages
+/// set off, each run once however many packages match. They get `count`,
+/// the instances of `package` once its operation is done, as $1 and the
+/// instances of the first matching package as $2.
+pub fn immediate(db: &PackageDatabase, package: &Package, kind: TriggerKind, count: u32) -> Vec<TriggerRun> {
+    let owners = owners(db);
+    let mut runs = Vec::new();
+    for trigger in package.triggers.iter().filter(|trigger| trigger.kind == kind) {
+        let target = owners.iter().find(|target| {
+            target.name.name != package.name.name
+                && trigger.conditions.iter().any(|c| c.matches_package(&target.name.name, &target.version))
+        });
+        if let Some(target) = target {
+            runs.push(TriggerRun {
+                label: format!("%trigger{}({}) for {}", kind.suffix(), package.nevra(), target.nevra()),
+                script: trigger.script.clone(),
+                args: vec![count, db.instances(&target.name.name)],
+                input: None,
+            });
+        }
+    }
+    runs
+}
+
+/// File triggers of `kind` in installed packages not named in `except`
+/// that any of `files` set off, with the matching files as input
+pub fn file_set_off(
+    db: &PackageDatabase,
+    kind: TriggerKind,
+    transaction: bool,
+    files: &[String],
+    except: &[&str],
+) -> Vec<TriggerRun> {
+    let mut runs = Vec::new();
+    for owner in owners(db) {
+        if except.contains(&owner.name.name.as_str()) {
+            continue;
+        }
+        runs.extend(file_triggers(owner, kind, transaction, files));
+    }
+    by_priority(runs)
+}
+
+/// File triggers of `kind` in `package` itself, set off by the files of
+/// all installed packages
+pub fn file_immediate(db: &PackageDatabase, package: &Package, kind: TriggerKind, transaction: bool) -> Vec<TriggerRun> {
+    let mut files: Vec<String> = db
+        .installed_packages
+        .values()
+        .flat_map(|installed| installed.installed_files.iter().cloned())
+        .collect();
+    files.sort();
+    files.dedup();
+    by_priority(file_triggers(package, kind, transaction, &files))
+}
+
+fn file_triggers(owner: &Package, kind: TriggerKind, transaction: bool, files: &[String]) -> Vec<(u32, TriggerRun)> {
+    owner
+        .file_triggers
+        .iter()
+        .filter(|trigger| trigger.kind == kind && trigger.transaction == transaction)
+        .filter_map(|trigger| {
+            let input = matching_files(trigger, files)?;
+            let prefix = if transaction { "%transfiletrigger" } else { "%filetrigger" };
+            Some((
+                trigger.priority,
+                TriggerRun {
+                    label: format!("{}{}({})", prefix, kind.suffix(), owner.nevra()),
+                    script: trigger.script.clone(),
+                    // rpm passes 0 to file triggers; what matters is their input
+                    args: vec![0],
+                    input: Some(input),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// The paths among `files` under one of the trigger's prefixes, one per
+/// line, or None if there are none
+fn matching_files(trigger: &FileTrigger, files: &[String]) -> Option<Vec<u8>> {
+    let mut input = Vec::new();
+    for file in files {
+        if trigger.prefixes.iter().any(|prefix| file.starts_with(prefix.as_str())) {
+            input.extend_from_slice(file.as_bytes());
+            input.push(b'\n');
+        }
+    }
+    (!input.is_empty()).then_some(input)
+}
+
+/// File triggers with a higher priority run first
+fn by_priority(mut runs: Vec<(u32, TriggerRun)>) -> Vec<TriggerRun> {
+    runs.sort_by_key(|(priority, _)| Reverse(*priority));
+    runs.into_iter().map(|(_, run)| run).collect()
+}
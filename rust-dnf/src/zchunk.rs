@@ -0,0 +1,546 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: &[u8; 5] = b"\0ZCK1";
+
+/// Most byte ranges an update is fetched in. Missing chunks separated by
+/// the smallest gaps are fetched together, gaps included, to stay below.
+const MAX_RANGES: usize = 16;
+
+/// Largest chunk accepted, compressed or not, so that a corrupt index
+/// cannot ask for gigabytes
+const MAX_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+
+const FLAG_STREAMS: u64 = 1;
+const FLAG_OPTIONAL_ELEMENTS: u64 = 2;
+const FLAG_UNCOMPRESSED_CHECKSUMS: u64 = 4;
+
+const COMPRESSION_NONE: u64 = 0;
+const COMPRESSION_ZSTD: u64 = 2;
+
+/// Checksum types zchunk files use for their header and chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashType {
+    Sha256,
+    Sha512,
+    Sha512_128, // The first 16 bytes of SHA-512, the usual chunk checksum
+}
+
+impl HashType {
+    fn from_id(id: u64) -> Result<Self> {
+        match id {
+            1 => Ok(Self::Sha256),
+            2 => Ok(Self::Sha512),
+            3 => Ok(Self::Sha512_128),
+            0 => anyhow::bail!("SHA-1 zchunk checksums are not supported"),
+            _ => anyhow::bail!("Unknown zchunk checksum type {}", id),
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+            Self::Sha512_128 => 16,
+        }
+    }
+
+    pub fn digest(self, parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => {
+                let mut hasher = Sha256::new();
+                parts.iter().for_each(|part| hasher.update(part));
+                hasher.finalize().to_vec()
+            }
+            Self::Sha512 | Self::Sha512_128 => {
+                let mut hasher = Sha512::new();
+                parts.iter().for_each(|part| hasher.update(part));
+                let mut digest = hasher.finalize().to_vec();
+                digest.truncate(self.len());
+                digest
+            }
+        }
+    }
+}
+
+/// One chunk of a zchunk file as listed in its index. The first chunk is
+/// the compression dictionary, which may be empty.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub digest: Vec<u8>, // Of the compressed data
+    pub offset: u64,     // From the start of the file
+    pub length: u64,
+    pub uncompressed_length: u64,
+}
+
+/// The lead, preface and index of a zchunk file
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub digest: Vec<u8>, // Of the header itself, as listed in repomd.xml
+    pub length: u64,     // Chunk data starts here
+    pub compression: u64,
+    pub chunk_hash: HashType,
+    pub chunks: Vec<Chunk>,
+}
+
+impl Header {
+    /// Read and validate the header at the start of a zchunk file
+    pub fn read<R: Read>(reader: &mut R) -> Result<Header> {
+        let mut lead = vec![0; MAGIC.len()];
+        reader.read_exact(&mut lead)?;
+        if lead != MAGIC {
+            anyhow::bail!("Not a zchunk file");
+        }
+        let hash = HashType::from_id(read_compint(reader, &mut lead)?)?;
+        let header_size = read_compint(reader, &mut lead)?;
+        let digest_offset = lead.len();
+        let mut digest = vec![0; hash.len()];
+        reader.read_exact(&mut digest)?;
+
+        // Read rather than allocated up front, the size is not checked yet
+        let mut header = Vec::new();
+        (&mut *reader).take(header_size).read_to_end(&mut header)?;
+        if header.len() as u64 != header_size {
+            anyhow::bail!("zchunk header truncated");
+        }
+        // The header checksum covers everything up to the end of the
+        // signatures, except for the checksum itself
+        if hash.digest(&[&lead, &header]) != digest {
+            anyhow::bail!("zchunk header checksum mismatch");
+        }
+
+        let length = (digest_offset + digest.len()) as u64 + header_size;
+        let mut cursor = &header[..];
+        let cursor = &mut cursor;
+        let mut skip = Vec::new();
+
+        // Preface: checksum of all chunk data, flags, compression type and
+        // optional elements, which are of no interest here
+        read_bytes(cursor, hash.len())?;
+        let flags = read_compint(cursor, &mut skip)?;
+        let compression = read_compint(cursor, &mut skip)?;
+        if flags & FLAG_OPTIONAL_ELEMENTS != 0 {
+            for _ in 0..read_compint(cursor, &mut skip)? {
+                read_compint(cursor, &mut skip)?;
+                let size = read_compint(cursor, &mut skip)?;
+                read_bytes(cursor, usize::try_from(size)?)?;
+            }
+        }
+
+        // Index
+        read_compint(cursor, &mut skip)?;
+        let chunk_hash = HashType::from_id(read_compint(cursor, &mut skip)?)?;
+        let count = read_compint(cursor, &mut skip)?;
+        let mut chunks = Vec::new();
+        let mut offset = length;
+        for _ in 0..count {
+            if flags & FLAG_STREAMS != 0 {
+                read_compint(cursor, &mut skip)?;
+            }
+            let digest = read_bytes(cursor, chunk_hash.len())?.to_vec();
+            if flags & FLAG_UNCOMPRESSED_CHECKSUMS != 0 {
+                read_bytes(cursor, chunk_hash.len())?;
+            }
+            let chunk_length = read_compint(cursor, &mut skip)?;
+            let uncompressed_length = read_compint(cursor, &mut skip)?;
+            if chunk_length > MAX_CHUNK_SIZE || uncompressed_length > MAX_CHUNK_SIZE {
+                anyhow::bail!("zchunk chunk of {} bytes is too large", chunk_length.max(uncompressed_length));
+            }
+            chunks.push(Chunk {
+                digest,
+                offset,
+                length: chunk_length,
+                uncompressed_length,
+            });
+            offset += chunk_length;
+        }
+        if chunks.is_empty() {
+            anyhow::bail!("zchunk index has no dictionary entry");
+        }
+
+        Ok(Header {
+            digest,
+            length,
+            compression,
+            chunk_hash,
+            chunks,
+        })
+    }
+}
+
+/// Read a zchunk compressed integer: little endian groups of seven bits,
+/// with the high bit set on the last byte. The raw bytes are appended to
+/// `raw`.
+fn read_compint<R: Read>(reader: &mut R, raw: &mut Vec<u8>) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        raw.push(byte[0]);
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 != 0 {
+            return Ok(value);
+        }
+    }
+    anyhow::bail!("zchunk integer too large")
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if cursor.len() < len {
+        anyhow::bail!("zchunk header truncated");
+    }
+    let (bytes, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Ok(bytes)
+}
+
+/// Decompress one chunk with the file's dictionary
+fn decompress(compression: u64, decompressor: &mut zstd::bulk::Decompressor<'static>, chunk: &Chunk, data: &[u8]) -> Result<Vec<u8>> {
+    let length = usize::try_from(chunk.uncompressed_length)?;
+    match compression {
+        COMPRESSION_NONE => Ok(data.to_vec()),
+        COMPRESSION_ZSTD => Ok(decompressor.decompress(data, length)?),
+        other => anyhow::bail!("Unsupported zchunk compression type {}", other),
+    }
+}
+
+/// Streams the content of a zchunk file, one chunk at a time
+pub struct Decoder<R: Read> {
+    reader: R,
+    header: Header,
+    decompressor: zstd::bulk::Decompressor<'static>,
+    next_chunk: usize,
+    buffer: VecDeque<u8>,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let header = Header::read(&mut reader)?;
+        let dict_chunk = &header.chunks[0];
+        let mut data = vec![0; usize::try_from(dict_chunk.length)?];
+        reader.read_exact(&mut data)?;
+        let dict = match data.is_empty() {
+            true => Vec::new(),
+            false => decompress(header.compression, &mut zstd::bulk::Decompressor::new()?, dict_chunk, &data)?,
+        };
+
+        Ok(Self {
+            reader,
+            decompressor: zstd::bulk::Decompressor::with_dictionary(&dict)?,
+            header,
+            next_chunk: 1,
+            buffer: VecDeque::new(),
+        })
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        while self.buffer.is_empty() && self.next_chunk < self.header.chunks.len() {
+            let chunk = &self.header.chunks[self.next_chunk];
+            let mut data = vec![0; usize::try_from(chunk.length)?];
+            self.reader.read_exact(&mut data)?;
+            self.buffer = decompress(self.header.compression, &mut self.decompressor, chunk, &data)?.into();
+            self.next_chunk += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.buffer.read(buf)
+    }
+}
+
+/// How to build a new zchunk file from an older copy: where each chunk
+/// can be copied from in the old file, and the byte ranges of the new file
+/// that have to be fetched for the rest
+#[derive(Debug)]
+pub struct Plan {
+    pub reuse: Vec<Option<u64>>, // Offset in the old file, by chunk
+    pub ranges: Vec<(u64, u64)>, // First and last byte, inclusive
+}
+
+impl Plan {
+    pub fn new(header: &Header, old: Option<&Header>) -> Plan {
+        let old_chunks: HashMap<(&[u8], u64), u64> = old
+            .map(|old| {
+                old.chunks
+                    .iter()
+                    .map(|chunk| ((chunk.digest.as_slice(), chunk.length), chunk.offset))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut reuse = Vec::with_capacity(header.chunks.len());
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for chunk in &header.chunks {
+            let offset = old_chunks.get(&(chunk.digest.as_slice(), chunk.length)).copied();
+            reuse.push(offset);
+            if offset.is_some() || chunk.length == 0 {
+                continue;
+            }
+            let last = chunk.offset + chunk.length - 1;
+            match ranges.last_mut() {
+                Some(range) if range.1 + 1 == chunk.offset => range.1 = last,
+                _ => ranges.push((chunk.offset, last)),
+            }
+        }
+
+        if ranges.len() > MAX_RANGES {
+            let mut gaps: Vec<u64> = ranges.windows(2).map(|pair| pair[1].0 - pair[0].1).collect();
+            gaps.sort_unstable();
+            let max_gap = gaps[gaps.len() - MAX_RANGES];
+            let mut merged: Vec<(u64, u64)> = Vec::new();
+            for range in ranges {
+                match merged.last_mut() {
+                    Some(last) if range.0 - last.1 <= max_gap => last.1 = range.1,
+                    _ => merged.push(range),
+                }
+            }
+            ranges = merged;
+        }
+
+        Plan { reuse, ranges }
+    }
+
+    /// Chunks that can be copied from the old file
+    pub fn reused(&self) -> usize {
+        self.reuse.iter().filter(|offset| offset.is_some()).count()
+    }
+
+    pub fn fetch_size(&self) -> u64 {
+        self.ranges.iter().map(|(first, last)| last - first + 1).sum()
+    }
+}
+
+/// Write the file `header` describes to `dest`: the header from the start
+/// of `header_path`, then each chunk from the old file or from the fetched
+/// range files, whose order follows `plan.ranges`. Every chunk is checked
+/// against its checksum in the index.
+pub fn assemble(
+    header: &Header,
+    header_path: &Path,
+    old_path: &Path,
+    plan: &Plan,
+    range_paths: &[PathBuf],
+    dest: &Path,
+) -> Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(dest)?);
+    io::copy(&mut fs::File::open(header_path)?.take(header.length), &mut out)?;
+
+    let mut old = None;
+    let mut range_files = Vec::with_capacity(range_paths.len());
+    for path in range_paths {
+        range_files.push(fs::File::open(path)?);
+    }
+
+    for (chunk, reuse) in header.chunks.iter().zip(&plan.reuse) {
+        // An empty dictionary has no data and so no fetched range either
+        if chunk.length == 0 {
+            continue;
+        }
+        let (file, offset) = match reuse {
+            Some(offset) => {
+                if old.is_none() {
+                    old = Some(fs::File::open(old_path)?);
+                }
+                (old.as_mut().expect("opened above"), *offset)
+            }
+            None => {
+                let index = plan
+                    .ranges
+                    .iter()
+                    .position(|(first, last)| *first <= chunk.offset && chunk.offset <= *last)
+                    .ok_or_else(|| anyhow::anyhow!("No fetched range holds chunk at {}", chunk.offset))?;
+                (&mut range_files[index], chunk.offset - plan.ranges[index].0)
+            }
+        };
+
+        let mut data = vec![0; usize::try_from(chunk.length)?];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        if header.chunk_hash.digest(&[&data]) != chunk.digest {
+            anyhow::bail!("zchunk chunk at {} has a bad checksum", chunk.offset);
+        }
+        out.write_all(&data)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn compint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            if value < 0x80 {
+                bytes.push(value as u8 | 0x80);
+                return bytes;
+            }
+            bytes.push((value & 0x7f) as u8);
+            value >>= 7;
+        }
+    }
+
+    /// An uncompressed zchunk file with an empty dictionary and `chunks`
+    pub(crate) fn zchunk_file(chunks: &[&[u8]]) -> Vec<u8> {
+        let chunk_hash = HashType::Sha512_128;
+        let mut index = compint(3);
+        index.extend(compint(chunks.len() as u64 + 1));
+        index.extend(chunk_hash.digest(&[]));
+        index.extend(compint(0));
+        index.extend(compint(0));
+        for chunk in chunks {
+            index.extend(chunk_hash.digest(&[chunk]));
+            index.extend(compint(chunk.len() as u64));
+            index.extend(compint(chunk.len() as u64));
+        }
+
+        let mut header = HashType::Sha256.digest(&[&chunks.concat()]);
+        header.extend(compint(0));
+        header.extend(compint(COMPRESSION_NONE));
+        header.extend(compint(index.len() as u64));
+        header.extend(index);
+        header.extend(compint(0)); // No signatures
+
+        let mut lead = MAGIC.to_vec();
+        lead.extend(compint(1));
+        lead.extend(compint(header.len() as u64));
+        let mut file = lead.clone();
+        file.extend(HashType::Sha256.digest(&[&lead, &header]));
+        file.extend(header);
+        chunks.iter().for_each(|chunk| file.extend(*chunk));
+        file
+    }
+
+    #[test]
+    fn compints_are_read() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64] {
+            let mut raw = Vec::new();
+            assert_eq!(read_compint(&mut &compint(value)[..], &mut raw).unwrap(), value);
+            assert_eq!(raw, compint(value));
+        }
+        assert!(read_compint(&mut &[0u8; 10][..], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn header_lists_chunks() {
+        let file = zchunk_file(&[b"alpha", b"beta", b"gamma"]);
+        let header = Header::read(&mut &file[..]).unwrap();
+
+        assert_eq!(header.compression, COMPRESSION_NONE);
+        assert_eq!(header.chunk_hash, HashType::Sha512_128);
+        let lengths: Vec<u64> = header.chunks.iter().map(|chunk| chunk.length).collect();
+        assert_eq!(lengths, [0, 5, 4, 5]);
+        assert_eq!(header.length + 14, file.len() as u64);
+        assert_eq!(header.chunks[1].offset, header.length);
+        assert_eq!(header.chunks[3].offset, header.length + 9);
+
+        let mut content = String::new();
+        Decoder::new(&file[..]).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "alphabetagamma");
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let file = zchunk_file(&[b"alpha"]);
+        let header_length = Header::read(&mut &file[..]).unwrap().length as usize;
+
+        let mut not_zchunk = file.clone();
+        not_zchunk[1] = b'X';
+        assert!(Header::read(&mut &not_zchunk[..]).is_err());
+
+        let mut tampered = file.clone();
+        tampered[header_length - 2] ^= 1;
+        let error = Header::read(&mut &tampered[..]).unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch"));
+
+        assert!(Header::read(&mut &file[..header_length - 1]).is_err());
+    }
+
+    #[test]
+    fn plan_fetches_only_missing_chunks() {
+        let old_file = zchunk_file(&[b"alpha", b"beta", b"gamma"]);
+        let new_file = zchunk_file(&[b"alpha", b"delta", b"epsilon", b"gamma"]);
+        let old = Header::read(&mut &old_file[..]).unwrap();
+        let new = Header::read(&mut &new_file[..]).unwrap();
+
+        let plan = Plan::new(&new, Some(&old));
+        // The empty dictionary is in both, and needs no range anyway
+        assert_eq!(plan.reuse, [Some(old.chunks[0].offset), Some(old.chunks[1].offset), None, None, Some(old.chunks[3].offset)]);
+        assert_eq!(plan.reused(), 3);
+        // Adjacent missing chunks are fetched as one range
+        assert_eq!(plan.ranges, [(new.chunks[2].offset, new.chunks[3].offset + 6)]);
+        assert_eq!(plan.fetch_size(), 12);
+
+        let fresh = Plan::new(&new, None);
+        assert_eq!(fresh.reused(), 0);
+        assert_eq!(fresh.ranges, [(new.length, new_file.len() as u64 - 1)]);
+    }
+
+    #[test]
+    fn plan_merges_ranges_across_smallest_gaps() {
+        let chunks: Vec<Vec<u8>> = (0..40).map(|i| format!("chunk {:02}", i).into_bytes()).collect();
+        let kept: Vec<&[u8]> = chunks.iter().step_by(2).map(|chunk| chunk.as_slice()).collect();
+        let all: Vec<&[u8]> = chunks.iter().map(|chunk| chunk.as_slice()).collect();
+        let old_file = zchunk_file(&kept);
+        let new_file = zchunk_file(&all);
+        let old = Header::read(&mut &old_file[..]).unwrap();
+        let new = Header::read(&mut &new_file[..]).unwrap();
+
+        let plan = Plan::new(&new, Some(&old));
+        assert!(plan.ranges.len() <= MAX_RANGES);
+        for (chunk, reuse) in new.chunks.iter().zip(&plan.reuse) {
+            if reuse.is_none() && chunk.length > 0 {
+                let last = chunk.offset + chunk.length - 1;
+                assert!(plan.ranges.iter().any(|&(first, end)| first <= chunk.offset && last <= end));
+            }
+        }
+    }
+
+    #[test]
+    fn assemble_rebuilds_the_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_file = zchunk_file(&[b"alpha", b"beta", b"gamma"]);
+        let new_file = zchunk_file(&[b"alpha", b"delta", b"gamma", b"epsilon"]);
+        let old = Header::read(&mut &old_file[..]).unwrap();
+        let new = Header::read(&mut &new_file[..]).unwrap();
+        let mut plan = Plan::new(&new, Some(&old));
+        assert_eq!(plan.ranges.len(), 2);
+        // As if the old file had another dictionary: the empty one is not
+        // in any fetched range but needs nothing fetched
+        plan.reuse[0] = None;
+
+        let old_path = dir.path().join("old.zck");
+        let header_path = dir.path().join("new.header");
+        fs::write(&old_path, &old_file).unwrap();
+        fs::write(&header_path, &new_file[..new.length as usize]).unwrap();
+        let range_paths: Vec<PathBuf> = plan
+            .ranges
+            .iter()
+            .enumerate()
+            .map(|(i, &(first, last))| {
+                let path = dir.path().join(format!("range{}", i));
+                fs::write(&path, &new_file[first as usize..=last as usize]).unwrap();
+                path
+            })
+            .collect();
+
+        let dest = dir.path().join("new.zck");
+        assemble(&new, &header_path, &old_path, &plan, &range_paths, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), new_file);
+
+        // A fetched range with other content fails the chunk checksums
+        fs::write(&range_paths[0], b"DELTA").unwrap();
+        let error = assemble(&new, &header_path, &old_path, &plan, &range_paths, &dest).unwrap_err();
+        assert!(error.to_string().contains("bad checksum"));
+    }
+}